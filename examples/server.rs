@@ -24,7 +24,7 @@ async fn main() -> Result<()> {
                 AsyncProstStream::<_, CommandRequest, CommandResponse, _>::from(stream).for_async();
             while let Some(Ok(cmd)) = stream.next().await {
                 // 返回一个 404 response
-                let resp = svc.execute(cmd);
+                let resp = svc.execute(cmd).await;
                 stream.send(resp).await.unwrap();
             }
             info!("Client {:?} disconnected", addr);
@@ -26,7 +26,7 @@ async fn main() -> Result<()> {
                 let cmd = CommandRequest::decode(&mut buf).unwrap();
                 info!("Got a new command: {:?}", cmd);
 
-                let resp = svc.execute(cmd);
+                let resp = svc.execute(cmd).await;
                 buf.clear();
                 resp.encode(&mut buf).unwrap();
 
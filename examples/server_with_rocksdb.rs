@@ -10,7 +10,7 @@ async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
     let service: Service<RocksDb> = ServiceInner::new(RocksDb::new("/tmp/hikv"))
-        .fn_before_reply(|ret| match ret.message.as_ref() {
+        .fn_before_reply(|ret, _| match ret.message.as_ref() {
             "" => ret.message = "altered. Original message is empty.".into(),
             s => ret.message = format!("altered: {}", s),
         })
@@ -29,7 +29,7 @@ async fn main() -> Result<()> {
                 AsyncProstStream::<_, CommandRequest, CommandResponse, _>::from(stream).for_async();
             while let Some(Ok(cmd)) = stream.next().await {
                 // 返回一个 404 response
-                let resp = svc.execute(cmd);
+                let resp = svc.execute(cmd).await;
                 stream.send(resp).await.unwrap();
             }
             info!("Client {:?} disconnected", addr);
@@ -0,0 +1,201 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::{AdmissionConfig, Kvpair};
+
+/// 连接数、请求队列和存储并发的准入控制，同时统计各个队列的深度
+pub struct Admission {
+    config: AdmissionConfig,
+    storage: Option<Semaphore>,
+    connections: AtomicUsize,
+    rejected: AtomicU64,
+    queued: AtomicUsize,
+    storage_waiting: AtomicUsize,
+    storage_running: AtomicUsize,
+}
+
+/// 某一时刻的队列深度
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AdmissionMetrics {
+    /// 当前连接数
+    pub connections: usize,
+    /// 因超过最大连接数被拒绝的连接总数
+    pub rejected_connections: u64,
+    /// 所有连接中已读取、等待执行的请求数
+    pub queued_requests: usize,
+    /// 等待存储并发许可的请求数
+    pub storage_waiting: usize,
+    /// 正在执行的存储操作数
+    pub storage_running: usize,
+}
+
+/// 计数器 +1，drop 时 -1
+pub struct Gauge<'a>(&'a AtomicUsize);
+
+/// 执行存储操作的许可
+pub struct StoragePermit<'a> {
+    _permit: Option<SemaphorePermit<'a>>,
+    _running: Gauge<'a>,
+}
+
+impl Admission {
+    pub fn new(config: AdmissionConfig) -> Self {
+        Self {
+            storage: config.max_storage_ops.map(Semaphore::new),
+            config,
+            connections: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+            queued: AtomicUsize::new(0),
+            storage_waiting: AtomicUsize::new(0),
+            storage_running: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn config(&self) -> &AdmissionConfig {
+        &self.config
+    }
+
+    /// 接受新连接，超过最大连接数时返回 None
+    pub fn try_connect(&self) -> Option<Gauge<'_>> {
+        let max = self.config.max_connections.unwrap_or(usize::MAX);
+        let ret = self
+            .connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            });
+        match ret {
+            Ok(_) => Some(Gauge(&self.connections)),
+            Err(_) => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// 每个连接的在途请求上限，至少为 1
+    pub fn max_in_flight(&self) -> usize {
+        self.config.max_in_flight.max(1)
+    }
+
+    /// 请求已读取，等待执行
+    pub fn enqueue(&self) -> Gauge<'_> {
+        Gauge::new(&self.queued)
+    }
+
+    /// 等待存储并发许可
+    pub async fn acquire_storage(&self) -> StoragePermit<'_> {
+        let permit = match &self.storage {
+            Some(semaphore) => {
+                let _waiting = Gauge::new(&self.storage_waiting);
+                // semaphore 不会被 close
+                semaphore.acquire().await.ok()
+            }
+            None => None,
+        };
+        StoragePermit {
+            _permit: permit,
+            _running: Gauge::new(&self.storage_running),
+        }
+    }
+
+    pub fn metrics(&self) -> AdmissionMetrics {
+        AdmissionMetrics {
+            connections: self.connections.load(Ordering::Relaxed),
+            rejected_connections: self.rejected.load(Ordering::Relaxed),
+            queued_requests: self.queued.load(Ordering::Relaxed),
+            storage_waiting: self.storage_waiting.load(Ordering::Relaxed),
+            storage_running: self.storage_running.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for Admission {
+    fn default() -> Self {
+        Self::new(AdmissionConfig::default())
+    }
+}
+
+impl AdmissionMetrics {
+    /// 以 server. 前缀展开，附加在 Info 的结果后面
+    pub fn to_pairs(&self) -> Vec<Kvpair> {
+        vec![
+            Kvpair::new("server.connections", (self.connections as i64).into()),
+            Kvpair::new(
+                "server.rejected_connections",
+                (self.rejected_connections as i64).into(),
+            ),
+            Kvpair::new(
+                "server.queued_requests",
+                (self.queued_requests as i64).into(),
+            ),
+            Kvpair::new(
+                "server.storage_waiting",
+                (self.storage_waiting as i64).into(),
+            ),
+            Kvpair::new(
+                "server.storage_running",
+                (self.storage_running as i64).into(),
+            ),
+        ]
+    }
+}
+
+impl<'a> Gauge<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::AcqRel);
+        Self(counter)
+    }
+}
+
+impl Drop for Gauge<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn connections_over_limit_should_be_rejected() {
+        let admission = Admission::new(AdmissionConfig {
+            max_connections: Some(2),
+            ..Default::default()
+        });
+        let c1 = admission.try_connect().unwrap();
+        let _c2 = admission.try_connect().unwrap();
+        assert!(admission.try_connect().is_none());
+        assert_eq!(admission.metrics().connections, 2);
+        assert_eq!(admission.metrics().rejected_connections, 1);
+
+        drop(c1);
+        assert!(admission.try_connect().is_some());
+    }
+
+    #[tokio::test]
+    async fn storage_ops_should_be_limited() {
+        let admission = Arc::new(Admission::new(AdmissionConfig {
+            max_storage_ops: Some(1),
+            ..Default::default()
+        }));
+        let permit = admission.acquire_storage().await;
+
+        let waiter = admission.clone();
+        let handle = tokio::spawn(async move {
+            let _permit = waiter.acquire_storage().await;
+        });
+        while admission.metrics().storage_waiting == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(admission.metrics().storage_running, 1);
+
+        drop(permit);
+        handle.await.unwrap();
+        let metrics = admission.metrics();
+        assert_eq!((metrics.storage_waiting, metrics.storage_running), (0, 0));
+    }
+}
@@ -0,0 +1,539 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::command_request::Data;
+use crate::{
+    value, AuditConfig, AuditOverflow, BoxFuture, CommandRequest, CommandResponse, Context,
+    HikvError, ImportedKeys, Kvpair, Middleware, Next, Value,
+};
+
+const CURRENT_FILE: &str = "audit.jsonl";
+
+/// 写命令的审计日志
+///
+/// 作为中间件记录每个写命令，Import 按写入的 key 逐条记录。记录交给后台线程批量写盘，
+/// 每批写完后 fsync；队列满时按配置丢弃并计数或等待，丢弃数通过 Info 的 audit.dropped 查看
+#[derive(Clone)]
+pub struct AuditLog {
+    inner: Arc<AuditLogInner>,
+}
+
+struct AuditLogInner {
+    config: AuditConfig,
+    sender: SyncSender<Message>,
+    dropped: AtomicU64,
+    writer: Mutex<Option<JoinHandle<()>>>,
+}
+
+enum Message {
+    Record(Box<AuditRecord>),
+    Sync(SyncSender<()>),
+    Shutdown,
+}
+
+/// 一条审计记录
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// 毫秒时间戳
+    pub timestamp: u64,
+    pub command: String,
+    /// 认证通过的用户
+    pub identity: Option<String>,
+    /// 客户端地址，不经过网络执行的命令为 None
+    pub peer: Option<String>,
+    pub conn_id: u64,
+    pub key: Option<String>,
+    pub status: u32,
+    /// 修改前的 value，key 不存在或命令失败时为 None
+    pub old: Option<ValueInfo>,
+    /// 写入的 value
+    pub new: Option<ValueInfo>,
+}
+
+/// value 的类型和大小，按配置附带内容
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ValueInfo {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// 字节数
+    pub size: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+}
+
+/// 后台线程持有的当前文件
+struct Writer {
+    dir: PathBuf,
+    max_file_bytes: u64,
+    rotate_after: Option<Duration>,
+    file: BufWriter<File>,
+    written: u64,
+    opened: Instant,
+}
+
+impl AuditLog {
+    /// 创建日志目录并启动写盘线程
+    pub fn new(config: AuditConfig) -> Result<Self, HikvError> {
+        let writer = Writer::open(&config)?;
+        let (sender, receiver) = mpsc::sync_channel(config.queue_size.max(1));
+        let handle = thread::Builder::new()
+            .name("hikv-audit".into())
+            .spawn(move || writer.run(receiver))?;
+        info!("Audit log enabled in {:?}", config.dir);
+        Ok(Self {
+            inner: Arc::new(AuditLogInner {
+                config,
+                sender,
+                dropped: AtomicU64::new(0),
+                writer: Mutex::new(Some(handle)),
+            }),
+        })
+    }
+
+    /// 放入写盘队列，队列满时按配置丢弃或阻塞当前线程
+    pub fn record(&self, record: AuditRecord) {
+        let msg = Message::Record(Box::new(record));
+        let ret = match self.inner.config.overflow {
+            AuditOverflow::Drop => self.inner.sender.try_send(msg),
+            AuditOverflow::Block => self
+                .inner
+                .sender
+                .send(msg)
+                .map_err(|e| TrySendError::Disconnected(e.0)),
+        };
+        if let Err(e) = ret {
+            self.count_dropped(&e);
+        }
+    }
+
+    /// 和 record 相同，队列满且配置为阻塞时在 blocking 线程上等待，不占用 runtime 的 worker
+    async fn enqueue(&self, record: AuditRecord) {
+        let msg = Message::Record(Box::new(record));
+        let msg = match self.inner.sender.try_send(msg) {
+            Ok(()) => return,
+            Err(TrySendError::Full(msg)) if self.inner.config.overflow == AuditOverflow::Block => {
+                msg
+            }
+            Err(e) => return self.count_dropped(&e),
+        };
+        let sender = self.inner.sender.clone();
+        match tokio::task::spawn_blocking(move || sender.send(msg)).await {
+            Ok(Ok(())) => {}
+            _ => {
+                self.inner.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn count_dropped(&self, err: &TrySendError<Message>) {
+        let n = self.inner.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        if matches!(err, TrySendError::Full(_)) && n % 1000 == 1 {
+            warn!("Audit log queue is full, {} records dropped so far", n);
+        }
+    }
+
+    /// 因队列满或写盘线程退出丢弃的记录数
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+
+    /// 等待已入队的记录写盘，会阻塞，不要在请求路径上调用
+    pub fn sync(&self) {
+        let (tx, rx) = mpsc::sync_channel(1);
+        if self.inner.sender.send(Message::Sync(tx)).is_ok() {
+            let _ = rx.recv();
+        }
+    }
+
+    /// 根据请求和执行结果生成记录，非写命令返回 None
+    fn build(
+        &self,
+        cmd: &CommandRequest,
+        ret: &CommandResponse,
+        ctx: &Context,
+    ) -> Option<AuditRecord> {
+        if !cmd.is_write() {
+            return None;
+        }
+        let key = cmd.key();
+        let with_value = self.inner.config.include_values
+            && !key.is_some_and(|k| {
+                self.inner
+                    .config
+                    .redact_prefixes
+                    .iter()
+                    .any(|p| k.starts_with(p.as_str()))
+            });
+
+        // Set 和 Del 成功时返回修改前的 value
+        let old = match &cmd.data {
+            Some(Data::Set(_) | Data::Del(_)) if ret.status == 200 => ret
+                .values
+                .first()
+                .and_then(|v| ValueInfo::new(v, with_value)),
+            _ => None,
+        };
+        let new = match &cmd.data {
+            Some(Data::Set(param)) => param
+                .value
+                .as_ref()
+                .and_then(|v| ValueInfo::new(v, with_value)),
+            _ => None,
+        };
+        let conn = ctx.conn();
+        Some(AuditRecord {
+            timestamp: crate::now_millis(),
+            command: cmd.name().into(),
+            identity: conn.identity.clone(),
+            peer: conn.peer.map(|addr| addr.to_string()),
+            conn_id: conn.id,
+            key: key.map(Into::into),
+            status: ret.status,
+            old,
+            new,
+        })
+    }
+}
+
+impl Middleware for AuditLog {
+    fn call<'a>(
+        &'a self,
+        cmd: CommandRequest,
+        ctx: &'a mut Context,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, CommandResponse> {
+        Box::pin(async move {
+            if matches!(cmd.data, Some(Data::Info(_))) {
+                let mut ret = next.run(cmd, ctx).await;
+                if ret.status == 200 {
+                    ret.pairs
+                        .push(Kvpair::new("audit.dropped", (self.dropped() as i64).into()));
+                }
+                return ret;
+            }
+            if !cmd.is_write() {
+                return next.run(cmd, ctx).await;
+            }
+            // 请求会被 move 进后面的处理，只有写命令才需要保留一份
+            let req = cmd.clone();
+            let ret = next.run(cmd, ctx).await;
+            let record = match self.build(&req, &ret, ctx) {
+                Some(record) => record,
+                None => return ret,
+            };
+            // Import 每个写入的 key 一条记录，没有写入任何 key 时记录一条不带 key 的
+            let imported = match (&req.data, ctx.get::<ImportedKeys>()) {
+                (Some(Data::Import(_)), Some(ImportedKeys(keys))) => keys.clone(),
+                _ => Vec::new(),
+            };
+            if imported.is_empty() {
+                self.enqueue(record).await;
+                return ret;
+            }
+            for key in imported {
+                let record = AuditRecord {
+                    key: Some(key),
+                    ..record.clone()
+                };
+                self.enqueue(record).await;
+            }
+            ret
+        })
+    }
+}
+
+impl Drop for AuditLogInner {
+    fn drop(&mut self) {
+        // 退出前把队列中的记录写完
+        let _ = self.sender.send(Message::Shutdown);
+        if let Some(handle) = self.writer.lock().unwrap().take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl ValueInfo {
+    /// 空 value 表示 key 不存在，返回 None
+    fn new(v: &Value, with_value: bool) -> Option<Self> {
+        let (kind, size) = match v.value.as_ref()? {
+            value::Value::String(s) => ("string", s.len()),
+            value::Value::Binary(b) => ("binary", b.len()),
+            value::Value::Integer(_) => ("integer", 8),
+            value::Value::Float(_) => ("float", 8),
+            value::Value::Bool(_) => ("bool", 1),
+        };
+        Some(Self {
+            kind: kind.into(),
+            size,
+            value: with_value.then(|| v.clone()),
+        })
+    }
+}
+
+impl Writer {
+    fn open(config: &AuditConfig) -> Result<Self, HikvError> {
+        fs::create_dir_all(&config.dir)?;
+        let file = open_append(&config.dir.join(CURRENT_FILE))?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            dir: config.dir.clone(),
+            max_file_bytes: config.max_file_bytes,
+            rotate_after: config.rotate_secs.map(Duration::from_secs),
+            file: BufWriter::new(file),
+            written,
+            opened: Instant::now(),
+        })
+    }
+
+    fn run(mut self, receiver: Receiver<Message>) {
+        while let Ok(msg) = receiver.recv() {
+            // 一次取出所有排队的记录，写完再 flush
+            let mut shutdown = false;
+            let mut acks = Vec::new();
+            for msg in std::iter::once(msg).chain(receiver.try_iter()) {
+                match msg {
+                    Message::Record(record) => {
+                        if let Err(e) = self.write(&record) {
+                            error!("Failed to write audit log: {}", e);
+                        }
+                    }
+                    Message::Sync(ack) => acks.push(ack),
+                    Message::Shutdown => shutdown = true,
+                }
+            }
+            // 每批写完后落盘，之后才回复 Sync
+            if let Err(e) = self
+                .file
+                .flush()
+                .and_then(|_| self.file.get_ref().sync_data())
+            {
+                error!("Failed to sync audit log: {}", e);
+            }
+            for ack in acks {
+                let _ = ack.send(());
+            }
+            if shutdown {
+                break;
+            }
+        }
+    }
+
+    fn write(&mut self, record: &AuditRecord) -> Result<(), HikvError> {
+        if self.should_rotate() {
+            self.rotate()?;
+        }
+        let mut line =
+            serde_json::to_vec(record).map_err(|e| HikvError::Internal(e.to_string()))?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    fn should_rotate(&self) -> bool {
+        self.written > 0
+            && (self.written >= self.max_file_bytes
+                || self
+                    .rotate_after
+                    .is_some_and(|d| self.opened.elapsed() >= d))
+    }
+
+    /// 当前文件重命名为 audit-<毫秒时间戳>.jsonl，再打开新的当前文件
+    fn rotate(&mut self) -> Result<(), HikvError> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+        let current = self.dir.join(CURRENT_FILE);
+        let ts = crate::now_millis();
+        let mut target = self.dir.join(format!("audit-{}.jsonl", ts));
+        let mut n = 1;
+        while target.exists() {
+            target = self.dir.join(format!("audit-{}-{}.jsonl", ts, n));
+            n += 1;
+        }
+        fs::rename(&current, &target)?;
+        self.file = BufWriter::new(open_append(&current)?);
+        self.written = 0;
+        self.opened = Instant::now();
+        info!("Audit log rotated to {:?}", target);
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> Result<File, HikvError> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::{ConnInfo, DumpFormat, FilesConfig, MemTable, Service, ServiceInner};
+
+    fn read_records(dir: &Path) -> Vec<AuditRecord> {
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        // 已切换的文件在前，当前文件最后
+        paths.sort_by_key(|p| p.ends_with(CURRENT_FILE));
+        paths
+            .iter()
+            .flat_map(|p| {
+                fs::read_to_string(p)
+                    .unwrap()
+                    .lines()
+                    .map(|l| serde_json::from_str(l).unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn write_commands_should_be_audited() {
+        let dir = tempfile::tempdir().unwrap();
+        let audit = AuditLog::new(AuditConfig {
+            dir: dir.path().into(),
+            include_values: true,
+            redact_prefixes: vec!["secret:".into()],
+            ..Default::default()
+        })
+        .unwrap();
+        let service: Service = ServiceInner::new(MemTable::new())
+            .layer(audit.clone())
+            .into();
+
+        let peer: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let mut conn = ConnInfo::new(Some(peer));
+        conn.identity = Some("alice".into());
+        let mut ctx = Context::with_conn(conn);
+        for cmd in [
+            CommandRequest::new_set("k1", "hello".into()),
+            CommandRequest::new_get("k1"),
+            CommandRequest::new_set("k1", 42.into()),
+            CommandRequest::new_set("secret:k2", "p@ss".into()),
+            CommandRequest::new_del("k3"),
+        ] {
+            service.call(cmd, &mut ctx).await;
+        }
+        audit.sync();
+
+        let records = read_records(dir.path());
+        assert_eq!(records.len(), 4);
+        assert!(records
+            .iter()
+            .all(|r| r.identity.as_deref() == Some("alice")));
+        assert_eq!(records[0].peer.as_deref(), Some("10.0.0.1:4000"));
+        assert_eq!(records[0].old, None);
+
+        let r = &records[1];
+        assert_eq!((r.command.as_str(), r.key.as_deref()), ("set", Some("k1")));
+        let old = r.old.as_ref().unwrap();
+        assert_eq!((old.kind.as_str(), old.size), ("string", 5));
+        assert_eq!(old.value, Some("hello".into()));
+        assert_eq!(r.new.as_ref().unwrap().kind, "integer");
+
+        // 脱敏的 key 只记录类型和大小
+        let new = records[2].new.as_ref().unwrap();
+        assert_eq!(
+            (new.kind.as_str(), new.size, &new.value),
+            ("string", 4, &None)
+        );
+
+        assert_eq!(
+            (records[3].command.as_str(), records[3].status),
+            ("del", 404)
+        );
+        assert_eq!(audit.dropped(), 0);
+    }
+
+    #[test]
+    fn audit_log_should_rotate_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let audit = AuditLog::new(AuditConfig {
+            dir: dir.path().into(),
+            max_file_bytes: 1,
+            ..Default::default()
+        })
+        .unwrap();
+        let ctx = Context::new();
+        for i in 0..3 {
+            let cmd = CommandRequest::new_set(format!("k{}", i), "v".into());
+            let record = audit.build(&cmd, &Value::default().into(), &ctx).unwrap();
+            audit.record(record);
+        }
+        drop(audit);
+
+        // 每个文件只能放一条记录
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+        let keys: Vec<_> = read_records(dir.path())
+            .into_iter()
+            .filter_map(|r| r.key)
+            .collect();
+        assert_eq!(keys.len(), 3);
+        assert!(keys.contains(&"k2".to_string()));
+    }
+
+    #[tokio::test]
+    async fn import_should_be_audited_per_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let dump_dir = dir.path().join("dump");
+        fs::create_dir_all(&dump_dir).unwrap();
+        fs::write(
+            dump_dir.join("a.jsonl"),
+            "{\"key\":\"k1\",\"type\":\"string\",\"value\":\"v1\"}\n\
+             {\"key\":\"k2\",\"type\":\"integer\",\"value\":2}\n",
+        )
+        .unwrap();
+        let audit = AuditLog::new(AuditConfig {
+            dir: dir.path().join("audit"),
+            overflow: AuditOverflow::Block,
+            queue_size: 1,
+            ..Default::default()
+        })
+        .unwrap();
+        let service: Service = ServiceInner::new(MemTable::new())
+            .layer(audit.clone())
+            .files(FilesConfig {
+                dump_dir: Some(dump_dir),
+                backup_dir: None,
+            })
+            .into();
+
+        let cmd = CommandRequest::new_import("a.jsonl", DumpFormat::JsonLines, false);
+        assert_eq!(service.execute(cmd).await.status, 200);
+        let cmd = CommandRequest::new_import("missing.jsonl", DumpFormat::JsonLines, false);
+        assert_eq!(service.execute(cmd).await.status, 500);
+        audit.sync();
+
+        let records: Vec<_> = read_records(&dir.path().join("audit"))
+            .into_iter()
+            .map(|r| (r.command, r.key, r.status))
+            .collect();
+        assert_eq!(
+            records,
+            [
+                ("import".into(), Some("k1".into()), 200),
+                ("import".into(), Some("k2".into()), 200),
+                ("import".into(), None, 500),
+            ]
+        );
+
+        let ret = service.execute(CommandRequest::new_info()).await;
+        let dropped = ret.pairs.iter().find(|p| p.key == "audit.dropped").unwrap();
+        assert_eq!(dropped.value, Some(0.into()));
+    }
+}
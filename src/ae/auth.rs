@@ -0,0 +1,515 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use sha2::Sha256;
+use tracing::{debug, warn};
+
+use crate::command_request::Data;
+use crate::{
+    AclAction, AclRule, Auth, AuthConfig, BoxFuture, CommandRequest, CommandResponse, Context,
+    HikvError, Middleware, Next, UserConfig, Value,
+};
+
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+/// PBKDF2 迭代次数，测试中降低以免拖慢用例
+const HASH_ROUNDS: u32 = if cfg!(test) { 1000 } else { 600_000 };
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+/// 连续认证失败这么多次后锁定来源
+const MAX_AUTH_FAILURES: u32 = 5;
+/// 锁定时长，之后每次失败翻倍，最多 AUTH_MAX_LOCKOUT
+const AUTH_LOCKOUT: Duration = Duration::from_secs(1);
+const AUTH_MAX_LOCKOUT: Duration = Duration::from_secs(300);
+/// 失败记录超过这么久没有更新就可以清理
+const AUTH_FAILURE_TTL: Duration = Duration::from_secs(900);
+
+/// 认证和 ACL 检查
+///
+/// 未认证的连接只能执行 Auth 和 Ping，认证后按用户的 ACL 规则检查每个命令。
+/// 同一来源连续认证失败 MAX_AUTH_FAILURES 次后在一段时间内拒绝认证，时长随失败次数翻倍
+pub struct AuthLayer {
+    users: HashMap<String, UserConfig>,
+    failures: Mutex<Failures>,
+}
+
+/// 按来源记录的连续认证失败
+#[derive(Default)]
+struct Failures {
+    sources: HashMap<FailureSource, Failure>,
+    /// 记录数达到该值时清理过期记录，清理后翻倍，使清理的开销均摊到每次失败
+    prune_at: usize,
+}
+
+/// 有对端地址时按 IP 计数，否则按连接计数
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum FailureSource {
+    Ip(IpAddr),
+    Conn(u64),
+}
+
+struct Failure {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl AuthLayer {
+    pub fn new(config: AuthConfig) -> Self {
+        let users = config
+            .users
+            .into_iter()
+            .map(|user| (user.name.clone(), user))
+            .collect();
+        Self {
+            users,
+            failures: Mutex::default(),
+        }
+    }
+
+    /// 校验 Auth 命令，返回认证通过的用户名
+    fn authenticate(&self, auth: &Auth) -> Result<&str, HikvError> {
+        let user = if !auth.token.is_empty() {
+            self.users.values().find(|u| {
+                u.token
+                    .as_ref()
+                    .is_some_and(|t| constant_time_eq(t.as_bytes(), auth.token.as_bytes()))
+            })
+        } else {
+            self.users.get(&auth.username).filter(|u| {
+                u.password
+                    .as_ref()
+                    .is_some_and(|hash| verify_password(hash, &auth.password))
+            })
+        };
+        user.map(|u| u.name.as_str())
+            .ok_or_else(|| HikvError::Unauthenticated("invalid credentials".into()))
+    }
+
+    /// 来源被锁定时返回还需等待的时间
+    fn locked(&self, source: &FailureSource) -> Option<Duration> {
+        let failures = self.failures.lock().unwrap();
+        let until = failures.sources.get(source)?.locked_until?;
+        until.checked_duration_since(Instant::now())
+    }
+
+    fn record_failure(&self, source: FailureSource) {
+        let mut failures = self.failures.lock().unwrap();
+        let now = Instant::now();
+        if failures.sources.len() >= failures.prune_at {
+            failures
+                .sources
+                .retain(|_, f| now.duration_since(f.last) < AUTH_FAILURE_TTL);
+            failures.prune_at = (failures.sources.len() * 2).max(1024);
+        }
+        let failure = failures.sources.entry(source).or_insert(Failure {
+            count: 0,
+            last: now,
+            locked_until: None,
+        });
+        failure.count += 1;
+        failure.last = now;
+        if failure.count >= MAX_AUTH_FAILURES {
+            let lockout = AUTH_LOCKOUT
+                .saturating_mul(1 << (failure.count - MAX_AUTH_FAILURES).min(16))
+                .min(AUTH_MAX_LOCKOUT);
+            failure.locked_until = Some(now + lockout);
+        }
+    }
+
+    fn clear_failures(&self, source: &FailureSource) {
+        self.failures.lock().unwrap().sources.remove(source);
+    }
+
+    /// 检查当前连接的用户是否可以执行命令
+    fn check(&self, cmd: &CommandRequest, ctx: &Context) -> Result<(), HikvError> {
+        let name = ctx
+            .conn()
+            .identity
+            .as_ref()
+            .ok_or_else(|| HikvError::Unauthenticated("Auth required".into()))?;
+        let user = self
+            .users
+            .get(name)
+            .ok_or_else(|| HikvError::Unauthenticated(format!("unknown user {}", name)))?;
+        // 不针对单个 key 的命令(如 export、import)会涉及任意前缀：有按前缀拒绝的规则时一律拒绝，
+        // 写命令还需要明确列出命令名的规则才放行
+        let allowed = match cmd.key() {
+            Some(key) => user.is_allowed(cmd.name(), Some(key)),
+            None if user.has_prefix_deny(cmd.name()) => false,
+            None if cmd.is_write() => user.is_explicitly_allowed(cmd.name()),
+            None => user.is_allowed(cmd.name(), None),
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(HikvError::PermissionDenied(format!(
+                "user {} cannot run {} on {:?}",
+                name,
+                cmd.name(),
+                cmd.key().unwrap_or_default()
+            )))
+        }
+    }
+}
+
+impl Middleware for AuthLayer {
+    fn call<'a>(
+        &'a self,
+        cmd: CommandRequest,
+        ctx: &'a mut Context,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, CommandResponse> {
+        Box::pin(async move {
+            match &cmd.data {
+                Some(Data::Auth(auth)) => {
+                    // 重新认证失败时不能保留之前的身份
+                    ctx.conn_mut().identity = None;
+                    let source = match ctx.conn().peer {
+                        Some(peer) => FailureSource::Ip(peer.ip()),
+                        None => FailureSource::Conn(ctx.conn().id),
+                    };
+                    if let Some(wait) = self.locked(&source) {
+                        warn!("Connection {} is locked out of Auth", ctx.conn().id);
+                        return HikvError::RateLimited(wait.as_millis().max(1) as u64).into();
+                    }
+                    match self.authenticate(auth) {
+                        Ok(name) => {
+                            debug!("Connection {} authenticated as {}", ctx.conn().id, name);
+                            self.clear_failures(&source);
+                            ctx.conn_mut().identity = Some(name.into());
+                            Value::from("OK").into()
+                        }
+                        Err(e) => {
+                            warn!("Connection {} failed to authenticate", ctx.conn().id);
+                            self.record_failure(source);
+                            e.into()
+                        }
+                    }
+                }
+                Some(Data::Ping(_)) => next.run(cmd, ctx).await,
+                _ => match self.check(&cmd, ctx) {
+                    Ok(()) => next.run(cmd, ctx).await,
+                    Err(e) => e.into(),
+                },
+            }
+        })
+    }
+}
+
+impl UserConfig {
+    /// 按顺序匹配 ACL 规则，没有规则匹配时拒绝
+    pub fn is_allowed(&self, command: &str, key: Option<&str>) -> bool {
+        self.acl
+            .iter()
+            .find(|rule| rule.matches(command, key))
+            .is_some_and(|rule| rule.action == AclAction::Allow)
+    }
+
+    /// 是否有适用于该命令且限定了 key 前缀的 Deny 规则
+    pub fn has_prefix_deny(&self, command: &str) -> bool {
+        self.acl.iter().any(|rule| {
+            rule.action == AclAction::Deny
+                && !rule.prefix.is_empty()
+                && rule.matches(command, Some(&rule.prefix))
+        })
+    }
+
+    /// 和 is_allowed 相同，但第一条匹配的 Allow 规则必须在 commands 中列出命令名
+    pub fn is_explicitly_allowed(&self, command: &str) -> bool {
+        self.acl
+            .iter()
+            .find(|rule| rule.matches(command, None))
+            .is_some_and(|rule| {
+                rule.action == AclAction::Allow && rule.commands.iter().any(|c| c == command)
+            })
+    }
+}
+
+impl AclRule {
+    pub fn matches(&self, command: &str, key: Option<&str>) -> bool {
+        let command_matched =
+            self.commands.is_empty() || self.commands.iter().any(|c| c == "*" || c == command);
+        let key_matched = match key {
+            Some(key) => key.starts_with(&self.prefix),
+            None => self.prefix.is_empty(),
+        };
+        command_matched && key_matched
+    }
+}
+
+/// 用 PBKDF2-HMAC-SHA256 和系统随机数生成的盐计算密码哈希 `pbkdf2-sha256:<rounds>:<salt>:<hash>`
+pub fn hash_password(password: &str) -> String {
+    let mut salt = [0; SALT_LEN];
+    getrandom::getrandom(&mut salt).expect("failed to read system random numbers");
+    let hash = pbkdf2(password, &salt, HASH_ROUNDS);
+    format!(
+        "{}:{}:{}:{}",
+        HASH_SCHEME,
+        HASH_ROUNDS,
+        hex(&salt),
+        hex(&hash)
+    )
+}
+
+/// 校验密码是否和 hash_password 生成的哈希匹配
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    let mut parts = hash.splitn(4, ':');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(HASH_SCHEME), Some(rounds), Some(salt), Some(expected)) => {
+            match (rounds.parse(), unhex(salt)) {
+                (Ok(rounds), Some(salt)) if rounds > 0 => {
+                    let actual = hex(&pbkdf2(password, &salt, rounds));
+                    constant_time_eq(actual.as_bytes(), expected.as_bytes())
+                }
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+fn pbkdf2(password: &str, salt: &[u8], rounds: u32) -> [u8; HASH_LEN] {
+    let mut hash = [0; HASH_LEN];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, rounds, &mut hash);
+    hash
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// 比较耗时和内容无关，避免通过时间猜测密码
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assert_err, assert_ok, ConnInfo, MemTable, Service, ServiceInner, Storage};
+
+    fn rule(action: AclAction, commands: &[&str], prefix: &str) -> AclRule {
+        AclRule {
+            action,
+            commands: commands.iter().map(|c| c.to_string()).collect(),
+            prefix: prefix.into(),
+        }
+    }
+
+    fn service() -> Service {
+        let store = MemTable::new();
+        store.set("user:1", "tom").unwrap();
+        store.set("order:1", "book").unwrap();
+        let config = AuthConfig {
+            users: vec![
+                UserConfig {
+                    name: "alice".into(),
+                    password: Some(hash_password("wonderland")),
+                    acl: vec![
+                        rule(AclAction::Deny, &["del"], "user:"),
+                        rule(AclAction::Allow, &["*"], "user:"),
+                    ],
+                    ..Default::default()
+                },
+                UserConfig {
+                    name: "ops".into(),
+                    token: Some("t0ken".into()),
+                    acl: vec![rule(AclAction::Allow, &[], "")],
+                    ..Default::default()
+                },
+                UserConfig {
+                    name: "reader".into(),
+                    token: Some("r3ader".into()),
+                    acl: vec![
+                        rule(AclAction::Deny, &[], "secret/"),
+                        rule(AclAction::Allow, &[], ""),
+                    ],
+                    ..Default::default()
+                },
+                UserConfig {
+                    name: "loader".into(),
+                    token: Some("l0ader".into()),
+                    acl: vec![rule(AclAction::Allow, &["import"], "")],
+                    ..Default::default()
+                },
+            ],
+        };
+        ServiceInner::new(store)
+            .layer(AuthLayer::new(config))
+            .into()
+    }
+
+    #[test]
+    fn password_hash_should_verify() {
+        let hash = hash_password("secret");
+        assert!(hash.starts_with("pbkdf2-sha256:1000:"));
+        assert_ne!(hash, hash_password("secret"));
+        assert!(verify_password(&hash, "secret"));
+        assert!(!verify_password(&hash, "Secret"));
+        assert!(!verify_password("plain", "plain"));
+        assert!(!verify_password("pbkdf2-sha256:0:00:00", "secret"));
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_connection_can_only_auth_and_ping() {
+        let service = service();
+        let mut ctx = Context::new();
+
+        let ret = service.call(CommandRequest::new_ping(), &mut ctx).await;
+        assert_ok(ret, &["PONG".into()]);
+        let ret = service
+            .call(CommandRequest::new_get("user:1"), &mut ctx)
+            .await;
+        assert_err(ret, 401, "Auth required");
+        let ret = service
+            .call(CommandRequest::new_auth("alice", "wrong"), &mut ctx)
+            .await;
+        assert_err(ret, 401, "invalid credentials");
+        assert!(ctx.conn().identity.is_none());
+    }
+
+    #[tokio::test]
+    async fn acl_should_be_checked_per_prefix() {
+        let service = service();
+        let mut ctx = Context::new();
+        let ret = service
+            .call(CommandRequest::new_auth("alice", "wonderland"), &mut ctx)
+            .await;
+        assert_ok(ret, &["OK".into()]);
+
+        let ret = service
+            .call(CommandRequest::new_get("user:1"), &mut ctx)
+            .await;
+        assert_ok(ret, &["tom".into()]);
+        let ret = service
+            .call(CommandRequest::new_del("user:1"), &mut ctx)
+            .await;
+        assert_err(ret, 403, "cannot run del");
+        let ret = service
+            .call(CommandRequest::new_get("order:1"), &mut ctx)
+            .await;
+        assert_err(ret, 403, "cannot run get");
+        let ret = service.call(CommandRequest::new_info(), &mut ctx).await;
+        assert_err(ret, 403, "cannot run info");
+
+        // token 用户的空前缀规则适用于所有命令
+        let mut ctx = Context::new();
+        let ret = service
+            .call(CommandRequest::new_auth_token("t0ken"), &mut ctx)
+            .await;
+        assert_ok(ret, &["OK".into()]);
+        assert_eq!(ctx.conn().identity.as_deref(), Some("ops"));
+        let ret = service
+            .call(CommandRequest::new_del("user:1"), &mut ctx)
+            .await;
+        assert_ok(ret, &["tom".into()]);
+    }
+
+    #[tokio::test]
+    async fn failed_reauth_should_clear_identity() {
+        let service = service();
+        let mut ctx = Context::new();
+        let ret = service
+            .call(CommandRequest::new_auth_token("t0ken"), &mut ctx)
+            .await;
+        assert_ok(ret, &["OK".into()]);
+        let ret = service
+            .call(CommandRequest::new_auth("alice", "wrong"), &mut ctx)
+            .await;
+        assert_err(ret, 401, "invalid credentials");
+        assert!(ctx.conn().identity.is_none());
+        let ret = service
+            .call(CommandRequest::new_get("user:1"), &mut ctx)
+            .await;
+        assert_err(ret, 401, "Auth required");
+    }
+
+    #[tokio::test]
+    async fn repeated_failures_should_lock_out_auth() {
+        let service = service();
+        let peer = |ip: &str| Context::with_conn(ConnInfo::new(Some(ip.parse().unwrap())));
+        let mut ctx = peer("10.0.0.1:5000");
+        for _ in 0..MAX_AUTH_FAILURES {
+            let ret = service
+                .call(CommandRequest::new_auth("alice", "wrong"), &mut ctx)
+                .await;
+            assert_err(ret, 401, "invalid credentials");
+        }
+        // 锁定期间正确的密码也被拒绝
+        let ret = service
+            .call(CommandRequest::new_auth("alice", "wonderland"), &mut ctx)
+            .await;
+        assert_eq!(ret.status, 429);
+        assert!(ctx.conn().identity.is_none());
+
+        // 同一 IP 的新连接也被锁定，其他来源不受影响
+        let mut ctx = peer("10.0.0.1:5001");
+        let ret = service
+            .call(CommandRequest::new_auth("alice", "wonderland"), &mut ctx)
+            .await;
+        assert_eq!(ret.status, 429);
+        let mut other = peer("10.0.0.2:5000");
+        let ret = service
+            .call(CommandRequest::new_auth("alice", "wonderland"), &mut other)
+            .await;
+        assert_ok(ret, &["OK".into()]);
+    }
+
+    #[tokio::test]
+    async fn keyless_writes_should_need_explicit_rule() {
+        let service = service();
+        let import = || CommandRequest::new_import("a.jsonl", crate::DumpFormat::JsonLines, false);
+
+        let mut ctx = Context::new();
+        let ret = service
+            .call(CommandRequest::new_auth_token("t0ken"), &mut ctx)
+            .await;
+        assert_ok(ret, &["OK".into()]);
+        let ret = service.call(import(), &mut ctx).await;
+        assert_err(ret, 403, "cannot run import");
+
+        let mut ctx = Context::new();
+        let ret = service
+            .call(CommandRequest::new_auth_token("l0ader"), &mut ctx)
+            .await;
+        assert_ok(ret, &["OK".into()]);
+        let ret = service.call(import(), &mut ctx).await;
+        assert_ne!(ret.status, 403);
+    }
+
+    #[tokio::test]
+    async fn keyless_commands_should_be_denied_with_prefix_deny() {
+        let service = service();
+        let mut ctx = Context::new();
+        let ret = service
+            .call(CommandRequest::new_auth_token("r3ader"), &mut ctx)
+            .await;
+        assert_ok(ret, &["OK".into()]);
+
+        let ret = service
+            .call(CommandRequest::new_get("order:1"), &mut ctx)
+            .await;
+        assert_ok(ret, &["book".into()]);
+        let ret = service
+            .call(CommandRequest::new_get("secret/token"), &mut ctx)
+            .await;
+        assert_err(ret, 403, "cannot run get");
+        let export = CommandRequest::new_export("a.jsonl", crate::DumpFormat::JsonLines);
+        let ret = service.call(export, &mut ctx).await;
+        assert_err(ret, 403, "cannot run export");
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::command_request::Data;
+use crate::{CommandRequest, FilesConfig, HikvError};
+
+impl FilesConfig {
+    /// 把 Export/Import/Backup 中客户端给出的路径解析到配置的目录下，其他命令不变
+    pub fn resolve(&self, cmd: &mut CommandRequest) -> Result<(), HikvError> {
+        let (path, root, name) = match &mut cmd.data {
+            Some(Data::Export(param)) => (&mut param.path, &self.dump_dir, "dump"),
+            Some(Data::Import(param)) => (&mut param.path, &self.dump_dir, "dump"),
+            Some(Data::Backup(param)) => (&mut param.path, &self.backup_dir, "backup"),
+            _ => return Ok(()),
+        };
+        let root = root.as_deref().ok_or_else(|| {
+            HikvError::InvalidCommand(format!("{} directory is not configured", name))
+        })?;
+        *path = resolve_path(root, path)?.to_string_lossy().into_owned();
+        Ok(())
+    }
+}
+
+/// 把相对路径解析到 root 目录下，拒绝绝对路径、`..` 和指向 root 之外的符号链接
+pub fn resolve_path(root: &Path, path: &str) -> Result<PathBuf, HikvError> {
+    let invalid = || HikvError::InvalidCommand(format!("invalid path: {}", path));
+    let relative = Path::new(path);
+    if path.is_empty()
+        || !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(invalid());
+    }
+
+    fs::create_dir_all(root)?;
+    let root = root.canonicalize()?;
+    let joined = root.join(relative);
+    // 最后一级不能是符号链接，悬空的链接也算已存在
+    if fs::symlink_metadata(&joined).is_ok_and(|m| m.file_type().is_symlink()) {
+        return Err(invalid());
+    }
+    // 已存在的部分不能通过符号链接跳出 root
+    let mut existing = joined.as_path();
+    while fs::symlink_metadata(existing).is_err() {
+        existing = existing.parent().ok_or_else(invalid)?;
+    }
+    if !existing.canonicalize()?.starts_with(&root) {
+        return Err(invalid());
+    }
+    Ok(joined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DumpFormat;
+
+    #[test]
+    fn resolve_path_should_stay_in_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("dump");
+
+        let path = resolve_path(&root, "a/b.jsonl").unwrap();
+        assert_eq!(path, root.canonicalize().unwrap().join("a/b.jsonl"));
+
+        for path in ["", "/etc/passwd", "../x", "a/../../x", "./x"] {
+            assert!(resolve_path(&root, path).is_err(), "{}", path);
+        }
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path(), root.join("out")).unwrap();
+            assert!(resolve_path(&root, "out/x").is_err());
+            // 指向 root 之外不存在文件的悬空链接
+            let target = dir.path().join("escaped.jsonl");
+            std::os::unix::fs::symlink(&target, root.join("dangling.jsonl")).unwrap();
+            assert!(resolve_path(&root, "dangling.jsonl").is_err());
+            assert!(resolve_path(&root, "dangling.jsonl/x").is_err());
+        }
+    }
+
+    #[test]
+    fn resolve_should_require_dump_dir() {
+        let mut cmd = CommandRequest::new_export("a.jsonl", DumpFormat::JsonLines);
+        assert!(FilesConfig::default().resolve(&mut cmd).is_err());
+
+        let mut cmd = CommandRequest::new_backup("b1", false);
+        let err = FilesConfig::default().resolve(&mut cmd).unwrap_err();
+        assert!(err.to_string().contains("backup directory"));
+
+        let mut cmd = CommandRequest::new_get("k");
+        assert!(FilesConfig::default().resolve(&mut cmd).is_ok());
+    }
+}
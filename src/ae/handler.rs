@@ -1,8 +1,19 @@
-use crate::{CommandHandler, CommandResponse, Del, Exist, Get, HikvError, Set, Storage, Value};
+use std::{fs::File, time::Duration};
+
+use crate::{
+    backup, dump, Auth, Backup, BgSave, CommandHandler, CommandResponse, Compact, Del, Exist,
+    Export, Flush, Get, HikvError, Import, Info, KeyMeta, Meta, Ping, ReleaseSnapshot, Save, Set,
+    SetRateLimit, Snapshot, Storage, Value, DEFAULT_SNAPSHOT_TIMEOUT,
+};
 
 impl CommandHandler for Set {
     fn handle(self, store: &impl Storage) -> CommandResponse {
-        match store.set(self.key, self.value.unwrap_or_default()) {
+        let value = self.value.unwrap_or_default();
+        let ret = match self.expected_version {
+            Some(version) => store.set_if_version(self.key, value, version),
+            None => store.set(self.key, value),
+        };
+        match ret {
             Ok(Some(v)) => v.into(),
             Ok(None) => Value::default().into(),
             Err(e) => e.into(),
@@ -12,6 +23,27 @@ impl CommandHandler for Set {
 
 impl CommandHandler for Get {
     fn handle(self, store: &impl Storage) -> CommandResponse {
+        if self.snapshot_id != 0 {
+            if self.with_meta {
+                return HikvError::InvalidCommand("Get with_meta on snapshot".into()).into();
+            }
+            return match store.get_at(self.snapshot_id, &self.key) {
+                Ok(Some(v)) => v.into(),
+                Ok(None) => HikvError::NotFound(self.key).into(),
+                Err(e) => e.into(),
+            };
+        }
+        if self.with_meta {
+            return match store.get_with_meta(&self.key) {
+                Ok(Some((v, meta))) => {
+                    let mut values = vec![v];
+                    values.extend(meta_values(&meta));
+                    values.into()
+                }
+                Ok(None) => HikvError::NotFound(self.key).into(),
+                Err(e) => e.into(),
+            };
+        }
         match store.get(&self.key) {
             Ok(Some(v)) => v.into(),
             Ok(None) => HikvError::NotFound(self.key).into(),
@@ -20,6 +52,53 @@ impl CommandHandler for Get {
     }
 }
 
+impl CommandHandler for Meta {
+    fn handle(self, store: &impl Storage) -> CommandResponse {
+        match store.meta(&self.key) {
+            Ok(Some(meta)) => meta_values(&meta).to_vec().into(),
+            Ok(None) => HikvError::NotFound(self.key).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl Snapshot {
+    /// 快照超时，0 表示使用默认值
+    pub fn timeout(&self) -> Duration {
+        match self.timeout_ms {
+            0 => DEFAULT_SNAPSHOT_TIMEOUT,
+            ms => Duration::from_millis(ms),
+        }
+    }
+}
+
+impl CommandHandler for Snapshot {
+    fn handle(self, store: &impl Storage) -> CommandResponse {
+        match store.create_snapshot(self.timeout()) {
+            Ok(id) => Value::from(id as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandHandler for ReleaseSnapshot {
+    fn handle(self, store: &impl Storage) -> CommandResponse {
+        match store.release_snapshot(self.id) {
+            Ok(released) => Value::from(released).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// 元数据按 version、created_at、updated_at 顺序返回
+fn meta_values(meta: &KeyMeta) -> [Value; 3] {
+    [
+        Value::from(meta.version as i64),
+        Value::from(meta.created_at as i64),
+        Value::from(meta.updated_at as i64),
+    ]
+}
+
 impl CommandHandler for Del {
     fn handle(self, store: &impl Storage) -> CommandResponse {
         match store.del(&self.key) {
@@ -39,12 +118,137 @@ impl CommandHandler for Exist {
     }
 }
 
+impl CommandHandler for Save {
+    fn handle(self, store: &impl Storage) -> CommandResponse {
+        match store.save() {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandHandler for BgSave {
+    fn handle(self, store: &impl Storage) -> CommandResponse {
+        match store.bg_save() {
+            Ok(()) => Value::from("Background saving started").into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandHandler for Info {
+    fn handle(self, store: &impl Storage) -> CommandResponse {
+        match store.stats() {
+            Ok(stats) => stats.to_pairs().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandHandler for Compact {
+    fn handle(self, store: &impl Storage) -> CommandResponse {
+        match store.compact() {
+            Ok(()) => Value::from("OK").into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandHandler for Flush {
+    fn handle(self, store: &impl Storage) -> CommandResponse {
+        match store.flush() {
+            Ok(()) => Value::from("OK").into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandHandler for Backup {
+    fn handle(self, store: &impl Storage) -> CommandResponse {
+        match backup(store, &self.path, self.incremental) {
+            Ok(manifest) => vec![
+                Value::from(manifest.digest.count as i64),
+                Value::from(manifest.digest.checksum as i64),
+            ]
+            .into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandHandler for Ping {
+    fn handle(self, _store: &impl Storage) -> CommandResponse {
+        Value::from("PONG").into()
+    }
+}
+
+/// 启用认证时 Auth 由 AuthLayer 处理，到这里说明服务器没有配置认证
+impl CommandHandler for Auth {
+    fn handle(self, _store: &impl Storage) -> CommandResponse {
+        HikvError::InvalidCommand("authentication is not enabled".into()).into()
+    }
+}
+
+/// 启用限流时 SetRateLimit 由 RateLimiter 处理
+impl CommandHandler for SetRateLimit {
+    fn handle(self, _store: &impl Storage) -> CommandResponse {
+        HikvError::InvalidCommand("rate limiting is not enabled".into()).into()
+    }
+}
+
+impl CommandHandler for Export {
+    fn handle(self, store: &impl Storage) -> CommandResponse {
+        let format = self.format();
+        let ret = File::create(&self.path)
+            .map_err(HikvError::from)
+            .and_then(|file| dump::export(store, file, format));
+        match ret {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// Import 写入的 key，由 Service 放入 Context 供审计日志等中间件使用
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportedKeys(pub Vec<String>);
+
+impl Import {
+    /// 执行导入并返回写入的 key，中途失败时包含失败前已写入的 key
+    pub fn run(self, store: &impl Storage) -> (CommandResponse, ImportedKeys) {
+        let format = self.format();
+        let mut keys = Vec::new();
+        let ret = File::open(&self.path)
+            .map_err(HikvError::from)
+            .and_then(|file| {
+                dump::import_with(store, file, format, self.overwrite, |key| {
+                    keys.push(key.to_owned())
+                })
+            });
+        let ret = match ret {
+            Ok(r) => vec![
+                Value::from(r.imported as i64),
+                Value::from(r.skipped as i64),
+            ]
+            .into(),
+            Err(e) => e.into(),
+        };
+        (ret, ImportedKeys(keys))
+    }
+}
+
+impl CommandHandler for Import {
+    fn handle(self, store: &impl Storage) -> CommandResponse {
+        self.run(store).0
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
         ae::{assert_err, assert_ok},
-        dispatch, CommandRequest, MemTable,
+        dispatch, CommandRequest, DumpFormat, Kvpair, MemTable,
     };
 
     #[test]
@@ -78,6 +282,109 @@ mod tests {
         assert_ok(ret, &["rust".into()])
     }
 
+    #[test]
+    fn should_work_get_with_meta_and_meta_command() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_set("lang", "rust".into()), &store);
+        let meta = store.meta("lang").unwrap().unwrap();
+
+        let ret = dispatch(CommandRequest::new_get_with_meta("lang"), &store);
+        let expected: Vec<Value> = vec![
+            "rust".into(),
+            (meta.version as i64).into(),
+            (meta.created_at as i64).into(),
+            (meta.updated_at as i64).into(),
+        ];
+        assert_ok(ret, &expected);
+
+        let ret = dispatch(CommandRequest::new_meta("lang"), &store);
+        assert_ok(ret, &expected[1..]);
+
+        let ret = dispatch(CommandRequest::new_meta("language"), &store);
+        assert_err(ret, 404, "Not Found");
+    }
+
+    #[test]
+    fn set_with_stale_version_should_fail_412() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_set("lang", "rust".into()), &store);
+
+        let cmd = CommandRequest::new_set_if_version("lang", "go".into(), 0);
+        let ret = dispatch(cmd, &store);
+        assert_err(ret, 412, "Version mismatch");
+
+        let version = store.meta("lang").unwrap().unwrap().version;
+        let cmd = CommandRequest::new_set_if_version("lang", "go".into(), version);
+        let ret = dispatch(cmd, &store);
+        assert_ok(ret, &["rust".into()]);
+    }
+
+    #[test]
+    fn should_work_snapshot_commands() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_set("lang", "rust".into()), &store);
+
+        let ret = dispatch(CommandRequest::new_snapshot(0), &store);
+        assert_eq!(ret.status, 200);
+        let id = match ret.values[0].value {
+            Some(crate::value::Value::Integer(id)) => id as u64,
+            _ => panic!("snapshot id should be integer"),
+        };
+
+        dispatch(CommandRequest::new_set("lang", "go".into()), &store);
+        dispatch(CommandRequest::new_set("os", "linux".into()), &store);
+
+        let ret = dispatch(CommandRequest::new_get_at("lang", id), &store);
+        assert_ok(ret, &["rust".into()]);
+        let ret = dispatch(CommandRequest::new_get_at("os", id), &store);
+        assert_err(ret, 404, "Not Found");
+        let ret = dispatch(CommandRequest::new_get("lang"), &store);
+        assert_ok(ret, &["go".into()]);
+
+        let ret = dispatch(CommandRequest::new_release_snapshot(id), &store);
+        assert_ok(ret, &[true.into()]);
+        let ret = dispatch(CommandRequest::new_get_at("lang", id), &store);
+        assert_err(ret, 404, "not found or expired");
+    }
+
+    #[test]
+    fn should_work_info_compact_and_flush() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_set("lang", "rust".into()), &store);
+
+        let ret = dispatch(CommandRequest::new_info(), &store);
+        assert_eq!(ret.status, 200);
+        assert_eq!(ret.pairs[0], Kvpair::new("keys", 1.into()));
+        assert_eq!(ret.pairs[2], Kvpair::new("disk_bytes", 0.into()));
+        assert!(ret.pairs.iter().any(|p| p.key == "memory.used"));
+
+        let ret = dispatch(CommandRequest::new_compact(), &store);
+        assert_ok(ret, &["OK".into()]);
+        let ret = dispatch(CommandRequest::new_flush(), &store);
+        assert_ok(ret, &["OK".into()]);
+    }
+
+    #[test]
+    fn should_work_backup_command() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_set("lang", "rust".into()), &store);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("backup");
+        let path = path.to_str().unwrap();
+
+        let ret = dispatch(CommandRequest::new_backup(path, false), &store);
+        let digest = crate::Digest::of(&store).unwrap();
+        assert_ok(
+            ret,
+            &[
+                (digest.count as i64).into(),
+                (digest.checksum as i64).into(),
+            ],
+        );
+        let ret = dispatch(CommandRequest::new_backup(path, false), &store);
+        assert_err(ret, 500, "already exists");
+    }
+
     #[test]
     fn should_work_with_non_exist_key_404() {
         let store = MemTable::new();
@@ -118,4 +425,51 @@ mod tests {
         let ret = dispatch(cmd, &store);
         assert_ok(ret, &[true.into()]);
     }
+
+    #[test]
+    fn should_work_save_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MemTable::with_snapshot(dir.path().join("dump.snap")).unwrap();
+
+        let cmd = CommandRequest::new_set("hello", "world".into());
+        dispatch(cmd, &store);
+
+        let cmd = CommandRequest::new_save();
+        let ret = dispatch(cmd, &store);
+        assert_ok(ret, &[1.into()]);
+    }
+
+    #[test]
+    fn save_command_without_snapshot_should_fail() {
+        let store = MemTable::new();
+
+        let cmd = CommandRequest::new_save();
+        let ret = dispatch(cmd, &store);
+        assert_err(ret, 500, "snapshot path is not configured");
+    }
+
+    #[test]
+    fn should_work_export_import_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump.jsonl");
+        let path = path.to_str().unwrap();
+        let store = MemTable::new();
+        store.set("hello", "world").unwrap();
+        store.set("lang", "rust").unwrap();
+
+        let cmd = CommandRequest::new_export(path, DumpFormat::JsonLines);
+        let ret = dispatch(cmd, &store);
+        assert_ok(ret, &[2.into()]);
+
+        store.set("hello", "there").unwrap();
+        let cmd = CommandRequest::new_import(path, DumpFormat::JsonLines, false);
+        let ret = dispatch(cmd, &store);
+        assert_ok(ret, &[0.into(), 2.into()]);
+        assert_eq!(store.get("hello").unwrap(), Some("there".into()));
+
+        let cmd = CommandRequest::new_import(path, DumpFormat::JsonLines, true);
+        let ret = dispatch(cmd, &store);
+        assert_ok(ret, &[2.into(), 0.into()]);
+        assert_eq!(store.get("hello").unwrap(), Some("world".into()));
+    }
 }
@@ -0,0 +1,344 @@
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use tracing::{info, warn};
+
+use crate::command_request::Data;
+use crate::{
+    CommandRequest, CommandResponse, ConnInfo, HikvError, RateLimit, RateLimitConfig, SetRateLimit,
+    Value,
+};
+
+/// 超过这个数量时清理空闲的令牌桶
+const MAX_BUCKETS: usize = 10_000;
+/// 每检查这么多次才尝试清理一次，避免桶都不空闲时每次检查都遍历
+const PRUNE_INTERVAL: u64 = 1024;
+/// 空闲超过这个时间的令牌桶可以清理，重新创建时是满的，不影响限流结果
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 按 IP 和认证用户限制每秒命令数和请求字节数
+///
+/// 连接循环在执行命令前调用 check；SetRateLimit 命令在所有中间件之后处理，会先经过认证
+pub struct RateLimiter {
+    config: RwLock<RateLimitConfig>,
+    buckets: DashMap<String, Buckets>,
+    checks: AtomicU64,
+}
+
+/// 单个 IP 或用户的令牌桶
+struct Buckets {
+    commands: Option<Bucket>,
+    bytes: Option<Bucket>,
+    updated: Instant,
+}
+
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config: RwLock::new(config),
+            buckets: DashMap::new(),
+            checks: AtomicU64::new(0),
+        }
+    }
+
+    /// 检查并消耗令牌，超过限制时返回建议的重试等待时间
+    pub fn check(&self, conn: &ConnInfo, bytes: usize) -> Result<(), Duration> {
+        let n = self.checks.fetch_add(1, Ordering::Relaxed);
+        if n.is_multiple_of(PRUNE_INTERVAL) && self.buckets.len() > MAX_BUCKETS {
+            self.buckets
+                .retain(|_, b| b.updated.elapsed() < IDLE_TIMEOUT);
+        }
+
+        let (ip_limit, user_limit) = {
+            let config = self.config.read().unwrap();
+            let ip = conn.peer.map(|addr| addr.ip());
+            let ip_limit = ip.map(|ip| (ip, config.limit_for_ip(&ip)));
+            let user_limit = conn
+                .identity
+                .as_ref()
+                .map(|name| (name.clone(), config.limit_for_user(name)));
+            (ip_limit, user_limit)
+        };
+
+        let now = Instant::now();
+        let mut targets = Vec::with_capacity(2);
+        if let Some((ip, limit)) = ip_limit {
+            targets.push((format!("ip:{}", ip), limit));
+        }
+        if let Some((name, limit)) = user_limit {
+            targets.push((format!("user:{}", name), limit));
+        }
+
+        // 先检查所有桶，都允许时才消耗，避免被拒绝的请求也消耗令牌
+        // 同时持有多个 entry 可能在同一个分片上死锁，所以逐个加锁
+        let mut wait = Duration::ZERO;
+        for (target, limit) in &targets {
+            let mut entry = self
+                .buckets
+                .entry(target.clone())
+                .or_insert_with(|| Buckets::new(*limit, now));
+            entry.refill(now);
+            wait = wait.max(entry.wait(bytes as f64));
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for (target, _) in &targets {
+            if let Some(mut entry) = self.buckets.get_mut(target) {
+                entry.take(bytes as f64);
+            }
+        }
+        Ok(())
+    }
+
+    /// 调整限制，target 为 user:<name>、ip:<addr>，user:*、ip:* 表示默认限制
+    pub fn set_limit(&self, target: &str, limit: RateLimit) -> Result<(), HikvError> {
+        let mut config = self.config.write().unwrap();
+        // 按新的限制重新创建令牌桶
+        match target.split_once(':') {
+            Some(("ip", "*")) => {
+                config.ip = limit;
+                self.buckets.retain(|k, _| !k.starts_with("ip:"));
+            }
+            Some(("user", "*")) => {
+                config.user = limit;
+                self.buckets.retain(|k, _| !k.starts_with("user:"));
+            }
+            Some(("ip", ip)) => {
+                let ip: IpAddr = ip
+                    .parse()
+                    .map_err(|_| HikvError::InvalidCommand(format!("invalid ip {}", ip)))?;
+                config.ips.insert(ip.to_string(), limit);
+                self.buckets.remove(&format!("ip:{}", ip));
+            }
+            Some(("user", name)) if !name.is_empty() => {
+                config.users.insert(name.into(), limit);
+                self.buckets.remove(target);
+            }
+            _ => {
+                return Err(HikvError::InvalidCommand(format!(
+                    "invalid rate limit target {}",
+                    target
+                )))
+            }
+        }
+        info!("Rate limit of {} set to {:?}", target, limit);
+        Ok(())
+    }
+
+    pub fn config(&self) -> RateLimitConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    /// 处理 SetRateLimit，其他命令返回 None
+    pub(crate) fn handle(&self, cmd: &CommandRequest) -> Option<CommandResponse> {
+        let SetRateLimit {
+            target,
+            commands_per_sec,
+            bytes_per_sec,
+        } = match &cmd.data {
+            Some(Data::SetRateLimit(param)) => param,
+            _ => return None,
+        };
+        let limit = RateLimit {
+            commands_per_sec: *commands_per_sec,
+            bytes_per_sec: *bytes_per_sec,
+        };
+        Some(match self.set_limit(target, limit) {
+            Ok(()) => Value::from("OK").into(),
+            Err(e) => e.into(),
+        })
+    }
+}
+
+impl RateLimitConfig {
+    pub fn limit_for_ip(&self, ip: &IpAddr) -> RateLimit {
+        self.ips.get(&ip.to_string()).copied().unwrap_or(self.ip)
+    }
+
+    pub fn limit_for_user(&self, name: &str) -> RateLimit {
+        self.users.get(name).copied().unwrap_or(self.user)
+    }
+}
+
+impl Buckets {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            commands: limit.commands_per_sec.and_then(Bucket::new),
+            bytes: limit.bytes_per_sec.and_then(Bucket::new),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.updated = now;
+        for bucket in [&mut self.commands, &mut self.bytes].into_iter().flatten() {
+            bucket.tokens = (bucket.tokens + elapsed * bucket.rate).min(bucket.rate);
+        }
+    }
+
+    fn wait(&self, bytes: f64) -> Duration {
+        let commands = self
+            .commands
+            .as_ref()
+            .map_or(Duration::ZERO, |b| b.wait(1.0));
+        let bytes = self
+            .bytes
+            .as_ref()
+            .map_or(Duration::ZERO, |b| b.wait(bytes));
+        commands.max(bytes)
+    }
+
+    fn take(&mut self, bytes: f64) {
+        if let Some(b) = &mut self.commands {
+            b.tokens -= 1.0;
+        }
+        if let Some(b) = &mut self.bytes {
+            b.tokens -= bytes;
+        }
+    }
+}
+
+impl Bucket {
+    fn new(rate: f64) -> Option<Self> {
+        if rate > 0.0 {
+            Some(Self { rate, tokens: rate })
+        } else {
+            warn!("Ignore non-positive rate limit {}", rate);
+            None
+        }
+    }
+
+    /// 令牌足够前需要等待的时间
+    ///
+    /// 超过桶容量的请求在桶满时允许通过，之后的请求要等令牌补回来
+    fn wait(&self, n: f64) -> Duration {
+        let need = n.min(self.rate);
+        if self.tokens >= need {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((need - self.tokens) / self.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::{
+        assert_ok, AclAction, AclRule, AuthConfig, AuthLayer, Context, MemTable, Service,
+        ServiceInner, UserConfig,
+    };
+
+    fn conn(ip: &str, user: Option<&str>) -> ConnInfo {
+        let mut conn = ConnInfo::new(Some(SocketAddr::new(ip.parse().unwrap(), 1234)));
+        conn.identity = user.map(Into::into);
+        conn
+    }
+
+    #[test]
+    fn limiter_should_limit_commands_and_bytes() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            ip: RateLimit {
+                commands_per_sec: Some(2.0),
+                bytes_per_sec: None,
+            },
+            user: RateLimit {
+                commands_per_sec: None,
+                bytes_per_sec: Some(100.0),
+            },
+            ..Default::default()
+        });
+
+        let c1 = conn("10.0.0.1", None);
+        assert!(limiter.check(&c1, 10).is_ok());
+        assert!(limiter.check(&c1, 10).is_ok());
+        let wait = limiter.check(&c1, 10).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(1));
+        // 不同 IP 的令牌桶互不影响
+        assert!(limiter.check(&conn("10.0.0.2", None), 10).is_ok());
+
+        // 用户的字节限制，超过容量的请求在桶满时允许
+        let c2 = conn("10.0.0.3", Some("batch"));
+        assert!(limiter.check(&c2, 500).is_ok());
+        assert!(limiter.check(&c2, 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn limits_should_be_adjustable_at_runtime() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            ip: RateLimit {
+                commands_per_sec: Some(1.0),
+                bytes_per_sec: None,
+            },
+            ..Default::default()
+        });
+        let service: Service = ServiceInner::new(MemTable::new())
+            .rate_limiter(limiter)
+            .into();
+        let limiter = service.rate_limiter().unwrap();
+
+        let c1 = conn("10.0.0.1", None);
+        assert!(limiter.check(&c1, 1).is_ok());
+        assert!(limiter.check(&c1, 1).is_err());
+
+        let cmd = CommandRequest::new_set_rate_limit("ip:10.0.0.1", Some(100.0), None);
+        assert_ok(service.execute(cmd).await, &["OK".into()]);
+        assert!(limiter.check(&c1, 1).is_ok());
+        assert_eq!(
+            limiter.config().ips["10.0.0.1"].commands_per_sec,
+            Some(100.0)
+        );
+
+        let cmd = CommandRequest::new_set_rate_limit("host:1", None, None);
+        assert_eq!(service.execute(cmd).await.status, 400);
+    }
+
+    #[tokio::test]
+    async fn set_rate_limit_should_be_authorized_regardless_of_order() {
+        let auth = AuthConfig {
+            users: vec![UserConfig {
+                name: "app".into(),
+                token: Some("t0ken".into()),
+                acl: vec![AclRule {
+                    action: AclAction::Allow,
+                    commands: vec!["get".into()],
+                    prefix: String::new(),
+                }],
+                ..Default::default()
+            }],
+        };
+        let service: Service = ServiceInner::new(MemTable::new())
+            .rate_limiter(RateLimiter::new(Default::default()))
+            .layer(AuthLayer::new(auth))
+            .into();
+
+        let cmd = || CommandRequest::new_set_rate_limit("ip:*", Some(1.0), None);
+        assert_eq!(service.execute(cmd()).await.status, 401);
+        let mut ctx = Context::new();
+        let ret = service
+            .call(CommandRequest::new_auth_token("t0ken"), &mut ctx)
+            .await;
+        assert_ok(ret, &["OK".into()]);
+        assert_eq!(service.call(cmd(), &mut ctx).await.status, 403);
+        assert_eq!(
+            service.rate_limiter().unwrap().config().ip,
+            RateLimit::default()
+        );
+    }
+}
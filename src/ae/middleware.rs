@@ -0,0 +1,256 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{CommandRequest, CommandResponse};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// 中间件，包裹在命令执行的外层
+///
+/// 可以调用 `next.run` 继续执行内层，也可以直接返回自己的 Response
+pub trait Middleware: Send + Sync {
+    fn call<'a>(
+        &'a self,
+        cmd: CommandRequest,
+        ctx: &'a mut Context,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, CommandResponse>;
+}
+
+/// 最内层，真正执行命令
+pub(crate) trait Endpoint: Send + Sync {
+    fn call<'a>(
+        &'a self,
+        cmd: CommandRequest,
+        ctx: &'a mut Context,
+    ) -> BoxFuture<'a, CommandResponse>;
+}
+
+/// 中间件栈中剩余的部分
+pub struct Next<'a> {
+    layers: &'a [Arc<dyn Middleware>],
+    endpoint: &'a dyn Endpoint,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(layers: &'a [Arc<dyn Middleware>], endpoint: &'a dyn Endpoint) -> Self {
+        Self { layers, endpoint }
+    }
+
+    /// 执行下一层
+    pub fn run<'b>(
+        self,
+        cmd: CommandRequest,
+        ctx: &'b mut Context,
+    ) -> BoxFuture<'b, CommandResponse>
+    where
+        'a: 'b,
+    {
+        match self.layers.split_first() {
+            Some((layer, rest)) => layer.call(cmd, ctx, Next::new(rest, self.endpoint)),
+            None => self.endpoint.call(cmd, ctx),
+        }
+    }
+}
+
+static NEXT_CONN_ID: AtomicU64 = AtomicU64::new(1);
+
+/// 请求所在连接的信息
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnInfo {
+    /// 连接 id，进程内唯一，不经过网络直接执行的请求为 0
+    pub id: u64,
+    /// 对端地址
+    pub peer: Option<SocketAddr>,
+    /// 认证通过的用户
+    pub identity: Option<String>,
+}
+
+impl ConnInfo {
+    /// 为新连接分配 id
+    pub fn new(peer: Option<SocketAddr>) -> Self {
+        Self {
+            id: NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed),
+            peer,
+            identity: None,
+        }
+    }
+}
+
+/// 单个请求的上下文，中间件之间可以通过它按类型传递数据
+pub struct Context {
+    conn: ConnInfo,
+    started: Instant,
+    extensions: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::with_conn(ConnInfo::default())
+    }
+
+    pub fn with_conn(conn: ConnInfo) -> Self {
+        Self {
+            conn,
+            started: Instant::now(),
+            extensions: HashMap::new(),
+        }
+    }
+
+    pub fn conn(&self) -> &ConnInfo {
+        &self.conn
+    }
+
+    pub fn conn_mut(&mut self) -> &mut ConnInfo {
+        &mut self.conn
+    }
+
+    /// 取回连接信息，用于同一连接的下一个请求
+    pub fn into_conn(self) -> ConnInfo {
+        self.conn
+    }
+
+    /// 请求开始处理的时间
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// 请求开始处理到现在的耗时
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.extensions
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok().map(|v| *v))
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.extensions
+            .get(&TypeId::of::<T>())
+            .and_then(|v| v.downcast_ref())
+    }
+
+    pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T> {
+        self.extensions
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|v| v.downcast_mut())
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        self.extensions
+            .remove(&TypeId::of::<T>())
+            .and_then(|v| v.downcast().ok().map(|v| *v))
+    }
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Context")
+            .field("conn", &self.conn)
+            .field("started", &self.started)
+            .field("extensions", &self.extensions.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assert_ok, MemTable, Service, ServiceInner, Storage, Value};
+
+    /// 只读模式: 拒绝写命令
+    struct ReadOnly;
+
+    impl Middleware for ReadOnly {
+        fn call<'a>(
+            &'a self,
+            cmd: CommandRequest,
+            ctx: &'a mut Context,
+            next: Next<'a>,
+        ) -> BoxFuture<'a, CommandResponse> {
+            Box::pin(async move {
+                match cmd.data {
+                    Some(crate::command_request::Data::Set(_)) => CommandResponse {
+                        status: 403,
+                        message: "read only".into(),
+                        ..Default::default()
+                    },
+                    _ => next.run(cmd, ctx).await,
+                }
+            })
+        }
+    }
+
+    /// 统计执行次数，并把次数放进上下文
+    #[derive(Default)]
+    struct Counter(Arc<AtomicU64>);
+
+    struct Calls(u64);
+
+    impl Middleware for Counter {
+        fn call<'a>(
+            &'a self,
+            cmd: CommandRequest,
+            ctx: &'a mut Context,
+            next: Next<'a>,
+        ) -> BoxFuture<'a, CommandResponse> {
+            Box::pin(async move {
+                let n = self.0.fetch_add(1, Ordering::Relaxed) + 1;
+                ctx.insert(Calls(n));
+                let ret = next.run(cmd, ctx).await;
+                assert_eq!(ctx.get::<Calls>().map(|c| c.0), Some(n));
+                ret
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn middleware_should_wrap_dispatch() {
+        let store = MemTable::new();
+        store.set("k1", "v1").unwrap();
+        let calls = Arc::new(AtomicU64::new(0));
+        let service: Service = ServiceInner::new(store)
+            .layer(Counter(calls.clone()))
+            .layer(ReadOnly)
+            .into();
+
+        let ret = service
+            .execute(CommandRequest::new_set("k1", "v2".into()))
+            .await;
+        assert_eq!(ret.status, 403);
+        let ret = service.execute(CommandRequest::new_get("k1")).await;
+        assert_ok(ret, &[Value::from("v1")]);
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn context_should_store_typed_values() {
+        let mut ctx = Context::new();
+        assert!(ctx.insert(1u64).is_none());
+        assert_eq!(ctx.insert(2u64), Some(1));
+        ctx.insert("user");
+        *ctx.get_mut::<u64>().unwrap() += 1;
+        assert_eq!(ctx.get::<u64>(), Some(&3));
+        assert_eq!(ctx.remove::<&str>(), Some("user"));
+        assert!(ctx.get::<&str>().is_none());
+    }
+}
@@ -1,12 +1,35 @@
+mod admission;
+mod audit;
+mod auth;
+mod files;
 mod handler;
+mod limit;
+mod middleware;
+mod slowlog;
 
-use std::sync::Arc;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
+pub use admission::*;
+pub use audit::*;
+pub use auth::*;
+pub use files::*;
 pub use handler::*;
-use tracing::debug;
+pub use limit::*;
+pub use middleware::*;
+pub use slowlog::*;
+use tracing::{debug, info};
 
 use crate::command_request::Data;
-use crate::{CommandRequest, CommandResponse, HikvError, MemTable, Storage};
+use crate::{
+    AdmissionConfig, CommandRequest, CommandResponse, FilesConfig, HikvError, MemTable,
+    SlowLogConfig, Storage, MAX_SNAPSHOT_TIMEOUT,
+};
 
 /// 对 Command 的处理抽象
 pub trait CommandHandler {
@@ -20,6 +43,23 @@ pub fn dispatch(req: CommandRequest, store: &impl Storage) -> CommandResponse {
         Some(Data::Set(param)) => param.handle(store),
         Some(Data::Del(param)) => param.handle(store),
         Some(Data::Exist(param)) => param.handle(store),
+        Some(Data::Save(param)) => param.handle(store),
+        Some(Data::BgSave(param)) => param.handle(store),
+        Some(Data::Export(param)) => param.handle(store),
+        Some(Data::Import(param)) => param.handle(store),
+        Some(Data::Meta(param)) => param.handle(store),
+        Some(Data::Snapshot(param)) => param.handle(store),
+        Some(Data::ReleaseSnapshot(param)) => param.handle(store),
+        Some(Data::Info(param)) => param.handle(store),
+        Some(Data::Compact(param)) => param.handle(store),
+        Some(Data::Flush(param)) => param.handle(store),
+        Some(Data::Backup(param)) => param.handle(store),
+        Some(Data::Auth(param)) => param.handle(store),
+        Some(Data::Ping(param)) => param.handle(store),
+        Some(Data::SetRateLimit(param)) => param.handle(store),
+        Some(Data::SlowLogGet(_) | Data::SlowLogLen(_) | Data::SlowLogReset(_)) => {
+            HikvError::InvalidCommand("slow log is kept by Service".into()).into()
+        }
         None => HikvError::InvalidCommand("request has not data".into()).into(),
         // _ => HikvError::Internal("Not implemented".into()).into(),
     }
@@ -31,58 +71,311 @@ pub struct Service<Store = MemTable> {
 
 pub struct ServiceInner<Store> {
     store: Store,
-    on_received: Vec<fn(&CommandRequest)>,
-    on_executed: Vec<fn(&CommandResponse)>,
-    on_before_reply: Vec<fn(&mut CommandResponse)>,
-    on_after_reply: Vec<fn()>,
+    hooks: Hooks,
+    layers: Vec<Arc<dyn Middleware>>,
+    limiter: Option<Arc<RateLimiter>>,
+    admission: Arc<Admission>,
+    slow_log: SlowLog,
+    files: FilesConfig,
+    /// Snapshot 命令的超时上限
+    max_snapshot_timeout: Duration,
+    on_after_reply: Vec<Hook<Result<usize, HikvError>>>,
+    on_evicted: Vec<Hook<Evicted>>,
+    /// 已通知过的淘汰总数
+    evicted_seen: AtomicU64,
+}
+
+/// 请求相关的回调，作为最外层的中间件执行
+#[derive(Default)]
+struct Hooks {
+    on_received: Vec<Hook<CommandRequest>>,
+    on_executed: Vec<Hook<CommandResponse>>,
+    on_before_reply: Vec<HookMut<CommandResponse>>,
+}
+
+/// 事件回调，可以捕获状态，同时拿到请求上下文
+pub type Hook<Arg> = Arc<dyn Fn(&Arg, &Context) -> Decision + Send + Sync>;
+/// 可以修改参数的事件回调
+pub type HookMut<Arg> = Arc<dyn Fn(&mut Arg, &Context) -> Decision + Send + Sync>;
+
+/// 回调对后续处理的决定
+#[derive(Debug, Default)]
+pub enum Decision {
+    /// 继续后续处理
+    #[default]
+    Continue,
+    /// 拒绝请求，返回对应的错误
+    Reject(HikvError),
+    /// 跳过后续处理，直接返回给定的 Response
+    Respond(CommandResponse),
+}
+
+impl Decision {
+    /// 需要提前返回时的 Response
+    pub fn into_response(self) -> Option<CommandResponse> {
+        match self {
+            Decision::Continue => None,
+            Decision::Reject(e) => Some(e.into()),
+            Decision::Respond(resp) => Some(resp),
+        }
+    }
+}
+
+impl From<()> for Decision {
+    fn from(_: ()) -> Self {
+        Decision::Continue
+    }
+}
+
+/// 存储因内存上限淘汰 key 的事件
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Evicted {
+    /// 自上次通知以来淘汰的 key 数量
+    pub count: u64,
+    /// 累计淘汰的 key 数量
+    pub total: u64,
 }
 
 impl<Store: Storage> ServiceInner<Store> {
     pub fn new(store: Store) -> Self {
         Self {
             store,
-            on_received: Vec::new(),
-            on_executed: Vec::new(),
-            on_before_reply: Vec::new(),
+            hooks: Hooks::default(),
+            layers: Vec::new(),
+            limiter: None,
+            admission: Arc::new(Admission::default()),
+            slow_log: SlowLog::default(),
+            files: FilesConfig::default(),
+            max_snapshot_timeout: MAX_SNAPSHOT_TIMEOUT,
             on_after_reply: Vec::new(),
+            on_evicted: Vec::new(),
+            evicted_seen: AtomicU64::new(0),
         }
     }
 
-    pub fn fn_received(mut self, f: fn(&CommandRequest)) -> Self {
-        self.on_received.push(f);
+    /// 收到请求后回调，返回 Reject/Respond 时不再执行命令
+    pub fn fn_received<R, F>(mut self, f: F) -> Self
+    where
+        R: Into<Decision>,
+        F: Fn(&CommandRequest, &Context) -> R + Send + Sync + 'static,
+    {
+        self.hooks
+            .on_received
+            .push(Arc::new(move |cmd, ctx| f(cmd, ctx).into()));
+        self
+    }
+
+    /// 命令执行后回调，返回 Reject/Respond 时替换执行结果
+    pub fn fn_executed<R, F>(mut self, f: F) -> Self
+    where
+        R: Into<Decision>,
+        F: Fn(&CommandResponse, &Context) -> R + Send + Sync + 'static,
+    {
+        self.hooks
+            .on_executed
+            .push(Arc::new(move |resp, ctx| f(resp, ctx).into()));
+        self
+    }
+
+    /// 回复前回调，可以修改 Response，返回 Reject/Respond 时替换 Response
+    pub fn fn_before_reply<R, F>(mut self, f: F) -> Self
+    where
+        R: Into<Decision>,
+        F: Fn(&mut CommandResponse, &Context) -> R + Send + Sync + 'static,
+    {
+        self.hooks
+            .on_before_reply
+            .push(Arc::new(move |resp, ctx| f(resp, ctx).into()));
         self
     }
 
-    pub fn fn_executed(mut self, f: fn(&CommandResponse)) -> Self {
-        self.on_executed.push(f);
+    /// 回复发送后回调，参数为写入的字节数或发送错误
+    pub fn fn_after_reply<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Result<usize, HikvError>, &Context) + Send + Sync + 'static,
+    {
+        self.on_after_reply.push(Arc::new(move |sent, ctx| {
+            f(sent, ctx);
+            Decision::Continue
+        }));
         self
     }
 
-    pub fn fn_before_reply(mut self, f: fn(&mut CommandResponse)) -> Self {
-        self.on_before_reply.push(f);
+    pub fn fn_evicted<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Evicted, &Context) + Send + Sync + 'static,
+    {
+        self.on_evicted.push(Arc::new(move |event, ctx| {
+            f(event, ctx);
+            Decision::Continue
+        }));
         self
     }
 
-    pub fn fn_after_reply(mut self, f: fn()) -> Self {
-        self.on_after_reply.push(f);
+    /// 添加中间件，先添加的在外层，回调始终在所有中间件之外
+    pub fn layer(mut self, layer: impl Middleware + 'static) -> Self {
+        self.layers.push(Arc::new(layer));
+        self
+    }
+
+    /// 启用限流，连接循环检查限制；SetRateLimit 在所有中间件之后处理，和调用顺序无关
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = Some(Arc::new(limiter));
+        self
+    }
+
+    /// 设置连接数、在途请求数和存储并发的限制
+    pub fn admission(mut self, config: AdmissionConfig) -> Self {
+        self.admission = Arc::new(Admission::new(config));
+        self
+    }
+
+    pub fn slow_log(mut self, config: SlowLogConfig) -> Self {
+        self.slow_log = SlowLog::new(config);
+        self
+    }
+
+    /// 设置 Export/Import/Backup 可以访问的目录，未设置时拒绝这些命令
+    pub fn files(mut self, config: FilesConfig) -> Self {
+        self.files = config;
+        self
+    }
+
+    /// 设置 Snapshot 命令的超时上限，客户端请求更长的超时时按上限处理
+    pub fn max_snapshot_timeout(mut self, timeout: Duration) -> Self {
+        self.max_snapshot_timeout = timeout;
         self
     }
 }
 
-impl<Store: Storage> Service<Store> {
-    pub fn execute(&self, cmd: CommandRequest) -> CommandResponse {
+impl<Store: Storage + Send + Sync> Service<Store> {
+    pub async fn execute(&self, cmd: CommandRequest) -> CommandResponse {
+        self.call(cmd, &mut Context::new()).await
+    }
+
+    /// 经过回调和中间件执行命令
+    pub async fn call(&self, cmd: CommandRequest, ctx: &mut Context) -> CommandResponse {
         debug!("Got request: {:?}", cmd);
-        self.inner.on_received.notify(&cmd);
-        let mut ret = dispatch(cmd, &self.inner.store);
-        debug!("Executed response: {:?}", ret);
-        self.inner.on_executed.notify(&ret);
+        let pending = Pending::new(&cmd);
+        let next = Next::new(&self.inner.layers, &*self.inner);
+        let ret = self.inner.hooks.call(cmd, ctx, next).await;
+        if let Some(pending) = pending {
+            self.inner.slow_log.observe(pending, &ret, ctx);
+        }
+        ret
+    }
+
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.inner.limiter.as_deref()
+    }
+
+    pub fn admission(&self) -> &Arc<Admission> {
+        &self.inner.admission
+    }
 
-        self.inner.on_before_reply.notify(&mut ret);
-        if !self.inner.on_before_reply.is_empty() {
-            debug!("Modified response: {:?}", ret);
+    pub fn slow_log(&self) -> &SlowLog {
+        &self.inner.slow_log
+    }
+
+    /// 关闭服务前把存储中尚未持久化的修改写到磁盘
+    pub fn shutdown(&self) -> Result<(), HikvError> {
+        info!("Flushing storage before shutdown");
+        self.inner.store.flush()
+    }
+
+    /// 回复写入连接后通知 on_after_reply
+    pub fn notify_after_reply(&self, sent: &Result<usize, HikvError>, ctx: &Context) {
+        debug!("Reply sent: {:?}, elapsed {:?}", sent, ctx.elapsed());
+        self.inner.on_after_reply.notify(sent, ctx);
+    }
+}
+
+impl<Store: Storage> ServiceInner<Store> {
+    /// 存储有新的淘汰时通知 on_evicted，并发执行时每次淘汰只通知一次
+    fn notify_evicted(&self, ctx: &Context) {
+        let total = self.store.evicted();
+        let seen = self.evicted_seen.fetch_max(total, Ordering::AcqRel);
+        if total > seen {
+            let event = Evicted {
+                count: total - seen,
+                total,
+            };
+            debug!("Evicted: {:?}", event);
+            self.on_evicted.notify(&event, ctx);
         }
+    }
+}
 
-        ret
+impl<Store: Storage + Send + Sync> Endpoint for ServiceInner<Store> {
+    fn call<'a>(
+        &'a self,
+        mut cmd: CommandRequest,
+        ctx: &'a mut Context,
+    ) -> BoxFuture<'a, CommandResponse> {
+        Box::pin(async move {
+            if let Some(ret) = self.slow_log.handle(&cmd) {
+                return ret;
+            }
+            if let Some(ret) = self.limiter.as_ref().and_then(|l| l.handle(&cmd)) {
+                return ret;
+            }
+            if let Err(e) = self.files.resolve(&mut cmd) {
+                return e.into();
+            }
+            if let Some(Data::Snapshot(param)) = &mut cmd.data {
+                if param.timeout() > self.max_snapshot_timeout {
+                    param.timeout_ms = self.max_snapshot_timeout.as_millis() as u64;
+                }
+            }
+            let is_info = matches!(cmd.data, Some(Data::Info(_)));
+            let permit = self.admission.acquire_storage().await;
+            let mut ret = if let Some(Data::Import(param)) = &cmd.data {
+                let (ret, keys) = param.clone().run(&self.store);
+                ctx.insert(keys);
+                ret
+            } else {
+                dispatch(cmd, &self.store)
+            };
+            drop(permit);
+            if is_info && ret.status == 200 {
+                ret.pairs.extend(self.admission.metrics().to_pairs());
+            }
+            debug!("Executed response: {:?}", ret);
+            self.notify_evicted(ctx);
+            ret
+        })
+    }
+}
+
+impl Middleware for Hooks {
+    fn call<'a>(
+        &'a self,
+        cmd: CommandRequest,
+        ctx: &'a mut Context,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, CommandResponse> {
+        Box::pin(async move {
+            let mut ret = match self.on_received.notify(&cmd, ctx).into_response() {
+                Some(resp) => {
+                    debug!("Request short-circuited: {:?}", resp);
+                    resp
+                }
+                None => {
+                    let ret = next.run(cmd, ctx).await;
+                    let decision = self.on_executed.notify(&ret, ctx);
+                    decision.into_response().unwrap_or(ret)
+                }
+            };
+
+            if let Some(resp) = self.on_before_reply.notify(&mut ret, ctx).into_response() {
+                ret = resp;
+            }
+            if !self.on_before_reply.is_empty() {
+                debug!("Modified response: {:?}", ret);
+            }
+
+            ret
+        })
     }
 }
 
@@ -103,83 +396,203 @@ impl<Store> Clone for Service<Store> {
 }
 
 /// Immutable event notification
+///
+/// 依次执行回调，遇到第一个非 Continue 的决定时停止
 pub trait Notify<Arg> {
-    fn notify(&self, arg: &Arg);
+    fn notify(&self, arg: &Arg, ctx: &Context) -> Decision;
 }
-impl<Arg> Notify<Arg> for Vec<fn(&Arg)> {
+impl<Arg> Notify<Arg> for Vec<Hook<Arg>> {
     #[inline]
-    fn notify(&self, arg: &Arg) {
+    fn notify(&self, arg: &Arg, ctx: &Context) -> Decision {
         for f in self {
-            f(arg);
+            match f(arg, ctx) {
+                Decision::Continue => {}
+                decision => return decision,
+            }
         }
+        Decision::Continue
     }
 }
 
 /// mutable event notification
 pub trait NotifyMut<Arg> {
-    fn notify(&self, arg: &mut Arg);
+    fn notify(&self, arg: &mut Arg, ctx: &Context) -> Decision;
 }
-impl<Arg> NotifyMut<Arg> for Vec<fn(&mut Arg)> {
+impl<Arg> NotifyMut<Arg> for Vec<HookMut<Arg>> {
     #[inline]
-    fn notify(&self, arg: &mut Arg) {
+    fn notify(&self, arg: &mut Arg, ctx: &Context) -> Decision {
         for f in self {
-            f(arg);
+            match f(arg, ctx) {
+                Decision::Continue => {}
+                decision => return decision,
+            }
         }
+        Decision::Continue
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::thread;
-
     use tracing::info;
 
     use super::*;
-    use crate::{MemTable, Value};
+    use crate::{DumpFormat, EvictionPolicy, MemTable, Value};
 
-    #[test]
-    fn should_work_service() {
+    #[tokio::test]
+    async fn should_work_service() {
         let service: Service = ServiceInner::new(MemTable::new()).into();
 
         let cloned = service.clone();
 
-        let handle = thread::spawn(move || {
-            let ret = cloned.execute(CommandRequest::new_set("name", "tom".into()));
+        let handle = tokio::spawn(async move {
+            let ret = cloned
+                .execute(CommandRequest::new_set("name", "tom".into()))
+                .await;
             assert_ok(ret, &[Value::default()]);
         });
-        handle.join().unwrap();
+        handle.await.unwrap();
 
-        let ret = service.execute(CommandRequest::new_get("name"));
+        let ret = service.execute(CommandRequest::new_get("name")).await;
         assert_ok(ret, &["tom".into()]);
     }
 
-    #[test]
-    fn should_work_event_register() {
-        fn received0(cmd: &CommandRequest) {
+    #[tokio::test]
+    async fn should_work_event_register() {
+        fn received0(cmd: &CommandRequest, _: &Context) {
             info!("Got: {:?}", cmd);
         }
-        fn executed0(resp: &CommandResponse) {
+        fn executed0(resp: &CommandResponse, _: &Context) {
             info!("{:?}", resp);
         }
-        fn before_reply0(resp: &mut CommandResponse) {
+        fn before_reply0(resp: &mut CommandResponse, _: &Context) {
             resp.status = 201;
         }
-        fn after_reply0() {
-            info!("Data is sent")
+        fn after_reply0(sent: &Result<usize, HikvError>, ctx: &Context) {
+            info!("Data is sent: {:?}, {:?}", sent, ctx)
         }
         let service: Service = ServiceInner::new(MemTable::default())
-            .fn_received(|_: &CommandRequest| {})
+            .fn_received(|_: &CommandRequest, _: &Context| {})
             .fn_received(received0)
             .fn_executed(executed0)
             .fn_before_reply(before_reply0)
             .fn_after_reply(after_reply0)
             .into();
 
-        let ret = service.execute(CommandRequest::new_set("k1", "v1".into()));
+        let ret = service
+            .execute(CommandRequest::new_set("k1", "v1".into()))
+            .await;
         assert_eq!(ret.status, 201);
         assert_eq!(ret.message, "");
         assert_eq!(ret.values, vec![Value::default()]);
     }
+
+    #[tokio::test]
+    async fn should_work_closure_hooks_with_decision() {
+        let executed = Arc::new(AtomicU64::new(0));
+        let counter = executed.clone();
+        let service: Service = ServiceInner::new(MemTable::default())
+            .fn_received(|cmd: &CommandRequest, _: &Context| match &cmd.data {
+                Some(Data::Del(_)) => Decision::Reject(HikvError::Unsupported("del")),
+                Some(Data::Exist(_)) => Decision::Respond(CommandResponse::default()),
+                _ => Decision::Continue,
+            })
+            .fn_executed(move |_: &CommandResponse, _: &Context| {
+                counter.fetch_add(1, Ordering::Relaxed);
+            })
+            .into();
+
+        let ret = service
+            .execute(CommandRequest::new_set("k1", "v1".into()))
+            .await;
+        assert_ok(ret, &[Value::default()]);
+        let ret = service.execute(CommandRequest::new_del("k1")).await;
+        assert_eq!(ret.status, 501);
+        let ret = service.execute(CommandRequest::new_exist("k1")).await;
+        assert_eq!(ret.status, 0);
+        // 被拦截的请求不会执行
+        assert_eq!(executed.load(Ordering::Relaxed), 1);
+        assert_ok(
+            service.execute(CommandRequest::new_get("k1")).await,
+            &["v1".into()],
+        );
+    }
+
+    #[tokio::test]
+    async fn should_work_evicted_event() {
+        static EVICTED: AtomicU64 = AtomicU64::new(0);
+        fn evicted0(event: &Evicted, _: &Context) {
+            EVICTED.fetch_add(event.count, Ordering::Relaxed);
+        }
+        let store = MemTable::new().max_memory(256, EvictionPolicy::AllKeysRandom);
+        let service: Service = ServiceInner::new(store).fn_evicted(evicted0).into();
+
+        for i in 0..10 {
+            service
+                .execute(CommandRequest::new_set(format!("k{}", i), "v".into()))
+                .await;
+        }
+
+        let total = service.inner.store.evicted();
+        assert!(total > 0);
+        assert_eq!(EVICTED.load(Ordering::Relaxed), total);
+    }
+
+    #[tokio::test]
+    async fn snapshot_timeout_should_be_clamped() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .max_snapshot_timeout(Duration::from_millis(1))
+            .into();
+        let ret = service.execute(CommandRequest::new_snapshot(60_000)).await;
+        let id: i64 = ret.values[0].clone().try_into().unwrap();
+
+        std::thread::sleep(Duration::from_millis(5));
+        let ret = service
+            .execute(CommandRequest::new_get_at("k", id as u64))
+            .await;
+        assert_eq!(ret.status, 404);
+    }
+
+    #[tokio::test]
+    async fn export_import_should_stay_in_dump_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = dir.path().join("outside.jsonl");
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let ret = service
+            .execute(CommandRequest::new_export("a.jsonl", DumpFormat::JsonLines))
+            .await;
+        assert_err(ret, 400, "dump directory is not configured");
+
+        let service: Service = ServiceInner::new(MemTable::new())
+            .files(FilesConfig {
+                dump_dir: Some(dir.path().join("dump")),
+                ..Default::default()
+            })
+            .into();
+        service
+            .execute(CommandRequest::new_set("k1", "v1".into()))
+            .await;
+        for path in [outside.to_str().unwrap(), "../outside.jsonl"] {
+            let ret = service
+                .execute(CommandRequest::new_export(path, DumpFormat::JsonLines))
+                .await;
+            assert_err(ret, 400, "invalid path");
+        }
+        assert!(!outside.exists());
+
+        let ret = service
+            .execute(CommandRequest::new_export("a.jsonl", DumpFormat::JsonLines))
+            .await;
+        assert_ok(ret, &[1.into()]);
+        assert!(dir.path().join("dump/a.jsonl").exists());
+        let ret = service
+            .execute(CommandRequest::new_import(
+                "a.jsonl",
+                DumpFormat::JsonLines,
+                true,
+            ))
+            .await;
+        assert_ok(ret, &[1.into(), 0.into()]);
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,208 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::command_request::Data;
+use crate::{CommandRequest, CommandResponse, Context, HikvError, SlowLogConfig, Value};
+
+/// 执行时间超过阈值的命令，最新的在前
+pub struct SlowLog {
+    config: SlowLogConfig,
+    next_id: AtomicU64,
+    entries: Mutex<VecDeque<SlowLogEntry>>,
+}
+
+/// 一条慢日志
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlowLogEntry {
+    /// 自增 id，清空后也不会重复
+    pub id: u64,
+    /// 记录时间，毫秒时间戳
+    pub timestamp: u64,
+    pub command: String,
+    pub key: Option<String>,
+    pub duration_us: u64,
+    /// 客户端地址，不经过网络执行的命令为 None
+    pub client: Option<String>,
+    /// 写入或返回的 value 大小(字节)
+    pub value_size: usize,
+}
+
+/// 执行前从请求中取出的信息，执行变慢时用来生成记录
+pub(crate) struct Pending {
+    command: &'static str,
+    key: Option<String>,
+    value_size: usize,
+}
+
+impl SlowLog {
+    pub fn new(config: SlowLogConfig) -> Self {
+        Self {
+            config,
+            next_id: AtomicU64::new(1),
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn threshold(&self) -> Duration {
+        Duration::from_micros(self.config.threshold_us)
+    }
+
+    /// 记录超过阈值的命令
+    pub(crate) fn observe(&self, pending: Pending, ret: &CommandResponse, ctx: &Context) {
+        let elapsed = ctx.elapsed();
+        if elapsed < self.threshold() || self.config.max_len == 0 {
+            return;
+        }
+        let returned: usize = ret.values.iter().map(|v| v.encoded_len()).sum();
+        let entry = SlowLogEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            timestamp: crate::now_millis(),
+            command: pending.command.into(),
+            key: pending.key,
+            duration_us: elapsed.as_micros() as u64,
+            client: ctx.conn().peer.map(|addr| addr.to_string()),
+            value_size: pending.value_size + returned,
+        };
+        warn!(
+            "Slow command {} on {:?} took {:?}",
+            entry.command, entry.key, elapsed
+        );
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_front(entry);
+        entries.truncate(self.config.max_len);
+    }
+
+    /// 最近的 count 条记录，count 为 0 时返回全部
+    pub fn get(&self, count: usize) -> Vec<SlowLogEntry> {
+        let entries = self.entries.lock().unwrap();
+        let count = if count == 0 { entries.len() } else { count };
+        entries.iter().take(count).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn reset(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// 处理 SlowLogGet/SlowLogLen/SlowLogReset，其他命令返回 None
+    pub(crate) fn handle(&self, cmd: &CommandRequest) -> Option<CommandResponse> {
+        let ret = match &cmd.data {
+            Some(Data::SlowLogGet(param)) => {
+                let entries: Result<Vec<Value>, _> = self
+                    .get(param.count as usize)
+                    .iter()
+                    .map(|entry| serde_json::to_string(entry).map(Value::from))
+                    .collect();
+                match entries {
+                    Ok(values) => values.into(),
+                    Err(e) => HikvError::Internal(e.to_string()).into(),
+                }
+            }
+            Some(Data::SlowLogLen(_)) => Value::from(self.len() as i64).into(),
+            Some(Data::SlowLogReset(_)) => {
+                self.reset();
+                Value::from("OK").into()
+            }
+            _ => return None,
+        };
+        Some(ret)
+    }
+}
+
+impl Default for SlowLog {
+    fn default() -> Self {
+        Self::new(SlowLogConfig::default())
+    }
+}
+
+impl Pending {
+    /// 慢日志自己的命令不记录
+    pub(crate) fn new(cmd: &CommandRequest) -> Option<Self> {
+        let value_size = match &cmd.data {
+            Some(Data::SlowLogGet(_) | Data::SlowLogLen(_) | Data::SlowLogReset(_)) => return None,
+            Some(Data::Set(param)) => param.value.as_ref().map_or(0, |v| v.encoded_len()),
+            _ => 0,
+        };
+        Some(Self {
+            command: cmd.name(),
+            key: cmd.key().map(Into::into),
+            value_size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assert_ok, value, MemTable, Service, ServiceInner};
+
+    fn slow_log(threshold_us: u64, max_len: usize) -> SlowLogConfig {
+        SlowLogConfig {
+            threshold_us,
+            max_len,
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_commands_should_be_recorded() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .slow_log(slow_log(0, 2))
+            .into();
+        service
+            .execute(CommandRequest::new_set("k1", "hello".into()))
+            .await;
+        service.execute(CommandRequest::new_get("k1")).await;
+        service.execute(CommandRequest::new_exist("k2")).await;
+
+        // 只保留最新的 2 条
+        let ret = service.execute(CommandRequest::new_slow_log_len()).await;
+        assert_ok(ret, &[Value::from(2)]);
+
+        let ret = service.execute(CommandRequest::new_slow_log_get(0)).await;
+        let entries: Vec<SlowLogEntry> = ret
+            .values
+            .into_iter()
+            .map(|v| match v.value {
+                Some(value::Value::String(s)) => serde_json::from_str(&s).unwrap(),
+                v => panic!("unexpected value {:?}", v),
+            })
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].command, "exist");
+        assert_eq!(entries[1].command, "get");
+        assert_eq!(entries[1].key.as_deref(), Some("k1"));
+        assert!(entries[1].value_size > 0);
+        assert!(entries[0].id > entries[1].id);
+
+        let ret = service.execute(CommandRequest::new_slow_log_reset()).await;
+        assert_ok(ret, &["OK".into()]);
+        assert!(service.slow_log().is_empty());
+    }
+
+    #[tokio::test]
+    async fn fast_commands_should_not_be_recorded() {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .slow_log(slow_log(60_000_000, 16))
+            .into();
+        service.execute(CommandRequest::new_get("k1")).await;
+        assert!(service.slow_log().is_empty());
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{AnyStorage, Digest, HikvError, Storage, StorageKind, StorageSpec};
+
+/// 备份目录中的清单文件
+pub const BACKUP_MANIFEST: &str = "MANIFEST";
+/// 备份目录中的数据: memory 后端为快照文件，其余为可直接打开的数据目录
+pub const BACKUP_DATA: &str = "data";
+/// 恢复时每批写入的 key 数量
+const RESTORE_BATCH: usize = 1000;
+
+/// 备份清单
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupManifest {
+    /// 存储后端: memory / sled / rocksdb / bitcask
+    pub backend: StorageKind,
+    /// 备份时间(毫秒时间戳)
+    pub created_at: u64,
+    /// 是否只复制了上次备份之后变化的文件
+    pub incremental: bool,
+    #[serde(flatten)]
+    pub digest: Digest,
+}
+
+impl BackupManifest {
+    pub fn new(backend: StorageKind, digest: Digest, incremental: bool) -> Self {
+        Self {
+            backend,
+            created_at: crate::now_millis(),
+            incremental,
+            digest,
+        }
+    }
+
+    /// 读取备份目录中的清单
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self, HikvError> {
+        let content = fs::read_to_string(dir.as_ref().join(BACKUP_MANIFEST))?;
+        serde_yaml::from_str(&content).map_err(|e| HikvError::BackupError(e.to_string()))
+    }
+
+    fn store(&self, dir: &Path) -> Result<(), HikvError> {
+        let content =
+            serde_yaml::to_string(self).map_err(|e| HikvError::BackupError(e.to_string()))?;
+        let path = dir.join(BACKUP_MANIFEST);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// 在线备份到本地目录，写入数据后再写清单，清单存在即表示备份完整
+///
+/// 增量备份只有 bitcask 支持: segment 写满后不再修改，只需复制新增的文件
+pub fn backup(
+    store: &impl Storage,
+    dir: impl AsRef<Path>,
+    incremental: bool,
+) -> Result<BackupManifest, HikvError> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let data = dir.join(BACKUP_DATA);
+    if !incremental && data.exists() {
+        return Err(HikvError::BackupError(format!(
+            "backup target {:?} already exists",
+            data
+        )));
+    }
+    // 覆盖旧备份时先删除清单，中途失败不会留下看似完整的备份
+    let previous = fs::read(dir.join(BACKUP_MANIFEST)).ok();
+    let _ = fs::remove_file(dir.join(BACKUP_MANIFEST));
+
+    let manifest = match store.backup(&data, incremental) {
+        // 后端不支持增量时没有修改数据，恢复原来的清单
+        Err(e @ HikvError::Unsupported(_)) => {
+            if let Some(content) = previous {
+                fs::write(dir.join(BACKUP_MANIFEST), content)?;
+            }
+            return Err(e);
+        }
+        ret => ret?,
+    };
+    manifest.store(dir)?;
+    info!("Backup to {:?} finished: {:?}", dir, manifest.digest);
+    Ok(manifest)
+}
+
+/// 校验备份后把全部数据写入 target，target 必须为空
+///
+/// 备份数据先复制到临时目录再打开，恢复过程不会修改备份本身
+pub fn restore(dir: impl AsRef<Path>, target: &impl Storage) -> Result<BackupManifest, HikvError> {
+    let dir = dir.as_ref();
+    let manifest = BackupManifest::load(dir)?;
+    if target.get_iter()?.next().is_some() {
+        return Err(HikvError::BackupError("restore target is not empty".into()));
+    }
+
+    let tmp = tempfile::tempdir()?;
+    let data = tmp.path().join(BACKUP_DATA);
+    copy_all(&dir.join(BACKUP_DATA), &data)?;
+    let source = AnyStorage::open(&StorageSpec {
+        kind: manifest.backend,
+        path: data.to_string_lossy().into(),
+    })?;
+
+    let mut digest = Digest::default();
+    let mut batch = Vec::with_capacity(RESTORE_BATCH);
+    for pair in source.get_iter()? {
+        let pair = pair?;
+        digest.update(&pair)?;
+        batch.push(pair);
+        if batch.len() >= RESTORE_BATCH {
+            target.set_all(std::mem::take(&mut batch))?;
+        }
+    }
+    target.set_all(batch)?;
+    target.flush()?;
+
+    if digest != manifest.digest {
+        return Err(HikvError::BackupError(format!(
+            "backup verify failed: manifest {:?}, data {:?}",
+            manifest.digest, digest
+        )));
+    }
+    info!("Restored {} keys from backup {:?}", digest.count, dir);
+    Ok(manifest)
+}
+
+/// 复制文件或整个目录
+fn copy_all(src: &Path, dst: &Path) -> Result<(), HikvError> {
+    if src.is_file() {
+        fs::copy(src, dst)?;
+        return Ok(());
+    }
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let to: PathBuf = dst.join(entry.file_name());
+        copy_all(&entry.path(), &to)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    #[cfg(feature = "sled")]
+    use crate::SledDb;
+    use crate::{Bitcask, BitcaskOptions, MemTable, Value};
+
+    fn fill(store: &impl Storage, n: i64) {
+        for i in 0..n {
+            store.set(format!("key{:03}", i), Value::from(i)).unwrap();
+        }
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn should_backup_and_restore_sled() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir.path().join("db"));
+        fill(&store, 100);
+
+        let target = dir.path().join("backup");
+        let manifest = backup(&store, &target, false).unwrap();
+        assert_eq!(manifest.backend, StorageKind::Sled);
+        assert_eq!(manifest.digest, Digest::of(&store).unwrap());
+        assert!(backup(&store, &target, false).is_err());
+        // 不支持增量时不修改已有备份
+        let err = backup(&store, &target, true).unwrap_err();
+        assert!(matches!(err, HikvError::Unsupported(_)));
+        assert_eq!(BackupManifest::load(&target).unwrap(), manifest);
+
+        let restored = MemTable::new();
+        assert_eq!(restore(&target, &restored).unwrap(), manifest);
+        assert_eq!(Digest::of(&restored).unwrap(), manifest.digest);
+        assert!(restore(&target, &restored).is_err());
+    }
+
+    #[test]
+    fn should_backup_bitcask_incrementally() {
+        let dir = tempdir().unwrap();
+        let opts = BitcaskOptions {
+            max_segment_size: 256,
+            merge_interval: None,
+            ..Default::default()
+        };
+        let store = Bitcask::open(dir.path().join("db"), opts).unwrap();
+        fill(&store, 50);
+
+        let target = dir.path().join("backup");
+        backup(&store, &target, true).unwrap();
+        fill(&store, 60);
+        store.del("key000").unwrap();
+        let manifest = backup(&store, &target, true).unwrap();
+        assert!(manifest.incremental);
+        assert_eq!(manifest.digest.count, 59);
+
+        let restored = MemTable::new();
+        restore(&target, &restored).unwrap();
+        assert_eq!(restored.get("key000").unwrap(), None);
+        assert_eq!(restored.get("key059").unwrap(), Some(59.into()));
+    }
+
+    #[test]
+    fn should_reject_corrupted_backup() {
+        let dir = tempdir().unwrap();
+        let store = MemTable::new();
+        fill(&store, 10);
+
+        let target = dir.path().join("backup");
+        let mut manifest = backup(&store, &target, false).unwrap();
+        manifest.digest.checksum += 1;
+        manifest.store(&target).unwrap();
+
+        let err = restore(&target, &MemTable::new()).unwrap_err();
+        assert!(matches!(err, HikvError::BackupError(_)));
+    }
+}
@@ -1,21 +1,136 @@
-use hikv::{CommandRequest, HikvError, ProstClientStream};
+use clap::{Parser, Subcommand};
+use hikv::{CommandRequest, DumpFormat, HikvError, ProstClientStream};
 use tokio::net::TcpStream;
 use tracing::info;
 
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// 服务器地址
+    #[clap(short, long, default_value = "127.0.0.1:9527")]
+    addr: String,
+    /// 用户名，和 --password 一起在执行命令前认证
+    #[clap(short, long, requires = "password")]
+    user: Option<String>,
+    /// 密码
+    #[clap(short, long)]
+    password: Option<String>,
+    /// 静态 token，替代用户名和密码
+    #[clap(long, conflicts_with = "user")]
+    token: Option<String>,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// 检查连接是否可用
+    Ping,
+    /// 导出所有 key-value 到服务器上的文件，文件写在服务器而不是本机
+    Export {
+        /// 相对于服务器 files.dump_dir 的路径
+        path: String,
+        /// 文件格式: jsonl / yaml
+        #[clap(short, long, default_value = "jsonl")]
+        format: DumpFormat,
+    },
+    /// 从服务器上的文件导入 key-value
+    Import {
+        /// 相对于服务器 files.dump_dir 的路径
+        path: String,
+        /// 文件格式: jsonl / yaml
+        #[clap(short, long, default_value = "jsonl")]
+        format: DumpFormat,
+        /// 覆盖已存在的 key，默认跳过
+        #[clap(long)]
+        overwrite: bool,
+    },
+    /// 查看存储统计信息
+    Info,
+    /// 整理磁盘数据，回收已删除记录占用的空间
+    Compact,
+    /// 把已写入的数据持久化到磁盘
+    Flush,
+    /// 调整限流
+    RateLimit {
+        /// user:<name>、ip:<addr>，user:*、ip:* 表示默认限制
+        target: String,
+        /// 每秒命令数，不指定表示不限制
+        #[clap(long)]
+        commands: Option<f64>,
+        /// 每秒请求字节数，不指定表示不限制
+        #[clap(long)]
+        bytes: Option<f64>,
+    },
+    /// 查看最近的慢命令
+    SlowLogGet {
+        /// 返回的条数，0 表示全部
+        #[clap(default_value = "10")]
+        count: u32,
+    },
+    /// 慢日志中的记录数
+    SlowLogLen,
+    /// 清空慢日志
+    SlowLogReset,
+    /// 在线备份到服务器上的目录
+    Backup {
+        /// 相对于服务器 files.backup_dir 的目录
+        path: String,
+        /// 只复制上次备份之后变化的文件，只有 bitcask 后端支持
+        #[clap(long)]
+        incremental: bool,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<(), HikvError> {
     tracing_subscriber::fmt::init();
+    let args = Args::parse();
 
-    let addr = "127.0.0.1:9527";
     // 连接服务器
-    let stream = TcpStream::connect(addr).await?;
+    let stream = TcpStream::connect(&args.addr).await?;
 
     let mut client = ProstClientStream::new(stream);
 
-    // 生成一个 HSET 命令
-    let cmd = CommandRequest::new_set("hello", "world".into());
+    let auth = match (args.user, args.password, args.token) {
+        (Some(user), Some(password), _) => Some(CommandRequest::new_auth(user, password)),
+        (_, _, Some(token)) => Some(CommandRequest::new_auth_token(token)),
+        _ => None,
+    };
+    if let Some(auth) = auth {
+        let ret = client.execute(auth).await?;
+        if ret.status != 200 {
+            return Err(HikvError::Unauthenticated(ret.message));
+        }
+    }
+
+    let cmd = match args.command {
+        Some(Command::Ping) => CommandRequest::new_ping(),
+        Some(Command::RateLimit {
+            target,
+            commands,
+            bytes,
+        }) => CommandRequest::new_set_rate_limit(target, commands, bytes),
+        Some(Command::Export { path, format }) => CommandRequest::new_export(path, format),
+        Some(Command::Import {
+            path,
+            format,
+            overwrite,
+        }) => CommandRequest::new_import(path, format, overwrite),
+        Some(Command::Info) => CommandRequest::new_info(),
+        Some(Command::Compact) => CommandRequest::new_compact(),
+        Some(Command::Flush) => CommandRequest::new_flush(),
+        Some(Command::SlowLogGet { count }) => CommandRequest::new_slow_log_get(count),
+        Some(Command::SlowLogLen) => CommandRequest::new_slow_log_len(),
+        Some(Command::SlowLogReset) => CommandRequest::new_slow_log_reset(),
+        Some(Command::Backup { path, incremental }) => {
+            CommandRequest::new_backup(path, incremental)
+        }
+        // 生成一个 HSET 命令
+        None => CommandRequest::new_set("hello", "world".into()),
+    };
 
-    // 发送 HSET 命令
+    // 发送命令
     let data = client.execute(cmd).await?;
     info!("Got response {:?}", data);
 
@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use hikv::{migrate, AnyStorage, CompressOptions, HikvError, MigrateOptions, StorageSpec};
+use tracing::info;
+
+/// 在不同存储后端之间迁移数据，存储格式为 `<kind>:<path>`，
+/// kind 可选 memory / sled / rocksdb / bitcask
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// 源存储，如 sled:/data/hikv
+    #[clap(long)]
+    from: StorageSpec,
+    /// 目标存储，如 rocksdb:/data/hikv-rocks
+    #[clap(long)]
+    to: StorageSpec,
+    /// 每批写入的 key 数量
+    #[clap(long, default_value_t = 1000)]
+    batch_size: usize,
+    /// 断点文件路径，默认为 `<目标路径>.checkpoint`
+    #[clap(long)]
+    checkpoint: Option<PathBuf>,
+    /// 目标存储中超过该字节数的 value 用 gzip 压缩，默认不压缩
+    #[clap(long)]
+    compress_threshold: Option<usize>,
+}
+
+fn main() -> Result<(), HikvError> {
+    tracing_subscriber::fmt::init();
+    let args = Args::parse();
+
+    let checkpoint = args
+        .checkpoint
+        .unwrap_or_else(|| PathBuf::from(format!("{}.checkpoint", args.to.path)));
+    let opts = MigrateOptions {
+        batch_size: args.batch_size.max(1),
+        checkpoint,
+    };
+
+    let src = AnyStorage::open(&args.from)?;
+    let compression = match args.compress_threshold {
+        Some(threshold) => CompressOptions {
+            threshold,
+            ..CompressOptions::gzip()
+        },
+        None => CompressOptions::disabled(),
+    };
+    let dst = AnyStorage::open_with(&args.to, compression)?;
+    let report = migrate(&src, &dst, &opts)?;
+
+    if let Some(key) = &report.resumed_from {
+        info!("Resumed after key {:?}", key);
+    }
+    info!(
+        "Copied {} keys, verified {} keys (checksum {:#x})",
+        report.copied, report.target.count, report.target.checksum
+    );
+    Ok(())
+}
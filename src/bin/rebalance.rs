@@ -0,0 +1,42 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use hikv::{rebalance, AnyStorage, HikvError, StorageKind, StorageSpec};
+use tracing::info;
+
+/// 离线调整分片数量，需要先停止服务
+///
+/// 例如从 2 个分片扩容到 3 个:
+/// `hikv-rebalance --kind sled --dir /nvme0/hikv --dir /nvme1/hikv --dir /nvme2/hikv --shards 3`
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// 分片的存储后端: memory / sled / rocksdb / bitcask
+    #[clap(long)]
+    kind: String,
+    /// 分片目录，按分片顺序给出调整前后用到的全部目录
+    #[clap(long = "dir", required = true)]
+    dirs: Vec<PathBuf>,
+    /// 调整后的分片数量
+    #[clap(long)]
+    shards: usize,
+}
+
+fn main() -> Result<(), HikvError> {
+    tracing_subscriber::fmt::init();
+    let args = Args::parse();
+
+    let kind: StorageKind = format!("{}:", args.kind).parse::<StorageSpec>()?.kind;
+    let report = rebalance(&args.dirs, args.shards, |path| {
+        AnyStorage::open(&StorageSpec {
+            kind,
+            path: path.to_string_lossy().into(),
+        })
+    })?;
+
+    info!(
+        "Moved {} keys, verified {} keys (checksum {:#x})",
+        report.moved, report.digest.count, report.digest.checksum
+    );
+    Ok(())
+}
@@ -1,18 +1,100 @@
-use hikv::{HikvError, MemTable, ProstServerStream, Service, ServiceInner};
+use std::{path::PathBuf, time::Duration};
+
+use clap::Parser;
+use hikv::{
+    hash_password, restore, AuditLog, AuthLayer, HikvError, MemTable, ProstServerStream,
+    RateLimiter, ServerConfig, Service, ServiceInner,
+};
 use tokio::net::TcpListener;
-use tracing::info;
+use tracing::{info, warn};
+
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// 配置文件路径(yaml)
+    #[clap(short, long)]
+    config: Option<PathBuf>,
+    /// 启动前从备份目录恢复数据，存储必须为空
+    #[clap(long)]
+    restore_from: Option<PathBuf>,
+    /// 输出密码哈希后退出，用于配置文件中的 auth.users[].password
+    #[clap(long)]
+    hash_password: Option<String>,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), HikvError> {
     tracing_subscriber::fmt::init();
-    let addr = "127.0.0.1:9527";
-    let service: Service = ServiceInner::new(MemTable::new()).into();
+    let args = Args::parse();
+    if let Some(password) = &args.hash_password {
+        println!("{}", hash_password(password));
+        return Ok(());
+    }
+    let config = match args.config {
+        Some(path) => ServerConfig::load(path)?,
+        None => ServerConfig::default(),
+    };
+
+    let mut store = match &config.storage.snapshot {
+        Some(path) => MemTable::with_snapshot(path)?,
+        None => MemTable::new(),
+    };
+    if let Some(options) = config.storage.compression {
+        store = store.compression(options);
+    }
+    if let Some(max) = config.storage.max_memory {
+        store = store.max_memory(max, config.storage.eviction);
+    }
+    if let Some(dir) = &args.restore_from {
+        restore(dir, &store)?;
+    }
+    let mut inner = ServiceInner::new(store);
+    if let Some(secs) = config.storage.max_snapshot_timeout_secs {
+        inner = inner.max_snapshot_timeout(Duration::from_secs(secs));
+    }
+    if let Some(auth) = config.auth {
+        info!("Authentication enabled for {} users", auth.users.len());
+        inner = inner.layer(AuthLayer::new(auth));
+    }
+    if let Some(limits) = config.rate_limit {
+        info!("Rate limiting enabled");
+        inner = inner.rate_limiter(RateLimiter::new(limits));
+    }
+    if let Some(audit) = config.audit {
+        inner = inner.layer(AuditLog::new(audit)?);
+    }
+    let service: Service = inner
+        .admission(config.admission)
+        .slow_log(config.slow_log)
+        .files(config.files)
+        .into();
+
+    let addr = &config.addr;
     let listener = TcpListener::bind(addr).await?;
     info!("Start listening on {}", addr);
     loop {
-        let (stream, addr) = listener.accept().await?;
-        info!("Client {:?} connected", addr);
-        let stream = ProstServerStream::new(stream, service.clone());
-        tokio::spawn(async move { stream.process().await });
+        tokio::select! {
+            ret = listener.accept() => {
+                // accept 失败(如文件描述符耗尽)时稍等后继续，不能让整个服务退出
+                let (stream, addr) = match ret {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Failed to accept connection: {}", e);
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                };
+                info!("Client {:?} connected", addr);
+                let stream = ProstServerStream::new(stream, service.clone()).peer(addr);
+                tokio::spawn(async move { stream.process().await });
+            }
+            _ = tokio::signal::ctrl_c() => {
+                info!("Shutting down");
+                break;
+            }
+        }
     }
+    service.shutdown()
 }
@@ -0,0 +1,297 @@
+use std::{collections::HashMap, fs, path::Path, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{CompressOptions, EvictionPolicy, HikvError};
+
+/// 服务端配置
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// 监听地址
+    pub addr: String,
+    pub storage: StorageConfig,
+    /// 认证和 ACL，None 表示不需要认证
+    pub auth: Option<AuthConfig>,
+    /// 限流，None 表示不限流
+    pub rate_limit: Option<RateLimitConfig>,
+    /// 连接数和并发限制
+    pub admission: AdmissionConfig,
+    /// 慢日志
+    pub slow_log: SlowLogConfig,
+    /// 写命令的审计日志，None 表示不记录
+    pub audit: Option<AuditConfig>,
+    /// 客户端命令可以读写的服务器目录
+    pub files: FilesConfig,
+}
+
+/// 存储配置
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// MemTable 快照文件，启动时从中恢复数据，Save/BgSave 写入该文件
+    pub snapshot: Option<PathBuf>,
+    /// MemTable 内存上限(字节)，None 表示不限制
+    pub max_memory: Option<usize>,
+    /// 超过内存上限时的淘汰策略
+    pub eviction: EvictionPolicy,
+    /// MemTable 的 value 压缩，None 表示不压缩；
+    /// 服务器只使用 MemTable，其他后端通过 `AnyStorage::open_with` 或 hikv-migrate 的 --compress-threshold 配置
+    pub compression: Option<CompressOptions>,
+    /// Snapshot 命令的超时上限(秒)，None 表示使用默认上限 600 秒
+    pub max_snapshot_timeout_secs: Option<u64>,
+}
+
+/// 认证配置，启用后连接需要先 Auth 才能执行 Ping 以外的命令
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub users: Vec<UserConfig>,
+}
+
+/// 用户，可以用密码或静态 token 认证
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserConfig {
+    pub name: String,
+    /// 密码哈希 `pbkdf2-sha256:<rounds>:<salt>:<hash>`，用 `hikv-server --hash-password` 生成
+    pub password: Option<String>,
+    /// 静态 token
+    pub token: Option<String>,
+    /// 按顺序匹配，第一条匹配的规则生效，没有规则匹配时拒绝
+    pub acl: Vec<AclRule>,
+}
+
+/// ACL 规则
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AclRule {
+    pub action: AclAction,
+    /// 适用的命令名(get、set、del 等)，为空或包含 "*" 时适用于所有命令
+    #[serde(default)]
+    pub commands: Vec<String>,
+    /// 适用的 key 前缀，例如 "user:" 这样的表前缀；为空时也适用于不针对 key 的命令。
+    /// 用户有适用于某命令的前缀 Deny 规则时，export 这类不针对 key 的命令一律拒绝；
+    /// import 这类不针对 key 的写命令只有 commands 中明确列出时才放行
+    #[serde(default)]
+    pub prefix: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AclAction {
+    Allow,
+    Deny,
+}
+
+/// 按 IP 和认证用户的限流配置，两者同时生效
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// 每个 IP 的默认限制
+    pub ip: RateLimit,
+    /// 每个用户的默认限制
+    pub user: RateLimit,
+    /// 单独配置的 IP，覆盖默认限制
+    pub ips: HashMap<String, RateLimit>,
+    /// 单独配置的用户，覆盖默认限制
+    pub users: HashMap<String, RateLimit>,
+}
+
+/// 令牌桶限制，桶容量为一秒的量
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    /// 每秒命令数，None 表示不限制
+    pub commands_per_sec: Option<f64>,
+    /// 每秒请求字节数，None 表示不限制
+    pub bytes_per_sec: Option<f64>,
+}
+
+/// 连接数和并发限制
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdmissionConfig {
+    /// 最大连接数，超过时新连接收到 503 后被关闭，None 表示不限制
+    pub max_connections: Option<usize>,
+    /// 每个连接已读取但还没回复的请求数上限，达到后暂停读取该连接
+    pub max_in_flight: usize,
+    /// 同时执行的存储操作数上限，None 表示不限制
+    pub max_storage_ops: Option<usize>,
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            max_in_flight: 8,
+            max_storage_ops: None,
+        }
+    }
+}
+
+/// 慢日志配置
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SlowLogConfig {
+    /// 执行时间超过该值(微秒)的命令记入慢日志
+    pub threshold_us: u64,
+    /// 最多保留的记录数，超过时丢弃最旧的记录
+    pub max_len: usize,
+}
+
+impl Default for SlowLogConfig {
+    fn default() -> Self {
+        Self {
+            threshold_us: 10_000,
+            max_len: 128,
+        }
+    }
+}
+
+/// 客户端命令中的路径都相对于这里的目录解析，不能是绝对路径或包含 `..`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FilesConfig {
+    /// Export/Import 的目录，None 表示禁用这两个命令
+    pub dump_dir: Option<PathBuf>,
+    /// Backup 的目录，None 表示禁用 Backup 命令
+    pub backup_dir: Option<PathBuf>,
+}
+
+/// 审计日志配置，每个写命令追加一行 JSON
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// 日志目录，当前文件为 audit.jsonl，切换时重命名为 audit-<毫秒时间戳>.jsonl
+    pub dir: PathBuf,
+    /// 当前文件超过该大小(字节)时切换
+    pub max_file_bytes: u64,
+    /// 当前文件打开超过该时间(秒)时切换，None 表示不按时间切换
+    pub rotate_secs: Option<u64>,
+    /// 记录 value 内容，默认只记录类型和大小
+    pub include_values: bool,
+    /// 这些前缀的 key 不记录 value 内容
+    pub redact_prefixes: Vec<String>,
+    /// 等待写盘的记录数上限
+    pub queue_size: usize,
+    /// 队列满时的处理方式
+    pub overflow: AuditOverflow,
+}
+
+/// 审计日志队列满时的处理方式
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOverflow {
+    /// 丢弃新记录并计数，不阻塞请求
+    #[default]
+    Drop,
+    /// 等待队列有空位再返回，写命令的延迟受写盘速度影响
+    Block,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            dir: "audit".into(),
+            max_file_bytes: 64 * 1024 * 1024,
+            rotate_secs: Some(24 * 3600),
+            include_values: false,
+            redact_prefixes: Vec::new(),
+            queue_size: 10_000,
+            overflow: AuditOverflow::Drop,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1:9527".into(),
+            storage: StorageConfig::default(),
+            auth: None,
+            rate_limit: None,
+            admission: AdmissionConfig::default(),
+            slow_log: SlowLogConfig::default(),
+            audit: None,
+            files: FilesConfig::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// 从 yaml 文件加载配置
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, HikvError> {
+        let content = fs::read_to_string(path)?;
+        serde_yaml::from_str(&content).map_err(|e| HikvError::ConfigError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_work_load_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.yml");
+        let config = ServerConfig {
+            addr: "0.0.0.0:9527".into(),
+            storage: StorageConfig {
+                snapshot: Some("/tmp/hikv.snap".into()),
+                max_memory: Some(64 * 1024 * 1024),
+                eviction: EvictionPolicy::AllKeysLru,
+                compression: Some(CompressOptions::gzip()),
+                max_snapshot_timeout_secs: Some(300),
+            },
+            auth: Some(AuthConfig {
+                users: vec![UserConfig {
+                    name: "alice".into(),
+                    token: Some("secret".into()),
+                    acl: vec![AclRule {
+                        action: AclAction::Allow,
+                        commands: vec!["get".into()],
+                        prefix: "user:".into(),
+                    }],
+                    ..Default::default()
+                }],
+            }),
+            rate_limit: Some(RateLimitConfig {
+                ip: RateLimit {
+                    commands_per_sec: Some(1000.0),
+                    bytes_per_sec: None,
+                },
+                users: HashMap::from([(
+                    "alice".into(),
+                    RateLimit {
+                        commands_per_sec: Some(10.0),
+                        bytes_per_sec: Some(1024.0),
+                    },
+                )]),
+                ..Default::default()
+            }),
+            admission: AdmissionConfig {
+                max_connections: Some(1024),
+                max_in_flight: 16,
+                max_storage_ops: Some(64),
+            },
+            slow_log: SlowLogConfig {
+                threshold_us: 5_000,
+                max_len: 256,
+            },
+            audit: Some(AuditConfig {
+                dir: "/var/log/hikv".into(),
+                include_values: true,
+                redact_prefixes: vec!["secret:".into()],
+                overflow: AuditOverflow::Block,
+                ..Default::default()
+            }),
+            files: FilesConfig {
+                dump_dir: Some("/var/lib/hikv/dump".into()),
+                backup_dir: Some("/var/lib/hikv/backup".into()),
+            },
+        };
+        fs::write(&path, serde_yaml::to_string(&config).unwrap()).unwrap();
+
+        assert_eq!(ServerConfig::load(&path).unwrap(), config);
+    }
+}
@@ -0,0 +1,217 @@
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{DumpFormat, HikvError, Storage, Value};
+
+/// 导出文件中的一条记录: key + type + value
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Record {
+    key: String,
+    #[serde(flatten)]
+    value: Value,
+}
+
+/// 导入结果
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub imported: usize,
+    /// 已存在而被跳过的 key 数量
+    pub skipped: usize,
+}
+
+/// 把存储中的所有数据按 key 顺序导出，返回导出的 key 数量
+///
+/// JSON_LINES 每行一条记录，YAML 为一个记录列表
+pub fn export(
+    store: &impl Storage,
+    writer: impl Write,
+    format: DumpFormat,
+) -> Result<usize, HikvError> {
+    let mut writer = BufWriter::new(writer);
+    let mut count = 0;
+    match format {
+        DumpFormat::JsonLines => {
+            for pair in store.get_iter()? {
+                let pair = pair?;
+                let record = Record {
+                    key: pair.key,
+                    value: pair.value.unwrap_or_default(),
+                };
+                serde_json::to_writer(&mut writer, &record)
+                    .map_err(|e| HikvError::DumpError(e.to_string()))?;
+                writer.write_all(b"\n")?;
+                count += 1;
+            }
+        }
+        DumpFormat::Yaml => {
+            let records = store
+                .get_iter()?
+                .map(|pair| {
+                    pair.map(|p| Record {
+                        key: p.key,
+                        value: p.value.unwrap_or_default(),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            serde_yaml::to_writer(&mut writer, &records)
+                .map_err(|e| HikvError::DumpError(e.to_string()))?;
+            count = records.len();
+        }
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// 导入 export 生成的数据，overwrite 为 false 时跳过已存在的 key
+pub fn import(
+    store: &impl Storage,
+    reader: impl Read,
+    format: DumpFormat,
+    overwrite: bool,
+) -> Result<ImportReport, HikvError> {
+    import_with(store, reader, format, overwrite, |_| {})
+}
+
+/// 和 [`import`] 相同，每写入一个 key 调用一次 on_set，中途失败时已写入的 key 也已通知
+pub fn import_with(
+    store: &impl Storage,
+    reader: impl Read,
+    format: DumpFormat,
+    overwrite: bool,
+    mut on_set: impl FnMut(&str),
+) -> Result<ImportReport, HikvError> {
+    let records: Vec<Record> = match format {
+        DumpFormat::JsonLines => BufReader::new(reader)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !matches!(line, Ok(l) if l.trim().is_empty()))
+            .map(|(i, line)| {
+                serde_json::from_str(&line?)
+                    .map_err(|e| HikvError::DumpError(format!("line {}: {}", i + 1, e)))
+            })
+            .collect::<Result<_, _>>()?,
+        DumpFormat::Yaml => {
+            serde_yaml::from_reader(reader).map_err(|e| HikvError::DumpError(e.to_string()))?
+        }
+    };
+
+    let mut report = ImportReport::default();
+    for record in records {
+        if !overwrite && store.contains(&record.key)? {
+            report.skipped += 1;
+            continue;
+        }
+        store.set(record.key.as_str(), record.value)?;
+        on_set(&record.key);
+        report.imported += 1;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::MemTable;
+
+    fn sample() -> MemTable {
+        let store = MemTable::new();
+        store.set("hello", "world").unwrap();
+        store.set("age", Value::from(18)).unwrap();
+        store.set("ratio", Value::from(0.5)).unwrap();
+        store.set("ok", true).unwrap();
+        store.set("bin", Bytes::from(vec![0u8, 1, 2, 255])).unwrap();
+        store
+    }
+
+    #[test]
+    fn value_serde_should_keep_type() {
+        let record = Record {
+            key: "bin".into(),
+            value: Bytes::from_static(b"hello").into(),
+        };
+        let json = serde_json::to_string(&record).unwrap();
+        assert_eq!(json, r#"{"key":"bin","type":"binary","value":"aGVsbG8="}"#);
+        assert_eq!(serde_json::from_str::<Record>(&json).unwrap(), record);
+
+        let v: Value = serde_json::from_str(r#"{"type":"integer","value":42}"#).unwrap();
+        assert_eq!(v, 42.into());
+    }
+
+    #[test]
+    fn should_work_export_import_roundtrip() {
+        for format in [DumpFormat::JsonLines, DumpFormat::Yaml] {
+            let store = sample();
+            let mut buf = Vec::new();
+            assert_eq!(export(&store, &mut buf, format).unwrap(), 5);
+
+            let restored = MemTable::new();
+            let report = import(&restored, &buf[..], format, false).unwrap();
+            assert_eq!(report.imported, 5);
+            for pair in store.get_iter().unwrap() {
+                let pair = pair.unwrap();
+                assert_eq!(restored.get(&pair.key).unwrap(), pair.value);
+            }
+        }
+    }
+
+    #[test]
+    fn non_finite_floats_should_roundtrip() {
+        for f in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let store = MemTable::new();
+            store.set("f", Value::from(f)).unwrap();
+            for format in [DumpFormat::JsonLines, DumpFormat::Yaml] {
+                let mut buf = Vec::new();
+                export(&store, &mut buf, format).unwrap();
+
+                let restored = MemTable::new();
+                import(&restored, &buf[..], format, false).unwrap();
+                let v: f64 = restored.get("f").unwrap().unwrap().try_into().unwrap();
+                assert_eq!(v.to_string(), f.to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn import_should_skip_or_overwrite_existing_keys() {
+        let data = "{\"key\":\"hello\",\"type\":\"string\",\"value\":\"new\"}\n\
+                    {\"key\":\"lang\",\"type\":\"string\",\"value\":\"rust\"}\n";
+        let store = MemTable::new();
+        store.set("hello", "world").unwrap();
+
+        let report = import(&store, data.as_bytes(), DumpFormat::JsonLines, false).unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                imported: 1,
+                skipped: 1
+            }
+        );
+        assert_eq!(store.get("hello").unwrap(), Some("world".into()));
+
+        let report = import(&store, data.as_bytes(), DumpFormat::JsonLines, true).unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                imported: 2,
+                skipped: 0
+            }
+        );
+        assert_eq!(store.get("hello").unwrap(), Some("new".into()));
+    }
+
+    #[test]
+    fn import_should_report_bad_line() {
+        let data = "{\"key\":\"a\",\"type\":\"bool\",\"value\":true}\nnot json\n";
+        let err = import(
+            &MemTable::new(),
+            data.as_bytes(),
+            DumpFormat::JsonLines,
+            true,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+    }
+}
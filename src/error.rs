@@ -13,10 +13,10 @@ pub enum HikvError {
     #[error("Cannot parse command: {0}")]
     InvalidCommand(String),
 
-    #[error("Cannot convert value {:?} to {1}")]
+    #[error("Cannot convert value {0:?} to {1}")]
     ConvertError(Value, &'static str),
 
-    #[error("Cannot process command {0} with table: {1}, key: {2}. Error: {}")]
+    #[error("Cannot process command {0} with table: {1}, key: {2}. Error: {3}")]
     StorageError(&'static str, String, String, String),
 
     #[error("Failed to encode protobuf message")]
@@ -25,14 +25,64 @@ pub enum HikvError {
     #[error("Failed to decode protobuf message")]
     DecodeError(#[from] prost::DecodeError),
 
+    #[cfg(feature = "sled")]
     #[error("Failed to access sled db")]
     SledError(#[from] sled::Error),
     #[error("I/O error")]
     IoError(#[from] std::io::Error),
 
+    #[cfg(feature = "rocksdb")]
     #[error("Failed to access rocksdb db")]
     RocksError(#[from] rocksdb::Error),
 
+    #[error("Bitcask error: {0}")]
+    BitcaskError(String),
+
     #[error("frame error")]
     FrameError,
+
+    #[error("Snapshot error: {0}")]
+    SnapshotError(String),
+
+    #[error("Snapshot {0} not found or expired")]
+    SnapshotNotFound(u64),
+
+    #[error("Command {0} is not supported by this storage")]
+    Unsupported(&'static str),
+
+    #[error("Out of memory: used {0} bytes, max {1} bytes")]
+    OutOfMemory(usize, usize),
+
+    #[error("Version mismatch for key {0}: expected {1}, actual {2}")]
+    VersionMismatch(String, u64, u64),
+
+    #[error("Codec error: {0}")]
+    CodecError(String),
+
+    #[error("Failed to parse dump: {0}")]
+    DumpError(String),
+
+    #[error("Failed to load config: {0}")]
+    ConfigError(String),
+
+    #[error("Backup error: {0}")]
+    BackupError(String),
+
+    #[error("Encryption error: {0}")]
+    EncryptionError(String),
+
+    #[error("Shard error: {0}")]
+    ShardError(String),
+
+    #[error("Unauthenticated: {0}")]
+    Unauthenticated(String),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("Rate limit exceeded, retry after {0} ms")]
+    RateLimited(u64),
+
+    #[error("Too many connections, max {0}")]
+    TooManyConnections(usize),
 }
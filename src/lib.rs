@@ -1,14 +1,38 @@
+//! 关闭 `server` feature 时只编译客户端需要的协议、网络和错误类型
+
+#[cfg(feature = "server")]
 mod ae;
+#[cfg(feature = "server")]
+mod backup;
+#[cfg(feature = "server")]
+mod config;
+#[cfg(feature = "server")]
+mod dump;
 mod error;
+#[cfg(feature = "server")]
+mod migrate;
 mod net;
 mod pb;
+#[cfg(feature = "server")]
 mod store;
+#[cfg(all(feature = "server", any(test, feature = "testkit")))]
+pub mod testkit;
 
+#[cfg(feature = "server")]
 pub use ae::*;
+#[cfg(feature = "server")]
+pub use backup::*;
+#[cfg(feature = "server")]
+pub use config::*;
+#[cfg(feature = "server")]
+pub use dump::*;
 pub use error::*;
+#[cfg(feature = "server")]
+pub use migrate::*;
 pub use net::*;
 pub use pb::abi::*;
 pub use pb::*;
+#[cfg(feature = "server")]
 pub use store::*;
 
 #[cfg(test)]
@@ -0,0 +1,266 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{HikvError, Kvpair, Storage};
+
+/// 迁移配置
+#[derive(Clone, Debug)]
+pub struct MigrateOptions {
+    /// 每批写入的 key 数量
+    pub batch_size: usize,
+    /// 断点文件，中断后从这里记录的位置继续
+    pub checkpoint: PathBuf,
+}
+
+/// key-value 集合的摘要: 数量 + 与顺序无关的校验和
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Digest {
+    pub count: u64,
+    pub checksum: u64,
+}
+
+/// 迁移结果
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MigrateReport {
+    /// 本次运行复制的 key 数量
+    pub copied: u64,
+    /// 从断点继续时，上次复制到的 key
+    pub resumed_from: Option<String>,
+    pub source: Digest,
+    pub target: Digest,
+}
+
+/// 断点: 已复制到的最后一个 key 以及已复制数据的摘要
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Checkpoint {
+    last_key: Option<String>,
+    digest: Digest,
+}
+
+impl Digest {
+    pub fn update(&mut self, pair: &Kvpair) -> Result<(), HikvError> {
+        let value: Vec<u8> = pair.value.clone().unwrap_or_default().try_into()?;
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&(pair.key.len() as u32).to_be_bytes());
+        hasher.update(pair.key.as_bytes());
+        hasher.update(&value);
+        self.count += 1;
+        self.checksum = self.checksum.wrapping_add(hasher.finalize() as u64);
+        Ok(())
+    }
+
+    /// 计算存储中所有数据的摘要
+    pub fn of(store: &impl Storage) -> Result<Self, HikvError> {
+        let mut digest = Self::default();
+        for pair in store.get_iter()? {
+            digest.update(&pair?)?;
+        }
+        Ok(digest)
+    }
+}
+
+impl Checkpoint {
+    fn load(path: &Path) -> Result<Self, HikvError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)?;
+        serde_yaml::from_str(&content).map_err(|e| HikvError::Internal(e.to_string()))
+    }
+
+    fn store(&self, path: &Path) -> Result<(), HikvError> {
+        let content =
+            serde_yaml::to_string(self).map_err(|e| HikvError::Internal(e.to_string()))?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// 把 src 中的所有数据分批复制到 dst，结束后校验数量和校验和
+///
+/// 每批写入并 flush 后才更新断点，中断后再次运行会跳过已复制的 key。
+/// 目标为 MemTable 时每次 flush 都会重写整个快照，没有快照文件的 MemTable 中断后无法继续
+pub fn migrate(
+    src: &impl Storage,
+    dst: &impl Storage,
+    opts: &MigrateOptions,
+) -> Result<MigrateReport, HikvError> {
+    let mut checkpoint = Checkpoint::load(&opts.checkpoint)?;
+    let resumed_from = checkpoint.last_key.clone();
+    if let Some(key) = &resumed_from {
+        info!("Resume migration after key {:?}", key);
+    }
+
+    let mut copied = 0;
+    let mut batch = Vec::with_capacity(opts.batch_size);
+    for pair in src.get_iter()? {
+        let pair = pair?;
+        // get_iter 按 key 升序返回，断点之前的 key 都已复制
+        if matches!(&resumed_from, Some(last) if pair.key <= *last) {
+            continue;
+        }
+        batch.push(pair);
+        if batch.len() >= opts.batch_size {
+            copied += write_batch(dst, &mut batch, &mut checkpoint, &opts.checkpoint)?;
+        }
+    }
+    copied += write_batch(dst, &mut batch, &mut checkpoint, &opts.checkpoint)?;
+
+    let target = Digest::of(dst)?;
+    if target != checkpoint.digest {
+        return Err(HikvError::Internal(format!(
+            "migration verify failed: source {:?}, target {:?}",
+            checkpoint.digest, target
+        )));
+    }
+    fs::remove_file(&opts.checkpoint)?;
+    info!("Migrated {} keys, {:?}", copied, target);
+
+    Ok(MigrateReport {
+        copied,
+        resumed_from,
+        source: checkpoint.digest,
+        target,
+    })
+}
+
+fn write_batch(
+    dst: &impl Storage,
+    batch: &mut Vec<Kvpair>,
+    checkpoint: &mut Checkpoint,
+    path: &Path,
+) -> Result<u64, HikvError> {
+    if batch.is_empty() {
+        // 没有数据时也要留下断点文件，保证结束时可以统一删除
+        return checkpoint.store(path).map(|_| 0);
+    }
+
+    let pairs = std::mem::take(batch);
+    for pair in &pairs {
+        checkpoint.digest.update(pair)?;
+    }
+    checkpoint.last_key = pairs.last().map(|p| p.key.clone());
+    let n = pairs.len() as u64;
+
+    dst.set_all(pairs)?;
+    // 断点不能超过已落盘的数据，否则中断后继续会跳过丢失的 key
+    dst.flush()?;
+    checkpoint.store(path)?;
+    info!(
+        "Copied {} keys, last key {:?}",
+        checkpoint.digest.count, checkpoint.last_key
+    );
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    #[cfg(feature = "sled")]
+    use crate::{Bitcask, SledDb};
+    use crate::{Fault, FaultKind, FaultRule, FaultyStorage, MemTable, StorageOp, Value};
+
+    fn options(dir: &Path) -> MigrateOptions {
+        MigrateOptions {
+            batch_size: 3,
+            checkpoint: dir.join("migrate.checkpoint"),
+        }
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn should_work_migrate_sled_to_bitcask() {
+        let dir = tempdir().unwrap();
+        let src = SledDb::new(dir.path().join("sled"));
+        for i in 0..10i64 {
+            src.set(format!("key{}", i), Value::from(i)).unwrap();
+        }
+        let dst = Bitcask::new(dir.path().join("bitcask"));
+
+        let opts = options(dir.path());
+        let report = migrate(&src, &dst, &opts).unwrap();
+        assert_eq!(report.copied, 10);
+        assert_eq!(report.source, Digest::of(&src).unwrap());
+        assert_eq!(dst.get("key7").unwrap(), Some(7.into()));
+        assert!(!opts.checkpoint.exists());
+    }
+
+    #[test]
+    fn should_resume_migrate_from_checkpoint() {
+        let dir = tempdir().unwrap();
+        let src = MemTable::new();
+        for i in 0..10i64 {
+            src.set(format!("key{}", i), Value::from(i)).unwrap();
+        }
+        let dst = MemTable::new();
+
+        // 模拟上次运行复制了前 4 个 key 后中断
+        let opts = options(dir.path());
+        let mut checkpoint = Checkpoint::default();
+        let copied: Vec<Kvpair> = src
+            .get_iter()
+            .unwrap()
+            .take(4)
+            .map(|p| p.unwrap())
+            .collect();
+        for pair in &copied {
+            checkpoint.digest.update(pair).unwrap();
+        }
+        checkpoint.last_key = Some(copied[3].key.clone());
+        checkpoint.store(&opts.checkpoint).unwrap();
+        dst.set_all(copied).unwrap();
+
+        let report = migrate(&src, &dst, &opts).unwrap();
+        assert_eq!(report.copied, 6);
+        assert_eq!(report.resumed_from, Some("key3".into()));
+        assert_eq!(report.target, Digest::of(&src).unwrap());
+    }
+
+    #[test]
+    fn should_resume_after_target_dropped_without_flush() {
+        let dir = tempdir().unwrap();
+        let src = MemTable::new();
+        for i in 0..10i64 {
+            src.set(format!("key{}", i), Value::from(i)).unwrap();
+        }
+        let snapshot = dir.path().join("dst.snap");
+        let opts = options(dir.path());
+
+        // 第三批写入失败，之前的批次已经落盘
+        let dst = FaultyStorage::new(MemTable::with_snapshot(&snapshot).unwrap(), 1).rule(
+            FaultRule::new(Fault::Error(FaultKind::Io))
+                .op(StorageOp::SetAll)
+                .calls([3]),
+        );
+        assert!(migrate(&src, &dst, &opts).is_err());
+        drop(dst);
+
+        let dst = MemTable::with_snapshot(&snapshot).unwrap();
+        let report = migrate(&src, &dst, &opts).unwrap();
+        assert_eq!(report.resumed_from, Some("key5".into()));
+        assert_eq!(report.copied, 4);
+        assert_eq!(report.target, Digest::of(&src).unwrap());
+    }
+
+    #[test]
+    fn migrate_should_fail_on_mismatch() {
+        let dir = tempdir().unwrap();
+        let src = MemTable::new();
+        src.set("hello", "world").unwrap();
+        let dst = MemTable::new();
+        dst.set("extra", "value").unwrap();
+
+        let opts = options(dir.path());
+        let err = migrate(&src, &dst, &opts).unwrap_err();
+        assert!(err.to_string().contains("verify failed"));
+    }
+}
@@ -1,53 +1,160 @@
 mod frame;
 mod tls;
 
+#[cfg(feature = "server")]
+use std::net::SocketAddr;
+
 use bytes::BytesMut;
 pub use frame::*;
 pub use tls::*;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+#[cfg(feature = "server")]
+use tokio::sync::{mpsc, Semaphore};
+#[cfg(feature = "server")]
+use tracing::{debug, warn};
 
-use crate::{CommandRequest, CommandResponse, HikvError, Service};
+use crate::{CommandRequest, CommandResponse, HikvError};
+#[cfg(feature = "server")]
+use crate::{ConnInfo, Context, MemTable, Service, Storage};
 
-pub struct ProstServerStream<S> {
+#[cfg(feature = "server")]
+pub struct ProstServerStream<S, Store = MemTable> {
     inner: S,
-    service: Service,
+    service: Service<Store>,
+    conn: ConnInfo,
 }
 
 pub struct ProstClientStream<S> {
     inner: S,
 }
 
-impl<S> ProstServerStream<S>
+#[cfg(feature = "server")]
+impl<S, Store> ProstServerStream<S, Store>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send,
+    Store: Storage + Send + Sync,
 {
-    pub fn new(inner: S, service: Service) -> Self {
-        Self { inner, service }
+    pub fn new(inner: S, service: Service<Store>) -> Self {
+        Self {
+            inner,
+            service,
+            conn: ConnInfo::new(None),
+        }
     }
 
-    pub async fn process(mut self) -> Result<(), HikvError> {
-        while let Ok(cmd) = self.recv().await {
-            let ret = self.service.execute(cmd);
-            self.send(ret).await?;
+    /// 设置对端地址，回调可以从上下文中拿到
+    pub fn peer(mut self, addr: SocketAddr) -> Self {
+        self.conn.peer = Some(addr);
+        self
+    }
+
+    /// 处理连接上的请求
+    ///
+    /// 读取和执行并行: 读取最多 max_in_flight 个还没回复的请求后暂停读取，请求按顺序执行和回复
+    pub async fn process(self) -> Result<(), HikvError> {
+        let Self {
+            inner,
+            service,
+            mut conn,
+        } = self;
+        let admission = service.admission().clone();
+        let (mut reader, mut writer) = tokio::io::split(inner);
+
+        let _conn_guard = match admission.try_connect() {
+            Some(guard) => guard,
+            None => {
+                let max = admission.config().max_connections.unwrap_or_default();
+                warn!("Reject connection {:?}: too many connections", conn.peer);
+                send_response(&mut writer, HikvError::TooManyConnections(max).into()).await?;
+                return Err(HikvError::TooManyConnections(max));
+            }
+        };
+
+        let in_flight = Semaphore::new(admission.max_in_flight());
+        let (tx, mut rx) = mpsc::channel(admission.max_in_flight());
+
+        let read = async {
+            let tx = tx;
+            // 每个请求持有一个许可，直到回复发送完成
+            while let Ok(permit) = in_flight.acquire().await {
+                let (cmd, size) = match recv_request(&mut reader).await {
+                    Ok(ret) => ret,
+                    Err(_) => break,
+                };
+                let queued = admission.enqueue();
+                if tx.send((cmd, size, permit, queued)).await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        let execute = async {
+            while let Some((cmd, size, permit, queued)) = rx.recv().await {
+                drop(queued);
+                let mut ctx = Context::with_conn(conn.clone());
+                let limited = service
+                    .rate_limiter()
+                    .and_then(|limiter| limiter.check(ctx.conn(), size).err());
+                let ret = match limited {
+                    Some(wait) => {
+                        debug!("Connection {} rate limited for {:?}", ctx.conn().id, wait);
+                        // 向上取整，避免客户端按 0 ms 立即重试
+                        HikvError::RateLimited(wait.as_millis() as u64 + 1).into()
+                    }
+                    None => service.call(cmd, &mut ctx).await,
+                };
+                let sent = send_response(&mut writer, ret).await;
+                drop(permit);
+                service.notify_after_reply(&sent, &ctx);
+                // 认证等对连接的修改在后续请求中继续有效
+                conn = ctx.into_conn();
+                sent?;
+            }
+            Ok(())
+        };
+
+        tokio::pin!(read, execute);
+        tokio::select! {
+            ret = &mut execute => ret,
+            // 读取结束后执行完已读取的请求
+            _ = &mut read => execute.await,
         }
-        Ok(())
     }
 
     pub async fn recv(&mut self) -> Result<CommandRequest, HikvError> {
-        let mut buf = BytesMut::new();
-        read_frame(&mut self.inner, &mut buf).await?;
-        CommandRequest::decode_frame(&mut buf)
+        recv_request(&mut self.inner).await.map(|(cmd, _)| cmd)
     }
 
-    pub async fn send(&mut self, cmd: CommandResponse) -> Result<(), HikvError> {
-        let mut buf = BytesMut::new();
-        cmd.encode_frame(&mut buf).unwrap();
-        let encoded = buf.freeze();
-        self.inner.write_all(&encoded[..]).await?;
-        Ok(())
+    /// 发送 Response，返回写入的字节数
+    pub async fn send(&mut self, cmd: CommandResponse) -> Result<usize, HikvError> {
+        send_response(&mut self.inner, cmd).await
     }
 }
 
+/// 接收请求，同时返回 frame 的字节数
+#[cfg(feature = "server")]
+async fn recv_request<S>(stream: &mut S) -> Result<(CommandRequest, usize), HikvError>
+where
+    S: AsyncRead + Unpin + Send,
+{
+    let mut buf = BytesMut::new();
+    read_frame(stream, &mut buf).await?;
+    let size = buf.len();
+    CommandRequest::decode_frame(&mut buf).map(|cmd| (cmd, size))
+}
+
+#[cfg(feature = "server")]
+async fn send_response<S>(stream: &mut S, cmd: CommandResponse) -> Result<usize, HikvError>
+where
+    S: AsyncWrite + Unpin + Send,
+{
+    let mut buf = BytesMut::new();
+    cmd.encode_frame(&mut buf)?;
+    let encoded = buf.freeze();
+    stream.write_all(&encoded[..]).await?;
+    Ok(encoded.len())
+}
+
 impl<S> ProstClientStream<S>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send,
@@ -76,14 +183,21 @@ where
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "server"))]
 mod tests {
     use anyhow::Result;
     use bytes::Bytes;
-    use std::net::SocketAddr;
+    use std::{
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
     use tokio::net::{TcpListener, TcpStream};
 
-    use crate::{assert_ok, MemTable, ServiceInner, Value};
+    use crate::{
+        assert_err, assert_ok, AdmissionConfig, Context, Fault, FaultKind, FaultRule,
+        FaultyStorage, Kvpair, MemTable, RateLimit, RateLimitConfig, RateLimiter, ServiceInner,
+        StorageOp, Value,
+    };
 
     use super::*;
 
@@ -133,6 +247,163 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn storage_faults_should_reach_client_as_error_response() -> anyhow::Result<()> {
+        let store = FaultyStorage::new(MemTable::new(), 7)
+            .rule(
+                FaultRule::new(Fault::Error(FaultKind::DiskFull))
+                    .op(StorageOp::Set)
+                    .key("user:*"),
+            )
+            .rule(
+                FaultRule::new(Fault::Error(FaultKind::Internal(
+                    "storage unavailable".into(),
+                )))
+                .op(StorageOp::Get)
+                .calls([1]),
+            );
+        let service: Service<_> = ServiceInner::new(store).into();
+        let addr = start_server_with(service).await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+
+        let ret = client
+            .execute(CommandRequest::new_set("user:1", "tom".into()))
+            .await?;
+        assert_err(ret, 500, "I/O error");
+
+        // 出错后连接仍然可用
+        let ret = client
+            .execute(CommandRequest::new_set("order:1", "book".into()))
+            .await?;
+        assert_ok(ret, &[Value::default()]);
+
+        let ret = client.execute(CommandRequest::new_get("order:1")).await?;
+        assert_err(ret, 500, "storage unavailable");
+        let ret = client.execute(CommandRequest::new_get("order:1")).await?;
+        assert_ok(ret, &["book".into()]);
+        let ret = client.execute(CommandRequest::new_get("user:1")).await?;
+        assert_err(ret, 404, "Not Found");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn after_reply_hooks_should_get_send_outcome_and_connection() -> anyhow::Result<()> {
+        let replies = Arc::new(Mutex::new(Vec::new()));
+        let log = replies.clone();
+        let service: Service = ServiceInner::new(MemTable::new())
+            .fn_after_reply(move |sent: &Result<usize, HikvError>, ctx: &Context| {
+                let conn = ctx.conn();
+                log.lock()
+                    .unwrap()
+                    .push((conn.id, conn.peer, *sent.as_ref().unwrap()));
+            })
+            .into();
+        let addr = start_server_with(service).await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let local = stream.local_addr()?;
+        let mut client = ProstClientStream::new(stream);
+        client
+            .execute(CommandRequest::new_set("k1", "v1".into()))
+            .await?;
+        client.execute(CommandRequest::new_get("k1")).await?;
+        // 回调在回复写完后才执行，收到第三个回复时前两个回调一定已经执行
+        client.execute(CommandRequest::new_get("k1")).await?;
+
+        let replies = replies.lock().unwrap();
+        assert!(replies.len() >= 2);
+        let (id, peer, bytes) = replies[0];
+        assert!(id > 0);
+        assert_eq!(peer, Some(local));
+        assert!(bytes > 0);
+        assert_eq!(replies[1].0, id);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rate_limited_requests_should_get_429() -> anyhow::Result<()> {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            ip: RateLimit {
+                commands_per_sec: Some(1.0),
+                bytes_per_sec: None,
+            },
+            ..Default::default()
+        });
+        let service: Service = ServiceInner::new(MemTable::new())
+            .rate_limiter(limiter)
+            .into();
+        let addr = start_server_with(service).await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        let ret = client.execute(CommandRequest::new_ping()).await?;
+        assert_ok(ret, &["PONG".into()]);
+
+        // 连接不会断开，返回 429 和建议的重试时间
+        let ret = client.execute(CommandRequest::new_ping()).await?;
+        assert_eq!(ret.status, 429);
+        assert!(ret.message.contains("retry after"));
+        let retry_after: i64 = ret.values[0].clone().try_into()?;
+        assert!(retry_after > 0 && retry_after <= 1001);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connections_over_limit_should_get_503() -> anyhow::Result<()> {
+        let service: Service = ServiceInner::new(MemTable::new())
+            .admission(AdmissionConfig {
+                max_connections: Some(1),
+                ..Default::default()
+            })
+            .into();
+        let admission = service.admission().clone();
+        let addr = start_server_with(service).await?;
+
+        let stream = TcpStream::connect(addr).await?;
+        let mut client = ProstClientStream::new(stream);
+        let ret = client.execute(CommandRequest::new_ping()).await?;
+        assert_ok(ret, &["PONG".into()]);
+
+        // 超过连接数的连接直接收到错误 frame 后被关闭
+        let mut stream = TcpStream::connect(addr).await?;
+        let mut buf = BytesMut::new();
+        read_frame(&mut stream, &mut buf).await?;
+        let ret = CommandResponse::decode_frame(&mut buf)?;
+        assert_err(ret, 503, "Too many connections");
+        assert_eq!(admission.metrics().rejected_connections, 1);
+
+        // 第一个连接不受影响
+        let ret = client.execute(CommandRequest::new_info()).await?;
+        assert!(ret
+            .pairs
+            .contains(&Kvpair::new("server.connections", 1.into())));
+
+        Ok(())
+    }
+
+    async fn start_server_with<Store>(service: Service<Store>) -> Result<SocketAddr>
+    where
+        Store: Storage + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = listener.accept().await.unwrap();
+                let server = ProstServerStream::new(stream, service.clone()).peer(peer);
+                tokio::spawn(server.process());
+            }
+        });
+
+        Ok(addr)
+    }
+
     async fn start_server() -> Result<SocketAddr> {
         let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let addr = listener.local_addr().unwrap();
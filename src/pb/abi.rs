@@ -1,7 +1,10 @@
 /// input
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct CommandRequest {
-    #[prost(oneof = "command_request::Data", tags = "1, 2, 3, 4")]
+    #[prost(
+        oneof = "command_request::Data",
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21"
+    )]
     pub data: ::core::option::Option<command_request::Data>,
 }
 /// Nested message and enum types in `CommandRequest`.
@@ -16,6 +19,40 @@ pub mod command_request {
         Del(super::Del),
         #[prost(message, tag = "4")]
         Exist(super::Exist),
+        #[prost(message, tag = "5")]
+        Save(super::Save),
+        #[prost(message, tag = "6")]
+        BgSave(super::BgSave),
+        #[prost(message, tag = "7")]
+        Export(super::Export),
+        #[prost(message, tag = "8")]
+        Import(super::Import),
+        #[prost(message, tag = "9")]
+        Meta(super::Meta),
+        #[prost(message, tag = "10")]
+        Snapshot(super::Snapshot),
+        #[prost(message, tag = "11")]
+        ReleaseSnapshot(super::ReleaseSnapshot),
+        #[prost(message, tag = "12")]
+        Info(super::Info),
+        #[prost(message, tag = "13")]
+        Compact(super::Compact),
+        #[prost(message, tag = "14")]
+        Flush(super::Flush),
+        #[prost(message, tag = "15")]
+        Backup(super::Backup),
+        #[prost(message, tag = "16")]
+        Auth(super::Auth),
+        #[prost(message, tag = "17")]
+        Ping(super::Ping),
+        #[prost(message, tag = "18")]
+        SetRateLimit(super::SetRateLimit),
+        #[prost(message, tag = "19")]
+        SlowLogGet(super::SlowLogGet),
+        #[prost(message, tag = "20")]
+        SlowLogLen(super::SlowLogLen),
+        #[prost(message, tag = "21")]
+        SlowLogReset(super::SlowLogReset),
     }
 }
 /// output
@@ -30,20 +67,30 @@ pub struct CommandResponse {
     /// 成功返回 values
     #[prost(message, repeated, tag = "3")]
     pub values: ::prost::alloc::vec::Vec<Value>,
+    /// 成功返回 kv pairs
+    #[prost(message, repeated, tag = "4")]
+    pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
 }
-/// set key = value
+/// set key = value，指定 expected_version 时只有当前版本相同才写入(0 表示 key 不存在)
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Set {
     #[prost(string, tag = "1")]
     pub key: ::prost::alloc::string::String,
     #[prost(message, optional, tag = "2")]
     pub value: ::core::option::Option<Value>,
+    #[prost(uint64, optional, tag = "3")]
+    pub expected_version: ::core::option::Option<u64>,
 }
-/// get key
+/// get key，with_meta 为 true 时在 value 后返回 version、created_at、updated_at
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Get {
     #[prost(string, tag = "1")]
     pub key: ::prost::alloc::string::String,
+    #[prost(bool, tag = "2")]
+    pub with_meta: bool,
+    /// 非 0 时从指定快照读取
+    #[prost(uint64, tag = "3")]
+    pub snapshot_id: u64,
 }
 /// delete key
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
@@ -57,6 +104,111 @@ pub struct Exist {
     #[prost(string, tag = "1")]
     pub key: ::prost::alloc::string::String,
 }
+/// 获取 key 的元数据: version、created_at、updated_at(毫秒时间戳)
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Meta {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 创建一致性读快照，返回快照 id；timeout_ms 为 0 时使用默认超时
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Snapshot {
+    #[prost(uint64, tag = "1")]
+    pub timeout_ms: u64,
+}
+/// 释放快照
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct ReleaseSnapshot {
+    #[prost(uint64, tag = "1")]
+    pub id: u64,
+}
+/// 同步保存快照
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Save {}
+/// 后台保存快照
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct BgSave {}
+/// 存储统计信息，以 kv pairs 返回
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Info {}
+/// 整理磁盘数据，回收已删除记录占用的空间
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Compact {}
+/// 把已写入的数据持久化到磁盘
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Flush {}
+/// 在线备份到服务器上的目录，返回 key 数量和校验和
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Backup {
+    #[prost(string, tag = "1")]
+    pub path: ::prost::alloc::string::String,
+    /// 只复制上次备份之后变化的文件，后端不支持时完整备份
+    #[prost(bool, tag = "2")]
+    pub incremental: bool,
+}
+/// 认证当前连接，使用 username + password 或者静态 token
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Auth {
+    #[prost(string, tag = "1")]
+    pub username: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub password: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub token: ::prost::alloc::string::String,
+}
+/// 检查连接是否可用，返回 "PONG"
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Ping {}
+/// 运行时调整限流，target 为 "user:<name>"、"ip:<addr>"，"user:*"、"ip:*" 表示默认限制
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct SetRateLimit {
+    #[prost(string, tag = "1")]
+    pub target: ::prost::alloc::string::String,
+    /// 每秒命令数，不设置表示不限制
+    #[prost(double, optional, tag = "2")]
+    pub commands_per_sec: ::core::option::Option<f64>,
+    /// 每秒请求字节数，不设置表示不限制
+    #[prost(double, optional, tag = "3")]
+    pub bytes_per_sec: ::core::option::Option<f64>,
+}
+/// 获取最近的慢命令，count 为 0 时返回全部，每条记录以 JSON 字符串返回
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct SlowLogGet {
+    #[prost(uint32, tag = "1")]
+    pub count: u32,
+}
+/// 慢日志中的记录数
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct SlowLogLen {}
+/// 清空慢日志
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct SlowLogReset {}
+/// 导出所有 key-value 到服务器上的文件
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Export {
+    #[prost(string, tag = "1")]
+    pub path: ::prost::alloc::string::String,
+    #[prost(enumeration = "DumpFormat", tag = "2")]
+    pub format: i32,
+}
+/// 从服务器上的文件导入 key-value，overwrite 为 false 时跳过已存在的 key
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Import {
+    #[prost(string, tag = "1")]
+    pub path: ::prost::alloc::string::String,
+    #[prost(enumeration = "DumpFormat", tag = "2")]
+    pub format: i32,
+    #[prost(bool, tag = "3")]
+    pub overwrite: bool,
+}
+/// key-value pair
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Kvpair {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub value: ::core::option::Option<Value>,
+}
 /// value
 #[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Value {
@@ -79,3 +231,10 @@ pub mod value {
         Bool(bool),
     }
 }
+/// 导出/导入的文本格式
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum DumpFormat {
+    JsonLines = 0,
+    Yaml = 1,
+}
@@ -2,9 +2,11 @@ pub mod abi;
 
 use crate::HikvError;
 use abi::{command_request::Data, *};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use bytes::Bytes;
 use prost::Message;
-use std::convert::TryFrom;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{convert::TryFrom, str::FromStr};
 
 impl CommandRequest {
     pub fn new_set(key: impl Into<String>, value: Value) -> Self {
@@ -12,13 +14,67 @@ impl CommandRequest {
             data: Some(Data::Set(Set {
                 key: key.into(),
                 value: Some(value),
+                expected_version: None,
+            })),
+        }
+    }
+
+    /// 只有当前版本等于 version 时才写入
+    pub fn new_set_if_version(key: impl Into<String>, value: Value, version: u64) -> Self {
+        Self {
+            data: Some(Data::Set(Set {
+                key: key.into(),
+                value: Some(value),
+                expected_version: Some(version),
             })),
         }
     }
 
     pub fn new_get(key: impl Into<String>) -> Self {
         Self {
-            data: Some(Data::Get(Get { key: key.into() })),
+            data: Some(Data::Get(Get {
+                key: key.into(),
+                with_meta: false,
+                snapshot_id: 0,
+            })),
+        }
+    }
+
+    pub fn new_get_with_meta(key: impl Into<String>) -> Self {
+        Self {
+            data: Some(Data::Get(Get {
+                key: key.into(),
+                with_meta: true,
+                snapshot_id: 0,
+            })),
+        }
+    }
+
+    pub fn new_get_at(key: impl Into<String>, snapshot_id: u64) -> Self {
+        Self {
+            data: Some(Data::Get(Get {
+                key: key.into(),
+                with_meta: false,
+                snapshot_id,
+            })),
+        }
+    }
+
+    pub fn new_meta(key: impl Into<String>) -> Self {
+        Self {
+            data: Some(Data::Meta(Meta { key: key.into() })),
+        }
+    }
+
+    pub fn new_snapshot(timeout_ms: u64) -> Self {
+        Self {
+            data: Some(Data::Snapshot(Snapshot { timeout_ms })),
+        }
+    }
+
+    pub fn new_release_snapshot(id: u64) -> Self {
+        Self {
+            data: Some(Data::ReleaseSnapshot(ReleaseSnapshot { id })),
         }
     }
 
@@ -33,6 +89,180 @@ impl CommandRequest {
             data: Some(Data::Exist(Exist { key: key.into() })),
         }
     }
+
+    pub fn new_save() -> Self {
+        Self {
+            data: Some(Data::Save(Save {})),
+        }
+    }
+
+    pub fn new_bg_save() -> Self {
+        Self {
+            data: Some(Data::BgSave(BgSave {})),
+        }
+    }
+
+    pub fn new_info() -> Self {
+        Self {
+            data: Some(Data::Info(Info {})),
+        }
+    }
+
+    pub fn new_compact() -> Self {
+        Self {
+            data: Some(Data::Compact(Compact {})),
+        }
+    }
+
+    pub fn new_flush() -> Self {
+        Self {
+            data: Some(Data::Flush(Flush {})),
+        }
+    }
+
+    pub fn new_backup(path: impl Into<String>, incremental: bool) -> Self {
+        Self {
+            data: Some(Data::Backup(Backup {
+                path: path.into(),
+                incremental,
+            })),
+        }
+    }
+
+    pub fn new_auth(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            data: Some(Data::Auth(Auth {
+                username: username.into(),
+                password: password.into(),
+                token: String::new(),
+            })),
+        }
+    }
+
+    pub fn new_auth_token(token: impl Into<String>) -> Self {
+        Self {
+            data: Some(Data::Auth(Auth {
+                token: token.into(),
+                ..Default::default()
+            })),
+        }
+    }
+
+    pub fn new_ping() -> Self {
+        Self {
+            data: Some(Data::Ping(Ping {})),
+        }
+    }
+
+    pub fn new_set_rate_limit(
+        target: impl Into<String>,
+        commands_per_sec: Option<f64>,
+        bytes_per_sec: Option<f64>,
+    ) -> Self {
+        Self {
+            data: Some(Data::SetRateLimit(SetRateLimit {
+                target: target.into(),
+                commands_per_sec,
+                bytes_per_sec,
+            })),
+        }
+    }
+
+    pub fn new_slow_log_get(count: u32) -> Self {
+        Self {
+            data: Some(Data::SlowLogGet(SlowLogGet { count })),
+        }
+    }
+
+    pub fn new_slow_log_len() -> Self {
+        Self {
+            data: Some(Data::SlowLogLen(SlowLogLen {})),
+        }
+    }
+
+    pub fn new_slow_log_reset() -> Self {
+        Self {
+            data: Some(Data::SlowLogReset(SlowLogReset {})),
+        }
+    }
+
+    pub fn new_export(path: impl Into<String>, format: DumpFormat) -> Self {
+        Self {
+            data: Some(Data::Export(Export {
+                path: path.into(),
+                format: format as i32,
+            })),
+        }
+    }
+
+    pub fn new_import(path: impl Into<String>, format: DumpFormat, overwrite: bool) -> Self {
+        Self {
+            data: Some(Data::Import(Import {
+                path: path.into(),
+                format: format as i32,
+                overwrite,
+            })),
+        }
+    }
+}
+
+impl CommandRequest {
+    /// 命令名，用于 ACL、日志等
+    pub fn name(&self) -> &'static str {
+        match &self.data {
+            Some(Data::Set(_)) => "set",
+            Some(Data::Get(_)) => "get",
+            Some(Data::Del(_)) => "del",
+            Some(Data::Exist(_)) => "exist",
+            Some(Data::Save(_)) => "save",
+            Some(Data::BgSave(_)) => "bg_save",
+            Some(Data::Export(_)) => "export",
+            Some(Data::Import(_)) => "import",
+            Some(Data::Meta(_)) => "meta",
+            Some(Data::Snapshot(_)) => "snapshot",
+            Some(Data::ReleaseSnapshot(_)) => "release_snapshot",
+            Some(Data::Info(_)) => "info",
+            Some(Data::Compact(_)) => "compact",
+            Some(Data::Flush(_)) => "flush",
+            Some(Data::Backup(_)) => "backup",
+            Some(Data::Auth(_)) => "auth",
+            Some(Data::Ping(_)) => "ping",
+            Some(Data::SetRateLimit(_)) => "set_rate_limit",
+            Some(Data::SlowLogGet(_)) => "slow_log_get",
+            Some(Data::SlowLogLen(_)) => "slow_log_len",
+            Some(Data::SlowLogReset(_)) => "slow_log_reset",
+            None => "unknown",
+        }
+    }
+
+    /// 命令操作的 key，不针对单个 key 的命令返回 None
+    pub fn key(&self) -> Option<&str> {
+        match &self.data {
+            Some(Data::Set(v)) => Some(&v.key),
+            Some(Data::Get(v)) => Some(&v.key),
+            Some(Data::Del(v)) => Some(&v.key),
+            Some(Data::Exist(v)) => Some(&v.key),
+            Some(Data::Meta(v)) => Some(&v.key),
+            _ => None,
+        }
+    }
+
+    /// 是否修改数据，新增写命令时需要加到这里
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self.data,
+            Some(Data::Set(_) | Data::Del(_) | Data::Import(_))
+        )
+    }
+}
+
+impl Kvpair {
+    pub fn new(key: impl Into<String>, value: Value) -> Self {
+        Self {
+            key: key.into(),
+            value: Some(value),
+        }
+    }
 }
 
 impl From<HikvError> for CommandResponse {
@@ -41,10 +271,22 @@ impl From<HikvError> for CommandResponse {
             status: 500,
             message: err.to_string(),
             values: vec![],
+            pairs: vec![],
         };
         match err {
-            HikvError::NotFound(_) => ret.status = 404,
-            HikvError::InvalidCommand(_) => ret.status = 400,
+            HikvError::NotFound(_) | HikvError::SnapshotNotFound(_) => ret.status = 404,
+            HikvError::InvalidCommand(_) | HikvError::DumpError(_) => ret.status = 400,
+            HikvError::Unsupported(_) => ret.status = 501,
+            HikvError::OutOfMemory(..) => ret.status = 507,
+            HikvError::VersionMismatch(..) => ret.status = 412,
+            HikvError::Unauthenticated(_) => ret.status = 401,
+            HikvError::PermissionDenied(_) => ret.status = 403,
+            HikvError::TooManyConnections(_) => ret.status = 503,
+            HikvError::RateLimited(ms) => {
+                ret.status = 429;
+                // 建议的重试等待时间(毫秒)
+                ret.values = vec![Value::from(ms as i64)];
+            }
             _ => {}
         }
         ret
@@ -57,6 +299,7 @@ impl From<Value> for CommandResponse {
             status: 200,
             message: "".into(),
             values: vec![value],
+            pairs: vec![],
         }
     }
 }
@@ -67,6 +310,18 @@ impl From<Vec<Value>> for CommandResponse {
             status: 200,
             message: "".into(),
             values,
+            pairs: vec![],
+        }
+    }
+}
+
+impl From<Vec<Kvpair>> for CommandResponse {
+    fn from(pairs: Vec<Kvpair>) -> Self {
+        Self {
+            status: 200,
+            message: "".into(),
+            values: vec![],
+            pairs,
         }
     }
 }
@@ -103,6 +358,14 @@ impl From<i64> for Value {
     }
 }
 
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Self {
+            value: Some(value::Value::Float(f)),
+        }
+    }
+}
+
 impl TryFrom<Value> for i64 {
     type Error = HikvError;
 
@@ -178,3 +441,95 @@ impl From<Bytes> for Value {
         }
     }
 }
+
+impl FromStr for DumpFormat {
+    type Err = HikvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" | "json" => Ok(Self::JsonLines),
+            "yaml" | "yml" => Ok(Self::Yaml),
+            _ => Err(HikvError::InvalidCommand(format!(
+                "unknown dump format: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Value 的文本表示: `{"type": "...", "value": ...}`，binary 用 base64 编码
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+enum TaggedValue {
+    Null,
+    String(String),
+    Binary(String),
+    Integer(i64),
+    Float(Float),
+    Bool(bool),
+}
+
+/// JSON 不能表示 NaN 和无穷大，这些值写成字符串 "NaN"、"inf"、"-inf"
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Float {
+    Number(f64),
+    Special(String),
+}
+
+impl From<f64> for Float {
+    fn from(f: f64) -> Self {
+        if f.is_finite() {
+            Float::Number(f)
+        } else {
+            Float::Special(f.to_string())
+        }
+    }
+}
+
+impl TryFrom<Float> for f64 {
+    type Error = String;
+
+    fn try_from(f: Float) -> Result<Self, Self::Error> {
+        match f {
+            Float::Number(f) => Ok(f),
+            Float::Special(s) => match s.parse::<f64>() {
+                Ok(f) if !f.is_finite() => Ok(f),
+                _ => Err(format!("invalid float: {}", s)),
+            },
+        }
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let tagged = match &self.value {
+            None => TaggedValue::Null,
+            Some(value::Value::String(s)) => TaggedValue::String(s.clone()),
+            Some(value::Value::Binary(b)) => TaggedValue::Binary(BASE64.encode(b)),
+            Some(value::Value::Integer(i)) => TaggedValue::Integer(*i),
+            Some(value::Value::Float(f)) => TaggedValue::Float((*f).into()),
+            Some(value::Value::Bool(b)) => TaggedValue::Bool(*b),
+        };
+        tagged.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = match TaggedValue::deserialize(deserializer)? {
+            TaggedValue::Null => None,
+            TaggedValue::String(s) => Some(value::Value::String(s)),
+            TaggedValue::Binary(b) => {
+                let buf = BASE64.decode(b).map_err(de::Error::custom)?;
+                Some(value::Value::Binary(buf.into()))
+            }
+            TaggedValue::Integer(i) => Some(value::Value::Integer(i)),
+            TaggedValue::Float(f) => Some(value::Value::Float(
+                f.try_into().map_err(de::Error::custom)?,
+            )),
+            TaggedValue::Bool(b) => Some(value::Value::Bool(b)),
+        };
+        Ok(Self { value })
+    }
+}
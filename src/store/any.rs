@@ -0,0 +1,212 @@
+use std::{path::Path, str::FromStr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "rocksdb")]
+use crate::RocksDb;
+#[cfg(feature = "sled")]
+use crate::SledDb;
+use crate::{
+    BackupManifest, Bitcask, BitcaskOptions, CompressOptions, Compression, HikvError, KeyMeta,
+    Kvpair, MemTable, Storage, StorageIter, StorageStats, Value,
+};
+
+/// 运行时选择的存储后端
+pub enum AnyStorage {
+    Memory(Box<MemTable>),
+    #[cfg(feature = "sled")]
+    Sled(SledDb),
+    #[cfg(feature = "rocksdb")]
+    Rocks(RocksDb),
+    Bitcask(Bitcask),
+}
+
+/// 存储后端描述，格式为 `<kind>:<path>`，如 `sled:/data/hikv`
+///
+/// memory 后端的 path 是快照文件，其余为数据目录
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StorageSpec {
+    pub kind: StorageKind,
+    pub path: String,
+}
+
+/// 后端种类与 feature 无关，未编译的后端在 [`AnyStorage::open`] 时报错
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    Memory,
+    Sled,
+    #[serde(rename = "rocksdb")]
+    Rocks,
+    Bitcask,
+}
+
+impl FromStr for StorageSpec {
+    type Err = HikvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, path) = s
+            .split_once(':')
+            .ok_or_else(|| HikvError::InvalidCommand(format!("invalid storage spec: {}", s)))?;
+        let kind = match kind {
+            "memory" => StorageKind::Memory,
+            "sled" => StorageKind::Sled,
+            "rocksdb" => StorageKind::Rocks,
+            "bitcask" => StorageKind::Bitcask,
+            _ => {
+                return Err(HikvError::InvalidCommand(format!(
+                    "unknown storage kind: {}",
+                    kind
+                )))
+            }
+        };
+        Ok(Self {
+            kind,
+            path: path.into(),
+        })
+    }
+}
+
+impl AnyStorage {
+    /// 打开存储，不压缩 value
+    pub fn open(spec: &StorageSpec) -> Result<Self, HikvError> {
+        Self::open_with(spec, CompressOptions::disabled())
+    }
+
+    /// 打开存储，之后的写入按 compression 压缩 value
+    pub fn open_with(spec: &StorageSpec, compression: CompressOptions) -> Result<Self, HikvError> {
+        let path = Path::new(&spec.path);
+        Ok(match spec.kind {
+            StorageKind::Memory => {
+                let store = MemTable::with_snapshot(path)?;
+                let store = match compression.algorithm {
+                    Compression::None => store,
+                    _ => store.compression(compression),
+                };
+                Self::Memory(Box::new(store))
+            }
+            #[cfg(feature = "sled")]
+            StorageKind::Sled => Self::Sled(SledDb::open(path)?.compression(compression)),
+            #[cfg(not(feature = "sled"))]
+            StorageKind::Sled => return Err(disabled("sled")),
+            #[cfg(feature = "rocksdb")]
+            StorageKind::Rocks => Self::Rocks(RocksDb::open(path)?.compression(compression)),
+            #[cfg(not(feature = "rocksdb"))]
+            StorageKind::Rocks => return Err(disabled("rocksdb")),
+            StorageKind::Bitcask => Self::Bitcask(Bitcask::open(
+                path,
+                BitcaskOptions {
+                    compression,
+                    ..Default::default()
+                },
+            )?),
+        })
+    }
+}
+
+/// 后端未编译时的错误
+#[cfg(any(not(feature = "sled"), not(feature = "rocksdb")))]
+fn disabled(feature: &str) -> HikvError {
+    HikvError::ConfigError(format!(
+        "storage backend {} is not enabled, rebuild with cargo feature `{}`",
+        feature, feature
+    ))
+}
+
+macro_rules! delegate {
+    ($self:ident, $s:ident => $e:expr) => {
+        match $self {
+            AnyStorage::Memory($s) => $e,
+            #[cfg(feature = "sled")]
+            AnyStorage::Sled($s) => $e,
+            #[cfg(feature = "rocksdb")]
+            AnyStorage::Rocks($s) => $e,
+            AnyStorage::Bitcask($s) => $e,
+        }
+    };
+}
+
+impl Storage for AnyStorage {
+    fn set(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, HikvError> {
+        let (key, value) = (key.into(), value.into());
+        delegate!(self, s => s.set(key, value))
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Value>, HikvError> {
+        delegate!(self, s => s.get(key))
+    }
+
+    fn get_with_meta(&self, key: &str) -> Result<Option<(Value, KeyMeta)>, HikvError> {
+        delegate!(self, s => s.get_with_meta(key))
+    }
+
+    fn set_if_version(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        expected: u64,
+    ) -> Result<Option<Value>, HikvError> {
+        let (key, value) = (key.into(), value.into());
+        delegate!(self, s => s.set_if_version(key, value, expected))
+    }
+
+    fn del(&self, key: &str) -> Result<Option<Value>, HikvError> {
+        delegate!(self, s => s.del(key))
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, HikvError> {
+        delegate!(self, s => s.contains(key))
+    }
+
+    fn get_iter(&self) -> Result<StorageIter<'_>, HikvError> {
+        delegate!(self, s => s.get_iter())
+    }
+
+    fn set_all(&self, pairs: Vec<Kvpair>) -> Result<(), HikvError> {
+        delegate!(self, s => s.set_all(pairs))
+    }
+
+    fn flush(&self) -> Result<(), HikvError> {
+        delegate!(self, s => s.flush())
+    }
+
+    fn compact(&self) -> Result<(), HikvError> {
+        delegate!(self, s => s.compact())
+    }
+
+    fn stats(&self) -> Result<StorageStats, HikvError> {
+        delegate!(self, s => s.stats())
+    }
+
+    fn backup(&self, path: &Path, incremental: bool) -> Result<BackupManifest, HikvError> {
+        delegate!(self, s => s.backup(path, incremental))
+    }
+
+    fn create_snapshot(&self, timeout: Duration) -> Result<u64, HikvError> {
+        delegate!(self, s => s.create_snapshot(timeout))
+    }
+
+    fn get_at(&self, snapshot: u64, key: &str) -> Result<Option<Value>, HikvError> {
+        delegate!(self, s => s.get_at(snapshot, key))
+    }
+
+    fn release_snapshot(&self, snapshot: u64) -> Result<bool, HikvError> {
+        delegate!(self, s => s.release_snapshot(snapshot))
+    }
+
+    fn save(&self) -> Result<usize, HikvError> {
+        delegate!(self, s => s.save())
+    }
+
+    fn bg_save(&self) -> Result<(), HikvError> {
+        delegate!(self, s => s.bg_save())
+    }
+
+    fn evicted(&self) -> u64 {
+        delegate!(self, s => s.evicted())
+    }
+}
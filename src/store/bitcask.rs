@@ -0,0 +1,1028 @@
+use std::{
+    collections::{btree_map::Entry, BTreeMap, HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, Read, Write},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+use bytes::{Buf, BufMut};
+use dashmap::DashMap;
+use tracing::{info, warn};
+
+use crate::{
+    decode_entry, BackupManifest, CompressOptions, Compressor, Digest, HikvError, KeyMeta, Kvpair,
+    ReadViews, Storage, StorageIter, StorageKind, StorageStats, Value, VersionClock,
+};
+
+/// 数据记录头: crc(4) + seq(8) + key_len(4) + value_len(4)
+const RECORD_HEADER_LEN: usize = 20;
+/// hint 记录头: seq(8) + key_len(4) + offset(8) + len(4)
+const HINT_HEADER_LEN: usize = 24;
+/// value_len 为该值时表示删除标记
+const TOMBSTONE: u32 = u32::MAX;
+const DATA_EXT: &str = "data";
+const HINT_EXT: &str = "hint";
+
+/// Bitcask 配置
+#[derive(Clone, Debug)]
+pub struct BitcaskOptions {
+    /// 单个 segment 文件的最大字节数，超过后切换到新文件
+    pub max_segment_size: u64,
+    /// 每次写入后是否 fsync
+    pub sync: bool,
+    /// 后台 merge 检查间隔，None 表示不启动后台 merge
+    pub merge_interval: Option<Duration>,
+    /// 垃圾数据占比超过该值时触发后台 merge
+    pub merge_ratio: f64,
+    /// value 压缩配置
+    pub compression: CompressOptions,
+}
+
+impl Default for BitcaskOptions {
+    fn default() -> Self {
+        Self {
+            max_segment_size: 64 * 1024 * 1024,
+            sync: false,
+            merge_interval: Some(Duration::from_secs(60)),
+            merge_ratio: 0.5,
+            compression: CompressOptions::default(),
+        }
+    }
+}
+
+/// 基于追加写日志 + 内存索引(keydir)的存储
+#[derive(Clone, Debug)]
+pub struct Bitcask {
+    inner: Arc<BitcaskInner>,
+}
+
+#[derive(Debug)]
+struct BitcaskInner {
+    dir: PathBuf,
+    opts: BitcaskOptions,
+    /// key -> 最新记录位置
+    keydir: DashMap<String, Position>,
+    /// 所有 segment 的只读句柄
+    readers: RwLock<BTreeMap<u64, Arc<File>>>,
+    /// 当前写入的 segment，写操作串行化
+    active: Mutex<ActiveSegment>,
+    /// 同一时刻只允许一个 merge
+    merging: Mutex<()>,
+    /// 全局递增的写入序号，重启时用来决定同一个 key 的哪条记录更新
+    seq: AtomicU64,
+    /// 下一个 segment 的 id
+    next_id: AtomicU64,
+    /// 所有 segment 的总字节数
+    total_bytes: AtomicU64,
+    /// 已被覆盖或删除的记录字节数
+    dead_bytes: AtomicU64,
+    /// 快照创建时的 keydir，有快照时不做 merge，旧记录一直可读
+    views: ReadViews<Arc<HashMap<String, Position>>>,
+    compressor: Compressor,
+    /// 分配写入的版本号
+    versions: VersionClock,
+}
+
+#[derive(Debug)]
+struct ActiveSegment {
+    id: u64,
+    file: File,
+    size: u64,
+}
+
+/// 记录在 segment 中的位置
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Position {
+    file_id: u64,
+    offset: u64,
+    /// 整条记录的长度
+    len: u32,
+    seq: u64,
+}
+
+/// merge 时被搬移的记录: key, 旧位置, 新位置
+type Moved = Vec<(String, Position, Position)>;
+
+/// 解析出的一条记录
+struct Record {
+    seq: u64,
+    key: String,
+    /// None 表示删除标记
+    value: Option<Vec<u8>>,
+}
+
+impl Bitcask {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self::open(path, BitcaskOptions::default()).unwrap()
+    }
+
+    /// 打开(或创建)目录下的 bitcask 数据
+    pub fn open(path: impl AsRef<Path>, opts: BitcaskOptions) -> Result<Self, HikvError> {
+        let dir = path.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let ids = segment_ids(&dir)?;
+        let mut latest: HashMap<String, (u64, Option<Position>)> = HashMap::new();
+        let mut readers = BTreeMap::new();
+        let mut total_bytes = 0;
+        let mut max_seq = 0;
+
+        for (i, &id) in ids.iter().enumerate() {
+            let is_last = i + 1 == ids.len();
+            let entries = match load_hint(&dir, id) {
+                Some(entries) if !is_last => entries,
+                _ => scan_segment(&dir, id, is_last)?,
+            };
+            for (key, pos) in entries {
+                max_seq = max_seq.max(pos.seq);
+                match latest.get(&key) {
+                    Some((seq, _)) if *seq > pos.seq => {}
+                    _ => {
+                        let live = (pos.len != 0).then_some(pos);
+                        latest.insert(key, (pos.seq, live));
+                    }
+                }
+            }
+            let file = File::open(segment_path(&dir, id, DATA_EXT))?;
+            total_bytes += file.metadata()?.len();
+            readers.insert(id, Arc::new(file));
+        }
+
+        let keydir = DashMap::new();
+        let mut live_bytes = 0;
+        for (key, (_, pos)) in latest {
+            if let Some(pos) = pos {
+                live_bytes += pos.len as u64;
+                keydir.insert(key, pos);
+            }
+        }
+
+        // 最后一个 segment 继续追加写入，它的 hint 文件不再完整
+        let active_id = ids.last().copied().unwrap_or(0);
+        let _ = fs::remove_file(segment_path(&dir, active_id, HINT_EXT));
+        let file = open_append(&dir, active_id)?;
+        let size = file.metadata()?.len();
+        if let Entry::Vacant(e) = readers.entry(active_id) {
+            e.insert(Arc::new(File::open(segment_path(
+                &dir, active_id, DATA_EXT,
+            ))?));
+        }
+
+        info!(
+            "Opened bitcask {:?}: {} segments, {} keys",
+            dir,
+            readers.len(),
+            keydir.len()
+        );
+
+        let inner = Arc::new(BitcaskInner {
+            dir,
+            compressor: Compressor::new(opts.compression),
+            opts,
+            keydir,
+            readers: RwLock::new(readers),
+            active: Mutex::new(ActiveSegment {
+                id: active_id,
+                file,
+                size,
+            }),
+            merging: Mutex::new(()),
+            seq: AtomicU64::new(max_seq),
+            next_id: AtomicU64::new(active_id + 1),
+            total_bytes: AtomicU64::new(total_bytes),
+            dead_bytes: AtomicU64::new(total_bytes.saturating_sub(live_bytes)),
+            views: ReadViews::default(),
+            versions: VersionClock::default(),
+        });
+
+        if let Some(interval) = inner.opts.merge_interval {
+            spawn_merger(Arc::downgrade(&inner), interval);
+        }
+
+        Ok(Self { inner })
+    }
+
+    /// 合并所有不再写入的 segment，丢弃被覆盖和删除的记录，并为新 segment 生成 hint 文件
+    pub fn merge(&self) -> Result<(), HikvError> {
+        self.inner.merge()
+    }
+
+    /// 当前 segment 文件数量
+    pub fn segments(&self) -> usize {
+        self.inner.readers.read().unwrap().len()
+    }
+
+    /// 在 active 锁内读取旧版本并追加新记录
+    fn put(
+        &self,
+        key: String,
+        value: Value,
+        expected: Option<u64>,
+    ) -> Result<Option<Value>, HikvError> {
+        let mut active = self.inner.active.lock().unwrap();
+        let old = self.inner.read_value(&key)?;
+        let meta = self
+            .inner
+            .versions
+            .update(&key, old.as_ref().map(|(_, m)| m), expected)?;
+        let data = self.inner.compressor.encode_entry(&value, &meta)?;
+        let pos = self.inner.append(&mut active, &key, Some(&data))?;
+        if let Some(old_pos) = self.inner.keydir.insert(key, pos) {
+            self.inner.mark_dead(&old_pos);
+        }
+        Ok(old.map(|(v, _)| v))
+    }
+}
+
+impl BitcaskInner {
+    /// 追加一条记录并返回其位置，调用方需持有 active 锁
+    fn append(
+        &self,
+        active: &mut ActiveSegment,
+        key: &str,
+        value: Option<&[u8]>,
+    ) -> Result<Position, HikvError> {
+        if active.size >= self.opts.max_segment_size {
+            self.rotate(active)?;
+        }
+
+        let seq = self.seq.fetch_add(1, Ordering::AcqRel) + 1;
+        let buf = encode_record(seq, key, value);
+        active.file.write_all(&buf)?;
+        if self.opts.sync {
+            active.file.sync_data()?;
+        }
+
+        let pos = Position {
+            file_id: active.id,
+            offset: active.size,
+            len: buf.len() as _,
+            seq,
+        };
+        active.size += buf.len() as u64;
+        self.total_bytes
+            .fetch_add(buf.len() as _, Ordering::Relaxed);
+        Ok(pos)
+    }
+
+    /// 切换到新的 segment 文件
+    fn rotate(&self, active: &mut ActiveSegment) -> Result<(), HikvError> {
+        active.file.sync_all()?;
+        let id = self.next_id.fetch_add(1, Ordering::AcqRel);
+        let file = open_append(&self.dir, id)?;
+        let reader = File::open(segment_path(&self.dir, id, DATA_EXT))?;
+        self.readers.write().unwrap().insert(id, Arc::new(reader));
+        *active = ActiveSegment { id, file, size: 0 };
+        Ok(())
+    }
+
+    fn read_value(&self, key: &str) -> Result<Option<(Value, KeyMeta)>, HikvError> {
+        // merge 会先更新 keydir 再删除旧文件，找不到文件时重新查一次 keydir
+        let mut last = None;
+        loop {
+            let pos = match self.keydir.get(key) {
+                Some(pos) => *pos,
+                None => return Ok(None),
+            };
+            let file = self.reader(pos.file_id);
+            if let Some(file) = file {
+                let record = read_record(&file, pos)?;
+                return record.value.map(|v| decode_entry(&v)).transpose();
+            }
+            if last == Some(pos) {
+                return Err(HikvError::BitcaskError(format!(
+                    "segment {} not found",
+                    pos.file_id
+                )));
+            }
+            last = Some(pos);
+        }
+    }
+
+    fn reader(&self, id: u64) -> Option<Arc<File>> {
+        self.readers.read().unwrap().get(&id).cloned()
+    }
+
+    fn mark_dead(&self, pos: &Position) {
+        self.dead_bytes.fetch_add(pos.len as _, Ordering::Relaxed);
+    }
+
+    fn dead_ratio(&self) -> f64 {
+        let total = self.total_bytes.load(Ordering::Relaxed);
+        if total == 0 {
+            return 0.0;
+        }
+        self.dead_bytes.load(Ordering::Relaxed) as f64 / total as f64
+    }
+
+    fn merge(&self) -> Result<(), HikvError> {
+        let _merging = self.merging.lock().unwrap();
+        if !self.views.is_empty() {
+            return Err(HikvError::BitcaskError(
+                "cannot merge while snapshots are active".into(),
+            ));
+        }
+
+        // 先切换 active segment，之前的 segment 都不再写入
+        let active_id = {
+            let mut active = self.active.lock().unwrap();
+            if active.size > 0 {
+                self.rotate(&mut active)?;
+            }
+            active.id
+        };
+        let old: Vec<(u64, Arc<File>)> = self
+            .readers
+            .read()
+            .unwrap()
+            .range(..active_id)
+            .map(|(id, f)| (*id, f.clone()))
+            .collect();
+        if old.is_empty() {
+            return Ok(());
+        }
+        let old_bytes: u64 = old
+            .iter()
+            .map(|(_, f)| f.metadata().map(|m| m.len()).unwrap_or_default())
+            .sum();
+
+        // 收集旧 segment 中仍然有效的记录
+        let mut live: Vec<(String, Position)> = self
+            .keydir
+            .iter()
+            .filter(|e| e.value().file_id < active_id)
+            .map(|e| (e.key().clone(), *e.value()))
+            .collect();
+        live.sort_by_key(|(_, pos)| (pos.file_id, pos.offset));
+
+        let mut writer: Option<MergeWriter> = None;
+        let mut merged = Vec::new();
+        for (key, pos) in live {
+            let file = &old.iter().find(|(id, _)| *id == pos.file_id).unwrap().1;
+            let record = read_record(file, pos)?;
+            let buf = encode_record(record.seq, &key, record.value.as_deref());
+
+            let full = match &writer {
+                Some(w) => w.size >= self.opts.max_segment_size,
+                None => true,
+            };
+            if full {
+                if let Some(w) = writer.take() {
+                    merged.push(w.finish()?);
+                }
+                let id = self.next_id.fetch_add(1, Ordering::AcqRel);
+                writer = Some(MergeWriter::new(&self.dir, id)?);
+            }
+            let w = writer.as_mut().unwrap();
+            let new_pos = w.write(&key, record.seq, &buf)?;
+            w.moved.push((key, pos, new_pos));
+        }
+        if let Some(w) = writer.take() {
+            merged.push(w.finish()?);
+        }
+
+        // 切换 keydir，期间被重新写入的 key 保持不变
+        let mut merged_bytes = 0;
+        for (id, moved, size) in merged {
+            let reader = File::open(segment_path(&self.dir, id, DATA_EXT))?;
+            self.readers.write().unwrap().insert(id, Arc::new(reader));
+            merged_bytes += size;
+            for (key, old_pos, new_pos) in moved {
+                if let Some(mut pos) = self.keydir.get_mut(&key) {
+                    if *pos == old_pos {
+                        *pos = new_pos;
+                        continue;
+                    }
+                }
+                self.mark_dead(&new_pos);
+            }
+        }
+
+        for (id, _) in &old {
+            self.readers.write().unwrap().remove(id);
+            fs::remove_file(segment_path(&self.dir, *id, DATA_EXT))?;
+            let _ = fs::remove_file(segment_path(&self.dir, *id, HINT_EXT));
+        }
+
+        self.total_bytes.fetch_add(merged_bytes, Ordering::Relaxed);
+        self.total_bytes.fetch_sub(old_bytes, Ordering::Relaxed);
+        let reclaimed = old_bytes.saturating_sub(merged_bytes);
+        let _ = self
+            .dead_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+                Some(v.saturating_sub(reclaimed))
+            });
+        info!(
+            "Merged {} segments in {:?}, reclaimed {} bytes",
+            old.len(),
+            self.dir,
+            reclaimed
+        );
+
+        Ok(())
+    }
+}
+
+impl Storage for Bitcask {
+    fn set(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, HikvError> {
+        self.put(key.into(), value.into(), None)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Value>, HikvError> {
+        Ok(self.inner.read_value(key)?.map(|(v, _)| v))
+    }
+
+    fn get_with_meta(&self, key: &str) -> Result<Option<(Value, KeyMeta)>, HikvError> {
+        self.inner.read_value(key)
+    }
+
+    fn set_if_version(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        expected: u64,
+    ) -> Result<Option<Value>, HikvError> {
+        self.put(key.into(), value.into(), Some(expected))
+    }
+
+    fn del(&self, key: &str) -> Result<Option<Value>, HikvError> {
+        let mut active = self.inner.active.lock().unwrap();
+        let old = self.inner.read_value(key)?.map(|(v, _)| v);
+        if old.is_some() {
+            let pos = self.inner.append(&mut active, key, None)?;
+            // 删除标记本身在 merge 之后就没用了
+            self.inner.mark_dead(&pos);
+            if let Some((_, old_pos)) = self.inner.keydir.remove(key) {
+                self.inner.mark_dead(&old_pos);
+            }
+        }
+        Ok(old)
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, HikvError> {
+        Ok(self.inner.keydir.contains_key(key))
+    }
+
+    fn get_iter(&self) -> Result<StorageIter<'_>, HikvError> {
+        let mut keys: Vec<String> = self.inner.keydir.iter().map(|e| e.key().clone()).collect();
+        keys.sort_unstable();
+        // value 在迭代时才读取，期间被删除的 key 直接跳过
+        let iter = keys
+            .into_iter()
+            .filter_map(|key| match self.inner.read_value(&key) {
+                Ok(Some((v, _))) => Some(Ok(Kvpair::new(key, v))),
+                Ok(None) => None,
+                Err(e) => Some(Err(e)),
+            });
+        Ok(Box::new(iter))
+    }
+
+    /// 复制一份 keydir，追加写入不会改变旧位置上的数据
+    fn create_snapshot(&self, timeout: Duration) -> Result<u64, HikvError> {
+        let inner = &self.inner;
+        // 持有 merging 锁避免旧 segment 被删除，持有 active 锁避免复制期间写入
+        let _merging = inner.merging.lock().unwrap();
+        let _active = inner.active.lock().unwrap();
+        let keydir: HashMap<String, Position> = inner
+            .keydir
+            .iter()
+            .map(|e| (e.key().clone(), *e.value()))
+            .collect();
+        Ok(inner.views.insert(Arc::new(keydir), timeout))
+    }
+
+    fn get_at(&self, snapshot: u64, key: &str) -> Result<Option<Value>, HikvError> {
+        let view = self.inner.views.get(snapshot)?;
+        let pos = match view.get(key) {
+            Some(pos) => *pos,
+            None => return Ok(None),
+        };
+        let file = self
+            .inner
+            .reader(pos.file_id)
+            .ok_or_else(|| HikvError::BitcaskError(format!("segment {} not found", pos.file_id)))?;
+        let record = read_record(&file, pos)?;
+        Ok(record
+            .value
+            .map(|v| decode_entry(&v))
+            .transpose()?
+            .map(|(v, _)| v))
+    }
+
+    fn release_snapshot(&self, snapshot: u64) -> Result<bool, HikvError> {
+        Ok(self.inner.views.remove(snapshot))
+    }
+
+    fn flush(&self) -> Result<(), HikvError> {
+        self.inner.active.lock().unwrap().file.sync_data()?;
+        Ok(())
+    }
+
+    /// 立即 merge，有快照时返回错误
+    fn compact(&self) -> Result<(), HikvError> {
+        self.inner.merge()
+    }
+
+    /// 切换 active segment 后复制所有写满的 segment，增量备份时跳过已复制的文件
+    fn backup(&self, path: &Path, incremental: bool) -> Result<BackupManifest, HikvError> {
+        if !incremental && path.exists() {
+            return Err(HikvError::BackupError(format!(
+                "backup target {:?} already exists",
+                path
+            )));
+        }
+        let inner = &self.inner;
+        // 持有 merging 锁，复制期间旧 segment 不会被删除
+        let _merging = inner.merging.lock().unwrap();
+        let (active_id, keydir) = {
+            let mut active = inner.active.lock().unwrap();
+            if active.size > 0 {
+                inner.rotate(&mut active)?;
+            }
+            let keydir: Vec<(String, Position)> = inner
+                .keydir
+                .iter()
+                .map(|e| (e.key().clone(), *e.value()))
+                .collect();
+            (active.id, keydir)
+        };
+        let ids: Vec<u64> = inner
+            .readers
+            .read()
+            .unwrap()
+            .range(..active_id)
+            .map(|(id, _)| *id)
+            .collect();
+
+        // segment 写满后不再修改，同名且大小相同的文件无需重新复制
+        fs::create_dir_all(path)?;
+        let mut files = HashSet::new();
+        for id in ids {
+            for ext in [DATA_EXT, HINT_EXT] {
+                let src = segment_path(&inner.dir, id, ext);
+                if !src.exists() {
+                    continue;
+                }
+                let dst = segment_path(path, id, ext);
+                let copied = incremental
+                    && matches!(
+                        (fs::metadata(&src), fs::metadata(&dst)),
+                        (Ok(a), Ok(b)) if a.len() == b.len()
+                    );
+                if !copied {
+                    fs::copy(&src, &dst)?;
+                }
+                files.insert(dst);
+            }
+        }
+        // 删除已被 merge 掉的旧 segment，目录中的其他文件不动
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let file = entry.path();
+            let is_segment = entry.file_type()?.is_file()
+                && file
+                    .extension()
+                    .is_some_and(|ext| ext == DATA_EXT || ext == HINT_EXT);
+            if is_segment && !files.contains(&file) {
+                fs::remove_file(file)?;
+            }
+        }
+
+        let mut digest = Digest::default();
+        for (key, pos) in keydir {
+            let file = inner.reader(pos.file_id).ok_or_else(|| {
+                HikvError::BitcaskError(format!("segment {} not found", pos.file_id))
+            })?;
+            if let Some(v) = read_record(&file, pos)?.value {
+                digest.update(&Kvpair::new(key, decode_entry(&v)?.0))?;
+            }
+        }
+        Ok(BackupManifest::new(
+            StorageKind::Bitcask,
+            digest,
+            incremental,
+        ))
+    }
+
+    /// 用 keydir 和维护的字节数统计，不读取数据；logical_bytes 为有效记录(含记录头)的总长度
+    fn stats(&self) -> Result<StorageStats, HikvError> {
+        let inner = &self.inner;
+        let total = inner.total_bytes.load(Ordering::Relaxed);
+        let dead = inner.dead_bytes.load(Ordering::Relaxed);
+        let stats = StorageStats {
+            keys: inner.keydir.len() as u64,
+            logical_bytes: total.saturating_sub(dead),
+            disk_bytes: total,
+            ..Default::default()
+        };
+        Ok(stats
+            .with("bitcask.segments", self.segments() as i64)
+            .with(
+                "bitcask.dead_bytes",
+                inner.dead_bytes.load(Ordering::Relaxed) as i64,
+            )
+            .with("bitcask.dead_ratio", inner.dead_ratio())
+            .with_compression(inner.compressor.stats()))
+    }
+}
+
+/// 后台 merge 线程，存储被 drop 后自动退出
+fn spawn_merger(inner: Weak<BitcaskInner>, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => break,
+        };
+        if inner.dead_ratio() >= inner.opts.merge_ratio && inner.views.is_empty() {
+            if let Err(e) = inner.merge() {
+                warn!("Background merge of {:?} failed: {}", inner.dir, e);
+            }
+        }
+    });
+}
+
+/// merge 时写入新 segment 及其 hint 文件
+struct MergeWriter {
+    dir: PathBuf,
+    id: u64,
+    data: BufWriter<File>,
+    hint: Vec<u8>,
+    size: u64,
+    moved: Moved,
+}
+
+impl MergeWriter {
+    fn new(dir: &Path, id: u64) -> Result<Self, HikvError> {
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            id,
+            data: BufWriter::new(open_append(dir, id)?),
+            hint: Vec::new(),
+            size: 0,
+            moved: Vec::new(),
+        })
+    }
+
+    fn write(&mut self, key: &str, seq: u64, buf: &[u8]) -> Result<Position, HikvError> {
+        self.data.write_all(buf)?;
+        let pos = Position {
+            file_id: self.id,
+            offset: self.size,
+            len: buf.len() as _,
+            seq,
+        };
+        self.size += buf.len() as u64;
+
+        self.hint.put_u64(seq);
+        self.hint.put_u32(key.len() as _);
+        self.hint.put_u64(pos.offset);
+        self.hint.put_u32(pos.len);
+        self.hint.put_slice(key.as_bytes());
+        Ok(pos)
+    }
+
+    /// 落盘数据文件，再写入带校验和的 hint 文件
+    fn finish(self) -> Result<(u64, Moved, u64), HikvError> {
+        let file = self.data.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+
+        let dir = self.dir;
+        let mut hint = self.hint;
+        let crc = crc32fast::hash(&hint);
+        hint.put_u32(crc);
+        let tmp = segment_path(&dir, self.id, "hint.tmp");
+        fs::write(&tmp, &hint)?;
+        fs::rename(&tmp, segment_path(&dir, self.id, HINT_EXT))?;
+
+        Ok((self.id, self.moved, self.size))
+    }
+}
+
+/// 从 hint 文件加载索引，文件不存在或损坏时返回 None
+fn load_hint(dir: &Path, id: u64) -> Option<Vec<(String, Position)>> {
+    let data = fs::read(segment_path(dir, id, HINT_EXT)).ok()?;
+    if data.len() < 4 {
+        return None;
+    }
+    let (mut body, mut crc) = data.split_at(data.len() - 4);
+    if crc32fast::hash(body) != crc.get_u32() {
+        warn!("Hint file of segment {} is corrupted, fallback to scan", id);
+        return None;
+    }
+
+    let mut entries = Vec::new();
+    while body.has_remaining() {
+        if body.remaining() < HINT_HEADER_LEN {
+            return None;
+        }
+        let seq = body.get_u64();
+        let key_len = body.get_u32() as usize;
+        let offset = body.get_u64();
+        let len = body.get_u32();
+        if body.remaining() < key_len {
+            return None;
+        }
+        let key = String::from_utf8(body[..key_len].to_vec()).ok()?;
+        body.advance(key_len);
+        entries.push((
+            key,
+            Position {
+                file_id: id,
+                offset,
+                len,
+                seq,
+            },
+        ));
+    }
+    Some(entries)
+}
+
+/// 顺序扫描 segment 重建索引，删除标记的 len 记为 0
+///
+/// 遇到不完整或校验失败的记录即停止；最后一个 segment 会被截断到最后一条完整记录
+fn scan_segment(dir: &Path, id: u64, truncate: bool) -> Result<Vec<(String, Position)>, HikvError> {
+    let path = segment_path(dir, id, DATA_EXT);
+    let mut reader = BufReader::new(File::open(&path)?);
+    let mut entries = Vec::new();
+    let mut offset = 0u64;
+    let mut header = [0u8; RECORD_HEADER_LEN];
+
+    loop {
+        if read_full(&mut reader, &mut header)? != RECORD_HEADER_LEN {
+            break;
+        }
+        let (key_len, value_len) = record_lens(&header);
+        let body_len = key_len + value_len.map_or(0, |v| v as usize);
+        let mut buf = header.to_vec();
+        buf.resize(RECORD_HEADER_LEN + body_len, 0);
+        if read_full(&mut reader, &mut buf[RECORD_HEADER_LEN..])? != body_len {
+            break;
+        }
+        let record = match decode_record(&buf) {
+            Ok(record) => record,
+            Err(e) => {
+                warn!("Segment {} is corrupted at offset {}: {}", id, offset, e);
+                break;
+            }
+        };
+
+        let len = if record.value.is_some() {
+            buf.len() as u32
+        } else {
+            0
+        };
+        entries.push((
+            record.key,
+            Position {
+                file_id: id,
+                offset,
+                len,
+                seq: record.seq,
+            },
+        ));
+        offset += buf.len() as u64;
+    }
+
+    let size = fs::metadata(&path)?.len();
+    if offset < size {
+        if truncate {
+            warn!("Truncate segment {} from {} to {} bytes", id, size, offset);
+            OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_len(offset)?;
+        } else {
+            warn!("Ignore {} trailing bytes of segment {}", size - offset, id);
+        }
+    }
+
+    Ok(entries)
+}
+
+fn read_record(file: &File, pos: Position) -> Result<Record, HikvError> {
+    let mut buf = vec![0u8; pos.len as usize];
+    file.read_exact_at(&mut buf, pos.offset)?;
+    decode_record(&buf)
+}
+
+/// 编码记录: crc | seq | key_len | value_len | key | value
+fn encode_record(seq: u64, key: &str, value: Option<&[u8]>) -> Vec<u8> {
+    let value_len = value.map_or(0, |v| v.len());
+    let mut buf = Vec::with_capacity(RECORD_HEADER_LEN + key.len() + value_len);
+    buf.put_u32(0);
+    buf.put_u64(seq);
+    buf.put_u32(key.len() as _);
+    buf.put_u32(value.map_or(TOMBSTONE, |v| v.len() as _));
+    buf.put_slice(key.as_bytes());
+    if let Some(v) = value {
+        buf.put_slice(v);
+    }
+    let crc = crc32fast::hash(&buf[4..]);
+    buf[..4].copy_from_slice(&crc.to_be_bytes());
+    buf
+}
+
+fn decode_record(buf: &[u8]) -> Result<Record, HikvError> {
+    if buf.len() < RECORD_HEADER_LEN {
+        return Err(HikvError::BitcaskError("record too short".into()));
+    }
+    let mut header = &buf[..RECORD_HEADER_LEN];
+    let crc = header.get_u32();
+    if crc32fast::hash(&buf[4..]) != crc {
+        return Err(HikvError::BitcaskError("record checksum mismatch".into()));
+    }
+    let seq = header.get_u64();
+    let (key_len, value_len) = record_lens(&buf[..RECORD_HEADER_LEN]);
+    let body = &buf[RECORD_HEADER_LEN..];
+    if body.len() != key_len + value_len.map_or(0, |v| v as usize) {
+        return Err(HikvError::BitcaskError("record length mismatch".into()));
+    }
+    let key = String::from_utf8(body[..key_len].to_vec())
+        .map_err(|_| HikvError::BitcaskError("key is not valid utf8".into()))?;
+    let value = value_len.map(|_| body[key_len..].to_vec());
+    Ok(Record { seq, key, value })
+}
+
+/// 从记录头解析 key/value 长度，删除标记的 value 长度为 None
+fn record_lens(mut header: &[u8]) -> (usize, Option<u32>) {
+    header.advance(12);
+    let key_len = header.get_u32() as usize;
+    let value_len = header.get_u32();
+    (key_len, (value_len != TOMBSTONE).then_some(value_len))
+}
+
+/// 尽量读满 buf，返回实际读到的字节数
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize, HikvError> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..])? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    Ok(n)
+}
+
+fn segment_ids(dir: &Path) -> Result<Vec<u64>, HikvError> {
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(DATA_EXT) {
+            continue;
+        }
+        if let Some(id) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse().ok())
+        {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+fn segment_path(dir: &Path, id: u64, ext: &str) -> PathBuf {
+    dir.join(format!("{:020}.{}", id, ext))
+}
+
+fn open_append(dir: &Path, id: u64) -> Result<File, HikvError> {
+    Ok(OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(segment_path(dir, id, DATA_EXT))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn small_segments() -> BitcaskOptions {
+        BitcaskOptions {
+            max_segment_size: 128,
+            merge_interval: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_work_bitcask_reopen() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::open(&dir, small_segments()).unwrap();
+        store.set("hello", "world").unwrap();
+        store.set("lang", "rust").unwrap();
+        store.del("lang").unwrap();
+        drop(store);
+
+        let store = Bitcask::open(&dir, small_segments()).unwrap();
+        assert_eq!(store.get("hello").unwrap(), Some("world".into()));
+        assert_eq!(store.get("lang").unwrap(), None);
+    }
+
+    #[test]
+    fn should_work_bitcask_rotate_and_merge() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::open(&dir, small_segments()).unwrap();
+        for i in 0..50i64 {
+            store.set(format!("key{}", i % 5), Value::from(i)).unwrap();
+        }
+        store.del("key0").unwrap();
+        assert!(store.segments() > 2);
+
+        store.merge().unwrap();
+        for i in 1..5i64 {
+            assert_eq!(
+                store.get(&format!("key{}", i)).unwrap(),
+                Some(Value::from(45 + i))
+            );
+        }
+        assert_eq!(store.get("key0").unwrap(), None);
+        assert!(store.inner.dead_ratio() < 0.5);
+        drop(store);
+
+        // 重启后通过 hint 文件恢复
+        let hints = fs::read_dir(&dir)
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().path().extension().unwrap() == HINT_EXT)
+            .count();
+        assert!(hints > 0);
+        let store = Bitcask::open(&dir, small_segments()).unwrap();
+        assert_eq!(store.get("key4").unwrap(), Some(49.into()));
+        assert_eq!(store.get("key0").unwrap(), None);
+    }
+
+    #[test]
+    fn snapshot_should_survive_rotate_and_block_merge() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::open(&dir, small_segments()).unwrap();
+        store.set("lang", "rust").unwrap();
+        let id = store.create_snapshot(Duration::from_secs(60)).unwrap();
+        for i in 0..50i64 {
+            store.set("lang", Value::from(i)).unwrap();
+        }
+        store.set("os", "linux").unwrap();
+
+        assert!(store.merge().is_err());
+        assert_eq!(store.get_at(id, "lang").unwrap(), Some("rust".into()));
+        assert_eq!(store.get_at(id, "os").unwrap(), None);
+
+        assert!(store.release_snapshot(id).unwrap());
+        store.merge().unwrap();
+        assert_eq!(store.get("lang").unwrap(), Some(49.into()));
+    }
+
+    #[test]
+    fn should_truncate_corrupted_tail() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::open(&dir, BitcaskOptions::default()).unwrap();
+        store.set("hello", "world").unwrap();
+        store.set("lang", "rust").unwrap();
+        drop(store);
+
+        // 破坏最后一条记录
+        let path = segment_path(dir.path(), 0, DATA_EXT);
+        let mut data = fs::read(&path).unwrap();
+        let n = data.len();
+        data[n - 1] ^= 0xff;
+        fs::write(&path, data).unwrap();
+
+        let store = Bitcask::open(&dir, BitcaskOptions::default()).unwrap();
+        assert_eq!(store.get("hello").unwrap(), Some("world".into()));
+        assert_eq!(store.get("lang").unwrap(), None);
+
+        store.set("lang", "go").unwrap();
+        assert_eq!(store.get("lang").unwrap(), Some("go".into()));
+    }
+
+    #[test]
+    fn backup_should_refuse_existing_target_and_keep_other_files() {
+        let dir = tempdir().unwrap();
+        let target = tempdir().unwrap();
+        let store = Bitcask::open(&dir, small_segments()).unwrap();
+        store.set("hello", "world").unwrap();
+
+        assert!(store.backup(target.path(), false).is_err());
+
+        let note = target.path().join("README");
+        std::fs::write(&note, "keep me").unwrap();
+        store.backup(target.path(), true).unwrap();
+        assert!(note.exists());
+
+        let restored = Bitcask::open(target.path(), small_segments()).unwrap();
+        assert_eq!(restored.get("hello").unwrap(), Some("world".into()));
+    }
+}
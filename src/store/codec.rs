@@ -0,0 +1,474 @@
+use std::{
+    io::{Read, Write},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bytes::{Buf, BufMut, Bytes};
+use flate2::{read::GzDecoder, write::GzEncoder};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::{value, HikvError, KeyMeta, Value};
+
+/// 带头部记录的首字节。protobuf 编码的 Value 首字节是 field 1~5 的 tag，
+/// 不会是 0xff，据此区分旧的无头部记录
+const MAGIC: u8 = 0xff;
+/// 当前格式版本
+pub const FORMAT_VERSION: u8 = 1;
+/// 头部长度: magic | version | codec | flags
+const HEADER_LEN: usize = 4;
+/// 头部后带有 key 元数据: version | created_at | updated_at
+pub const FLAG_META: u8 = 0x01;
+/// value 的压缩算法，见 [`Compression`]
+pub const FLAG_COMPRESSION: u8 = 0x06;
+const COMPRESSION_SHIFT: u8 = 1;
+/// 目前支持的所有 flags
+const KNOWN_FLAGS: u8 = FLAG_META | FLAG_COMPRESSION;
+/// 元数据长度
+const META_LEN: usize = 24;
+/// 默认压缩阈值，编码后超过该大小的 value 才压缩
+pub const DEFAULT_COMPRESS_THRESHOLD: usize = 4096;
+
+/// value 的编码方式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Codec {
+    /// protobuf 编码的 Value
+    Protobuf = 0,
+    /// Binary 直接保存原始字节
+    RawBinary = 1,
+    /// String 直接保存 utf8 字节
+    RawString = 2,
+}
+
+/// value 的压缩算法，记录在头部 flags 中，读取时按记录自动解压
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum Compression {
+    /// 不压缩
+    #[default]
+    None = 0,
+    Gzip = 1,
+}
+
+/// 写入时的压缩配置，默认不压缩
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompressOptions {
+    pub algorithm: Compression,
+    /// 编码后超过该字节数的 value 才压缩
+    pub threshold: usize,
+}
+
+/// 自启动以来写入的 value 压缩前后的字节数
+///
+/// 每次编码都会计入，包括被覆盖、删除的数据和 CAS/事务重试，反映的是写入流量而不是存储中的数据
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    pub raw_bytes: u64,
+    pub stored_bytes: u64,
+}
+
+/// 按配置压缩 value，并统计自启动以来写入的字节数，默认不压缩
+#[derive(Debug, Default)]
+pub struct Compressor {
+    options: CompressOptions,
+    raw_bytes: AtomicU64,
+    stored_bytes: AtomicU64,
+}
+
+/// 记录头部
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecordHeader {
+    pub version: u8,
+    pub codec: Codec,
+    /// 记录附带的扩展信息，见 FLAG_*
+    pub flags: u8,
+}
+
+impl TryFrom<u8> for Codec {
+    type Error = HikvError;
+
+    fn try_from(id: u8) -> Result<Self, Self::Error> {
+        match id {
+            0 => Ok(Self::Protobuf),
+            1 => Ok(Self::RawBinary),
+            2 => Ok(Self::RawString),
+            _ => Err(HikvError::CodecError(format!("unknown codec {}", id))),
+        }
+    }
+}
+
+impl TryFrom<u8> for Compression {
+    type Error = HikvError;
+
+    fn try_from(id: u8) -> Result<Self, Self::Error> {
+        match id {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Gzip),
+            _ => Err(HikvError::CodecError(format!("unknown compression {}", id))),
+        }
+    }
+}
+
+impl Compression {
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>, HikvError> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+
+    fn decompress(self, data: &[u8]) -> Result<Vec<u8>, HikvError> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Gzip => {
+                let mut buf = Vec::new();
+                GzDecoder::new(data)
+                    .read_to_end(&mut buf)
+                    .map_err(|e| HikvError::CodecError(format!("corrupted gzip value: {}", e)))?;
+                Ok(buf)
+            }
+        }
+    }
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        Self {
+            algorithm: Compression::None,
+            threshold: DEFAULT_COMPRESS_THRESHOLD,
+        }
+    }
+}
+
+impl CompressOptions {
+    /// 不压缩
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// 超过默认阈值的 value 用 gzip 压缩
+    pub fn gzip() -> Self {
+        Self {
+            algorithm: Compression::Gzip,
+            ..Default::default()
+        }
+    }
+}
+
+impl CompressionStats {
+    /// 写入的压缩比: 压缩前字节数 / 写入的字节数，没有写入时为 1
+    pub fn ratio(&self) -> f64 {
+        if self.stored_bytes == 0 {
+            1.0
+        } else {
+            self.raw_bytes as f64 / self.stored_bytes as f64
+        }
+    }
+}
+
+impl Compressor {
+    pub fn new(options: CompressOptions) -> Self {
+        Self {
+            options,
+            ..Default::default()
+        }
+    }
+
+    pub fn options(&self) -> CompressOptions {
+        self.options
+    }
+
+    /// 和 [`encode_value`] 相同，超过阈值时压缩
+    pub fn encode_value(&self, value: &Value) -> Result<Vec<u8>, HikvError> {
+        self.encode(value, None)
+    }
+
+    /// 和 [`encode_entry`] 相同，超过阈值时压缩
+    pub fn encode_entry(&self, value: &Value, meta: &KeyMeta) -> Result<Vec<u8>, HikvError> {
+        self.encode(value, Some(meta))
+    }
+
+    pub fn stats(&self) -> CompressionStats {
+        CompressionStats {
+            raw_bytes: self.raw_bytes.load(Ordering::Relaxed),
+            stored_bytes: self.stored_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    fn encode(&self, value: &Value, meta: Option<&KeyMeta>) -> Result<Vec<u8>, HikvError> {
+        let data = encode(value, meta, &self.options)?;
+        self.raw_bytes
+            .fetch_add(value.encoded_len() as u64, Ordering::Relaxed);
+        self.stored_bytes
+            .fetch_add(data.len() as u64, Ordering::Relaxed);
+        Ok(data)
+    }
+}
+
+impl Codec {
+    /// Binary/String 走原始字节，其余用 protobuf
+    pub fn for_value(value: &Value) -> Self {
+        match value.value {
+            Some(value::Value::Binary(_)) => Self::RawBinary,
+            Some(value::Value::String(_)) => Self::RawString,
+            _ => Self::Protobuf,
+        }
+    }
+
+    fn encode(self, value: &Value, buf: &mut Vec<u8>) -> Result<(), HikvError> {
+        match (self, &value.value) {
+            (Self::RawBinary, Some(value::Value::Binary(b))) => buf.put_slice(b),
+            (Self::RawString, Some(value::Value::String(s))) => buf.put_slice(s.as_bytes()),
+            (Self::Protobuf, _) => value.encode(buf)?,
+            (codec, _) => {
+                return Err(HikvError::CodecError(format!(
+                    "{:?} cannot encode {:?}",
+                    codec, value
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn decode(self, data: &[u8]) -> Result<Value, HikvError> {
+        Ok(match self {
+            Self::Protobuf => Value::decode(data)?,
+            Self::RawBinary => Bytes::copy_from_slice(data).into(),
+            Self::RawString => String::from_utf8(data.to_vec())
+                .map_err(|_| HikvError::CodecError("string is not valid utf8".into()))?
+                .into(),
+        })
+    }
+}
+
+impl RecordHeader {
+    pub fn new(codec: Codec) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            codec,
+            flags: 0,
+        }
+    }
+
+    fn write(&self, buf: &mut Vec<u8>) {
+        buf.put_slice(&[MAGIC, self.version, self.codec as u8, self.flags]);
+    }
+
+    /// 解析头部，旧的无头部记录返回 None
+    pub fn read(data: &mut &[u8]) -> Result<Option<Self>, HikvError> {
+        if data.first() != Some(&MAGIC) {
+            return Ok(None);
+        }
+        if data.len() < HEADER_LEN {
+            return Err(HikvError::CodecError("truncated record header".into()));
+        }
+        data.advance(1);
+        let version = data.get_u8();
+        if version > FORMAT_VERSION {
+            return Err(HikvError::CodecError(format!(
+                "unsupported format version {}",
+                version
+            )));
+        }
+        let codec = data.get_u8().try_into()?;
+        let flags = data.get_u8();
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(HikvError::CodecError(format!(
+                "unsupported flags {:#04x}",
+                flags
+            )));
+        }
+        let header = Self {
+            version,
+            codec,
+            flags,
+        };
+        header.compression()?;
+        Ok(Some(header))
+    }
+
+    /// value 使用的压缩算法
+    pub fn compression(&self) -> Result<Compression, HikvError> {
+        ((self.flags & FLAG_COMPRESSION) >> COMPRESSION_SHIFT).try_into()
+    }
+}
+
+/// 把 value 编码成带头部的存储格式，不压缩
+pub fn encode_value(value: &Value) -> Result<Vec<u8>, HikvError> {
+    encode(value, None, &CompressOptions::disabled())
+}
+
+/// 把 value 及其元数据编码成带头部的存储格式，不压缩
+pub fn encode_entry(value: &Value, meta: &KeyMeta) -> Result<Vec<u8>, HikvError> {
+    encode(value, Some(meta), &CompressOptions::disabled())
+}
+
+/// 解码存储格式，兼容旧的无头部 protobuf 记录
+pub fn decode_value(data: &[u8]) -> Result<Value, HikvError> {
+    decode_entry(data).map(|(value, _)| value)
+}
+
+/// 解码 value 及其元数据，没有元数据的记录返回 KeyMeta::legacy()
+pub fn decode_entry(mut data: &[u8]) -> Result<(Value, KeyMeta), HikvError> {
+    let header = match RecordHeader::read(&mut data)? {
+        Some(header) => header,
+        None => return Ok((Value::decode(data)?, KeyMeta::legacy())),
+    };
+
+    let meta = if header.flags & FLAG_META != 0 {
+        if data.len() < META_LEN {
+            return Err(HikvError::CodecError("truncated key metadata".into()));
+        }
+        KeyMeta {
+            version: data.get_u64(),
+            created_at: data.get_u64(),
+            updated_at: data.get_u64(),
+        }
+    } else {
+        KeyMeta::legacy()
+    };
+    let value = match header.compression()? {
+        Compression::None => header.codec.decode(data)?,
+        compression => header.codec.decode(&compression.decompress(data)?)?,
+    };
+    Ok((value, meta))
+}
+
+fn encode(
+    value: &Value,
+    meta: Option<&KeyMeta>,
+    options: &CompressOptions,
+) -> Result<Vec<u8>, HikvError> {
+    let codec = Codec::for_value(value);
+    let mut header = RecordHeader::new(codec);
+    let mut body = Vec::with_capacity(value.encoded_len());
+    codec.encode(value, &mut body)?;
+    // 压缩后没有变小的 value 按原样保存
+    if options.algorithm != Compression::None && body.len() > options.threshold {
+        let compressed = options.algorithm.compress(&body)?;
+        if compressed.len() < body.len() {
+            body = compressed;
+            header.flags |= (options.algorithm as u8) << COMPRESSION_SHIFT;
+        }
+    }
+
+    let mut buf = Vec::with_capacity(HEADER_LEN + META_LEN + body.len());
+    if meta.is_some() {
+        header.flags |= FLAG_META;
+    }
+    header.write(&mut buf);
+    if let Some(meta) = meta {
+        buf.put_u64(meta.version);
+        buf.put_u64(meta.created_at);
+        buf.put_u64(meta.updated_at);
+    }
+    buf.put_slice(&body);
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_work_encode_decode_roundtrip() {
+        let values: Vec<Value> = vec![
+            "hello".into(),
+            Bytes::from_static(b"\xff\x00raw").into(),
+            42.into(),
+            1.5.into(),
+            true.into(),
+            Value::default(),
+        ];
+        for v in values {
+            let data = encode_value(&v).unwrap();
+            assert_eq!(data[0], MAGIC);
+            assert_eq!(decode_value(&data).unwrap(), v);
+        }
+    }
+
+    #[test]
+    fn raw_codec_should_store_bytes_as_is() {
+        let data = encode_value(&"hello".into()).unwrap();
+        assert_eq!(&data[..HEADER_LEN], &[MAGIC, FORMAT_VERSION, 2, 0]);
+        assert_eq!(&data[HEADER_LEN..], b"hello");
+    }
+
+    #[test]
+    fn should_decode_legacy_records() {
+        for v in [Value::from("world"), 18.into(), Value::default()] {
+            let legacy: Vec<u8> = v.clone().try_into().unwrap();
+            assert_eq!(decode_value(&legacy).unwrap(), v);
+        }
+    }
+
+    #[test]
+    fn should_work_entry_with_meta() {
+        let meta = KeyMeta {
+            version: 3,
+            created_at: 1000,
+            updated_at: 2000,
+        };
+        let data = encode_entry(&"hello".into(), &meta).unwrap();
+        assert_eq!(data[3], FLAG_META);
+        assert_eq!(decode_entry(&data).unwrap(), ("hello".into(), meta));
+        assert_eq!(decode_value(&data).unwrap(), "hello".into());
+
+        let data = encode_value(&"hello".into()).unwrap();
+        assert_eq!(decode_entry(&data).unwrap().1, KeyMeta::legacy());
+    }
+
+    #[test]
+    fn large_values_should_be_compressed() {
+        let compressor = Compressor::new(CompressOptions {
+            algorithm: Compression::Gzip,
+            threshold: 64,
+        });
+        let meta = KeyMeta::new(1);
+        let json: Value = "{\"name\": \"hikv\"}".repeat(100).into();
+        let data = compressor.encode_entry(&json, &meta).unwrap();
+        let mut header = &data[..];
+        let header = RecordHeader::read(&mut header).unwrap().unwrap();
+        assert_eq!(header.compression().unwrap(), Compression::Gzip);
+        assert_eq!(header.flags & FLAG_META, FLAG_META);
+        assert!(data.len() < 200);
+        assert_eq!(decode_entry(&data).unwrap(), (json, meta));
+
+        // 小 value 和压缩后没有变小的 value 不压缩
+        let small = Value::from("small");
+        let data = compressor.encode_value(&small).unwrap();
+        assert_eq!(data[3] & FLAG_COMPRESSION, 0);
+        assert_eq!(decode_value(&data).unwrap(), small);
+        let random: Vec<u8> = (0..1024).map(|_| fastrand::u8(..)).collect();
+        let data = compressor
+            .encode_value(&Bytes::from(random).into())
+            .unwrap();
+        assert_eq!(data[3] & FLAG_COMPRESSION, 0);
+
+        let stats = compressor.stats();
+        assert!(stats.ratio() > 1.0);
+        assert!(stats.raw_bytes > stats.stored_bytes);
+    }
+
+    #[test]
+    fn should_reject_unknown_header() {
+        let err = decode_value(&[MAGIC, FORMAT_VERSION + 1, 0, 0]).unwrap_err();
+        assert!(err.to_string().contains("version"));
+        let err = decode_value(&[MAGIC, FORMAT_VERSION, 9, 0]).unwrap_err();
+        assert!(err.to_string().contains("codec"));
+        assert!(decode_value(&[MAGIC, FORMAT_VERSION]).is_err());
+        let err = decode_value(&[MAGIC, FORMAT_VERSION, 0, 0x80]).unwrap_err();
+        assert!(err.to_string().contains("flags"));
+        assert!(decode_value(&[MAGIC, FORMAT_VERSION, 0, FLAG_META, 0]).is_err());
+        let err = decode_value(&[MAGIC, FORMAT_VERSION, 0, FLAG_COMPRESSION]).unwrap_err();
+        assert!(err.to_string().contains("compression"));
+        assert!(decode_value(&[MAGIC, FORMAT_VERSION, 0, 1 << COMPRESSION_SHIFT, 1]).is_err());
+    }
+}
@@ -0,0 +1,672 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs::{self, OpenOptions},
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+use aes_gcm_siv::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256GcmSiv, Nonce,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL, Engine};
+use bytes::{BufMut, Bytes};
+use tracing::{error, info};
+
+use crate::{
+    decode_value, encode_value, BackupManifest, HikvError, KeyMeta, Kvpair, Storage, StorageIter,
+    StorageStats, Value,
+};
+
+/// 加密记录格式版本
+const RECORD_VERSION: u8 = 1;
+/// 加密记录头部: 版本 + key id + nonce
+const RECORD_HEADER_LEN: usize = 1 + 4 + NONCE_LEN;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+/// 加密 key 名时使用的固定 nonce，相同的 key 名总是得到相同的密文
+const NAME_NONCE: [u8; NONCE_LEN] = [0; NONCE_LEN];
+const NAME_AAD: &[u8] = b"hikv:key";
+
+/// 密钥文件中的密钥集合
+///
+/// 文件每行一个密钥 `<id> <base64 编码的 32 字节密钥>`，`#` 开头为注释；
+/// 最后一行是当前密钥，新写入的数据都用它加密，其余密钥只用于解密旧数据
+#[derive(Clone)]
+pub struct KeyRing {
+    keys: BTreeMap<u32, Aes256GcmSiv>,
+    current: u32,
+    /// 文件中的第一个密钥，用于加密 key 名，轮换时不变
+    first: u32,
+}
+
+impl KeyRing {
+    /// 从密钥文件读取
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, HikvError> {
+        let content = fs::read_to_string(path)?;
+        let mut keys = BTreeMap::new();
+        let mut order = Vec::new();
+        for (n, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid =
+                |msg: &str| HikvError::EncryptionError(format!("key file line {}: {}", n + 1, msg));
+            let (id, key) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| invalid("expect `<id> <key>`"))?;
+            let id: u32 = id.parse().map_err(|_| invalid("invalid key id"))?;
+            let key = base64::engine::general_purpose::STANDARD
+                .decode(key.trim())
+                .map_err(|_| invalid("invalid base64 key"))?;
+            if key.len() != KEY_LEN {
+                return Err(invalid("key must be 32 bytes"));
+            }
+            let cipher = Aes256GcmSiv::new_from_slice(&key).map_err(|e| invalid(&e.to_string()))?;
+            if keys.insert(id, cipher).is_some() {
+                return Err(invalid("duplicate key id"));
+            }
+            order.push(id);
+        }
+        match (order.first(), order.last()) {
+            (Some(&first), Some(&current)) => Ok(Self {
+                keys,
+                current,
+                first,
+            }),
+            _ => Err(HikvError::EncryptionError("key file is empty".into())),
+        }
+    }
+
+    /// 生成一个随机密钥追加到密钥文件末尾，使它成为当前密钥
+    ///
+    /// 先写入权限为 0600 的临时文件再改名，中途失败不会破坏原有密钥文件
+    pub fn generate(path: impl AsRef<Path>, id: u32) -> Result<(), HikvError> {
+        let path = path.as_ref();
+        let mut content = fs::read_to_string(path).unwrap_or_default();
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        let key = Aes256GcmSiv::generate_key(&mut OsRng);
+        let key = base64::engine::general_purpose::STANDARD.encode(&key[..]);
+        let _ = writeln!(content, "{} {}", id, key);
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&tmp)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// 当前密钥 id
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+
+    fn cipher(&self, id: u32) -> Result<&Aes256GcmSiv, HikvError> {
+        self.keys
+            .get(&id)
+            .ok_or_else(|| HikvError::EncryptionError(format!("unknown key id {}", id)))
+    }
+
+    /// 用当前密钥和随机 nonce 加密 value，key 名作为附加数据，密文不能挪到别的 key 下
+    fn seal(&self, key: &str, value: &Value) -> Result<Value, HikvError> {
+        let nonce = Aes256GcmSiv::generate_nonce(&mut OsRng);
+        let plain = encode_value(value)?;
+        let sealed = self
+            .cipher(self.current)?
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plain,
+                    aad: key.as_bytes(),
+                },
+            )
+            .map_err(|_| HikvError::EncryptionError(format!("failed to encrypt {}", key)))?;
+
+        let mut buf = Vec::with_capacity(RECORD_HEADER_LEN + sealed.len());
+        buf.put_u8(RECORD_VERSION);
+        buf.put_u32(self.current);
+        buf.put_slice(&nonce[..]);
+        buf.put_slice(&sealed);
+        Ok(Bytes::from(buf).into())
+    }
+
+    /// 解密记录，同时返回加密它的密钥 id
+    fn open(&self, key: &str, value: Value) -> Result<(Value, u32), HikvError> {
+        let data = Bytes::try_from(value)?;
+        let id = record_key_id(&data)
+            .ok_or_else(|| HikvError::EncryptionError(format!("{} is not encrypted", key)))?;
+        let plain = self
+            .cipher(id)?
+            .decrypt(
+                Nonce::from_slice(&data[5..RECORD_HEADER_LEN]),
+                Payload {
+                    msg: &data[RECORD_HEADER_LEN..],
+                    aad: key.as_bytes(),
+                },
+            )
+            .map_err(|_| {
+                HikvError::EncryptionError(format!("failed to decrypt {} with key {}", key, id))
+            })?;
+        Ok((decode_value(&plain)?, id))
+    }
+
+    /// 确定性加密 key 名
+    fn seal_name(&self, name: &str) -> Result<String, HikvError> {
+        let sealed = self
+            .cipher(self.first)?
+            .encrypt(
+                Nonce::from_slice(&NAME_NONCE),
+                Payload {
+                    msg: name.as_bytes(),
+                    aad: NAME_AAD,
+                },
+            )
+            .map_err(|_| HikvError::EncryptionError(format!("failed to encrypt key {}", name)))?;
+        Ok(BASE64_URL.encode(sealed))
+    }
+
+    fn open_name(&self, sealed: &str) -> Result<String, HikvError> {
+        let invalid = || HikvError::EncryptionError(format!("invalid encrypted key {}", sealed));
+        let data = BASE64_URL.decode(sealed).map_err(|_| invalid())?;
+        let plain = self
+            .cipher(self.first)?
+            .decrypt(
+                Nonce::from_slice(&NAME_NONCE),
+                Payload {
+                    msg: &data,
+                    aad: NAME_AAD,
+                },
+            )
+            .map_err(|_| invalid())?;
+        String::from_utf8(plain).map_err(|_| invalid())
+    }
+}
+
+/// 读取加密记录头部中的密钥 id
+fn record_key_id(data: &[u8]) -> Option<u32> {
+    if data.len() < RECORD_HEADER_LEN || data[0] != RECORD_VERSION {
+        return None;
+    }
+    Some(u32::from_be_bytes(data[1..5].try_into().unwrap()))
+}
+
+/// 轮换进度
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RotationReport {
+    /// 用当前密钥重新加密的记录数
+    pub reencrypted: usize,
+    /// 重新加密前被并发修改而跳过的记录数，修改时已使用当前密钥
+    pub skipped: usize,
+}
+
+/// 透明加密任意存储中的 value，可选确定性加密 key 名
+///
+/// 每条记录保存加密它的密钥 id；在密钥文件末尾追加新密钥后调用 [`rotate`](Self::rotate)，
+/// 后台线程会用新密钥重新加密旧记录，重新加密会让记录的 version 加 1。
+/// key 名固定使用密钥文件中的第一个密钥加密，轮换时保持不变，因此第一个密钥不能删除；
+/// 加密 key 名后 get_iter 需要在内存中重新排序
+pub struct EncryptedStorage<S: Storage> {
+    inner: Arc<EncryptedInner<S>>,
+}
+
+struct EncryptedInner<S: Storage> {
+    store: S,
+    key_file: PathBuf,
+    /// 读写时只克隆 Arc，轮换时整体替换
+    keys: RwLock<Arc<KeyRing>>,
+    encrypt_keys: bool,
+    rotating: AtomicBool,
+}
+
+impl<S> EncryptedStorage<S>
+where
+    S: Storage + Send + Sync + 'static,
+{
+    /// 读取密钥文件，encrypt_keys 为 true 时 key 名也加密
+    pub fn open(
+        store: S,
+        key_file: impl Into<PathBuf>,
+        encrypt_keys: bool,
+    ) -> Result<Self, HikvError> {
+        let key_file = key_file.into();
+        let keys = KeyRing::load(&key_file)?;
+        Ok(Self {
+            inner: Arc::new(EncryptedInner {
+                store,
+                key_file,
+                keys: RwLock::new(Arc::new(keys)),
+                encrypt_keys,
+                rotating: AtomicBool::new(false),
+            }),
+        })
+    }
+
+    /// 当前密钥 id
+    pub fn key_id(&self) -> u32 {
+        self.inner.keys.read().unwrap().current()
+    }
+
+    /// 重新读取密钥文件，并在后台用当前密钥重新加密旧记录
+    ///
+    /// 返回新的当前密钥 id，上一次轮换还没结束时返回错误
+    pub fn rotate(&self) -> Result<u32, HikvError> {
+        let keys = KeyRing::load(&self.inner.key_file)?;
+        let first = self.inner.keys.read().unwrap().first;
+        if self.inner.encrypt_keys && keys.first != first {
+            return Err(HikvError::EncryptionError(format!(
+                "key {} is used to encrypt key names and must stay first in key file",
+                first
+            )));
+        }
+        if self.inner.rotating.swap(true, Ordering::AcqRel) {
+            return Err(HikvError::EncryptionError(
+                "key rotation already in progress".into(),
+            ));
+        }
+
+        let current = keys.current();
+        *self.inner.keys.write().unwrap() = Arc::new(keys);
+        spawn_rotation(Arc::downgrade(&self.inner));
+        Ok(current)
+    }
+
+    /// 等待后台轮换结束
+    pub fn wait_rotation(&self, timeout: Duration) -> bool {
+        let step = Duration::from_millis(10);
+        let mut waited = Duration::ZERO;
+        while self.inner.rotating.load(Ordering::Acquire) {
+            if waited >= timeout {
+                return false;
+            }
+            thread::sleep(step);
+            waited += step;
+        }
+        true
+    }
+
+    /// 同步把不是用当前密钥加密的记录重新加密
+    pub fn reencrypt(&self) -> Result<RotationReport, HikvError> {
+        self.inner.reencrypt()
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner.store
+    }
+}
+
+impl<S: Storage> EncryptedInner<S> {
+    fn keys(&self) -> Arc<KeyRing> {
+        self.keys.read().unwrap().clone()
+    }
+
+    /// 底层存储中的 key 名
+    fn name(&self, keys: &KeyRing, key: &str) -> Result<String, HikvError> {
+        if self.encrypt_keys {
+            keys.seal_name(key)
+        } else {
+            Ok(key.into())
+        }
+    }
+
+    fn open(
+        &self,
+        keys: &KeyRing,
+        key: &str,
+        value: Option<Value>,
+    ) -> Result<Option<Value>, HikvError> {
+        value.map(|v| keys.open(key, v).map(|(v, _)| v)).transpose()
+    }
+
+    fn reencrypt(&self) -> Result<RotationReport, HikvError> {
+        let keys = self.keys();
+        let current = keys.current();
+        // 先找出旧记录再逐个重写，避免遍历时持有底层存储的锁
+        let stale: Vec<String> = self
+            .store
+            .get_iter()?
+            .filter_map(|pair| match pair {
+                Ok(pair) => {
+                    let id = pair
+                        .value
+                        .and_then(|v| Bytes::try_from(v).ok())
+                        .and_then(|data| record_key_id(&data));
+                    (id != Some(current)).then_some(Ok(pair.key))
+                }
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut report = RotationReport::default();
+        for name in stale {
+            let (value, meta) = match self.store.get_with_meta(&name)? {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let key = if self.encrypt_keys {
+                keys.open_name(&name)?
+            } else {
+                name.clone()
+            };
+            let (value, id) = keys.open(&key, value)?;
+            if id == current {
+                continue;
+            }
+            let sealed = keys.seal(&key, &value)?;
+            match self.store.set_if_version(name, sealed, meta.version) {
+                Ok(_) => report.reencrypted += 1,
+                Err(HikvError::VersionMismatch(..)) => report.skipped += 1,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// 后台重新加密，存储被 drop 后放弃
+fn spawn_rotation<S>(inner: Weak<EncryptedInner<S>>)
+where
+    S: Storage + Send + Sync + 'static,
+{
+    thread::spawn(move || {
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        // 重新加密中途 panic 也要清除标记，否则之后无法再轮换
+        let _rotating = Rotating(&inner.rotating);
+        match inner.reencrypt() {
+            Ok(report) => info!(
+                "Key rotation finished: {} re-encrypted, {} skipped",
+                report.reencrypted, report.skipped
+            ),
+            Err(e) => error!("Key rotation failed: {}", e),
+        }
+    });
+}
+
+/// drop 时清除轮换标记
+struct Rotating<'a>(&'a AtomicBool);
+
+impl Drop for Rotating<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl<S: Storage> Storage for EncryptedStorage<S> {
+    fn set(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, HikvError> {
+        let (key, value) = (key.into(), value.into());
+        let inner = &self.inner;
+        let keys = inner.keys();
+        let old = inner
+            .store
+            .set(inner.name(&keys, &key)?, keys.seal(&key, &value)?)?;
+        inner.open(&keys, &key, old)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Value>, HikvError> {
+        let inner = &self.inner;
+        let keys = inner.keys();
+        let value = inner.store.get(&inner.name(&keys, key)?)?;
+        inner.open(&keys, key, value)
+    }
+
+    fn get_with_meta(&self, key: &str) -> Result<Option<(Value, KeyMeta)>, HikvError> {
+        let inner = &self.inner;
+        let keys = inner.keys();
+        match inner.store.get_with_meta(&inner.name(&keys, key)?)? {
+            Some((value, meta)) => Ok(Some((keys.open(key, value)?.0, meta))),
+            None => Ok(None),
+        }
+    }
+
+    fn set_if_version(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        expected: u64,
+    ) -> Result<Option<Value>, HikvError> {
+        let (key, value) = (key.into(), value.into());
+        let inner = &self.inner;
+        let keys = inner.keys();
+        let old = inner.store.set_if_version(
+            inner.name(&keys, &key)?,
+            keys.seal(&key, &value)?,
+            expected,
+        );
+        // 版本不匹配的错误中带的是底层存储的 key 名
+        match old {
+            Err(HikvError::VersionMismatch(_, expected, actual)) => {
+                Err(HikvError::VersionMismatch(key, expected, actual))
+            }
+            old => inner.open(&keys, &key, old?),
+        }
+    }
+
+    fn meta(&self, key: &str) -> Result<Option<KeyMeta>, HikvError> {
+        let inner = &self.inner;
+        inner.store.meta(&inner.name(&inner.keys(), key)?)
+    }
+
+    fn del(&self, key: &str) -> Result<Option<Value>, HikvError> {
+        let inner = &self.inner;
+        let keys = inner.keys();
+        let old = inner.store.del(&inner.name(&keys, key)?)?;
+        inner.open(&keys, key, old)
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, HikvError> {
+        let inner = &self.inner;
+        inner.store.contains(&inner.name(&inner.keys(), key)?)
+    }
+
+    fn get_iter(&self) -> Result<StorageIter<'_>, HikvError> {
+        let keys = self.inner.keys();
+        let encrypt_keys = self.inner.encrypt_keys;
+        let iter = self.inner.store.get_iter()?.map(move |pair| {
+            let pair = pair?;
+            let key = if encrypt_keys {
+                keys.open_name(&pair.key)?
+            } else {
+                pair.key
+            };
+            let value = keys.open(&key, pair.value.unwrap_or_default())?.0;
+            Ok(Kvpair::new(key, value))
+        });
+        if !encrypt_keys {
+            return Ok(Box::new(iter));
+        }
+        // 密文的顺序和 key 名无关，只能整体排序
+        let mut pairs = iter.collect::<Result<Vec<_>, HikvError>>()?;
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(Box::new(pairs.into_iter().map(Ok)))
+    }
+
+    fn set_all(&self, pairs: Vec<Kvpair>) -> Result<(), HikvError> {
+        let inner = &self.inner;
+        let keys = inner.keys();
+        let pairs = pairs
+            .into_iter()
+            .map(|pair| {
+                let value = keys.seal(&pair.key, &pair.value.unwrap_or_default())?;
+                Ok(Kvpair::new(inner.name(&keys, &pair.key)?, value))
+            })
+            .collect::<Result<_, HikvError>>()?;
+        inner.store.set_all(pairs)
+    }
+
+    fn flush(&self) -> Result<(), HikvError> {
+        self.inner.store.flush()
+    }
+
+    fn compact(&self) -> Result<(), HikvError> {
+        self.inner.store.compact()
+    }
+
+    /// 底层存储的统计信息加上当前密钥 id，字节数按密文计算
+    fn stats(&self) -> Result<StorageStats, HikvError> {
+        let inner = &self.inner;
+        Ok(inner
+            .store
+            .stats()?
+            .with("encryption.key_id", inner.keys().current() as i64)
+            .with(
+                "encryption.rotating",
+                inner.rotating.load(Ordering::Acquire),
+            ))
+    }
+
+    /// 备份中保存的是密文，恢复时需要同一个密钥文件
+    fn backup(&self, path: &Path, incremental: bool) -> Result<BackupManifest, HikvError> {
+        self.inner.store.backup(path, incremental)
+    }
+
+    fn create_snapshot(&self, timeout: Duration) -> Result<u64, HikvError> {
+        self.inner.store.create_snapshot(timeout)
+    }
+
+    fn get_at(&self, snapshot: u64, key: &str) -> Result<Option<Value>, HikvError> {
+        let inner = &self.inner;
+        let keys = inner.keys();
+        let value = inner.store.get_at(snapshot, &inner.name(&keys, key)?)?;
+        inner.open(&keys, key, value)
+    }
+
+    fn release_snapshot(&self, snapshot: u64) -> Result<bool, HikvError> {
+        self.inner.store.release_snapshot(snapshot)
+    }
+
+    fn save(&self) -> Result<usize, HikvError> {
+        self.inner.store.save()
+    }
+
+    fn bg_save(&self) -> Result<(), HikvError> {
+        self.inner.store.bg_save()
+    }
+
+    fn evicted(&self) -> u64 {
+        self.inner.store.evicted()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+    use crate::MemTable;
+
+    fn key_file() -> (TempDir, PathBuf) {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("keys");
+        KeyRing::generate(&path, 1).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn values_should_be_encrypted_at_rest() {
+        let (_dir, path) = key_file();
+        let store = EncryptedStorage::open(MemTable::new(), &path, false).unwrap();
+        store.set("ssn", "123-45-6789").unwrap();
+
+        assert_eq!(store.get("ssn").unwrap(), Some("123-45-6789".into()));
+        let raw = Bytes::try_from(store.inner().get("ssn").unwrap().unwrap()).unwrap();
+        assert_eq!(record_key_id(&raw), Some(1));
+        assert!(!raw.windows(11).any(|w| w == b"123-45-6789"));
+
+        // 密文挪到别的 key 下无法解密
+        store.inner().set("copy", Value::from(raw)).unwrap();
+        assert!(matches!(
+            store.get("copy").unwrap_err(),
+            HikvError::EncryptionError(_)
+        ));
+    }
+
+    #[test]
+    fn encrypted_keys_should_support_point_lookups() {
+        let (_dir, path) = key_file();
+        let store = EncryptedStorage::open(MemTable::new(), &path, true).unwrap();
+        store.set("user:2", "bob").unwrap();
+        store.set("user:1", "alice").unwrap();
+
+        assert_eq!(store.get("user:1").unwrap(), Some("alice".into()));
+        assert!(store.inner().get("user:1").unwrap().is_none());
+        let keys: Vec<_> = store.get_iter().unwrap().map(|p| p.unwrap().key).collect();
+        assert_eq!(keys, ["user:1", "user:2"]);
+    }
+
+    #[test]
+    fn rotate_should_reencrypt_old_records() {
+        let (_dir, path) = key_file();
+        let store = EncryptedStorage::open(MemTable::new(), &path, true).unwrap();
+        for i in 0..20 {
+            store.set(format!("k{}", i), Value::from(i)).unwrap();
+        }
+
+        KeyRing::generate(&path, 2).unwrap();
+        assert_eq!(store.rotate().unwrap(), 2);
+        assert!(store.wait_rotation(Duration::from_secs(5)));
+
+        for pair in store.inner().get_iter().unwrap() {
+            let data = Bytes::try_from(pair.unwrap().value.unwrap()).unwrap();
+            assert_eq!(record_key_id(&data), Some(2));
+        }
+        assert_eq!(store.get("k7").unwrap(), Some(7.into()));
+        assert_eq!(store.reencrypt().unwrap(), RotationReport::default());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn generated_key_file_should_be_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (dir, path) = key_file();
+        KeyRing::generate(&path, 2).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(KeyRing::load(&path).unwrap().current(), 2);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn rotating_flag_should_reset_on_panic() {
+        let flag = AtomicBool::new(true);
+        let ret = std::panic::catch_unwind(|| {
+            let _rotating = Rotating(&flag);
+            panic!("boom");
+        });
+        assert!(ret.is_err());
+        assert!(!flag.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn invalid_key_file_should_be_rejected() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("keys");
+        fs::write(&path, "# no keys\n").unwrap();
+        assert!(EncryptedStorage::open(MemTable::new(), &path, false).is_err());
+        fs::write(&path, "1 c2hvcnQ=\n").unwrap();
+        assert!(EncryptedStorage::open(MemTable::new(), &path, false).is_err());
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, RwLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use tokio::runtime::RuntimeFlavor;
+use tracing::debug;
+
+use crate::{
+    BackupManifest, HikvError, KeyMeta, Kvpair, Storage, StorageIter, StorageStats, Value,
+};
+
+/// 可以注入故障的存储操作
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageOp {
+    /// get、get_with_meta、meta、get_at
+    Get,
+    /// set、set_if_version
+    Set,
+    Del,
+    Contains,
+    Iter,
+    SetAll,
+    Flush,
+    Compact,
+    Stats,
+    Backup,
+    /// create_snapshot、release_snapshot
+    Snapshot,
+    /// save、bg_save
+    Save,
+}
+
+/// 注入的错误类型
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FaultKind {
+    /// 普通 I/O 错误
+    Io,
+    /// 磁盘已满(ENOSPC)
+    DiskFull,
+    /// sled 返回的 I/O 错误
+    #[cfg(feature = "sled")]
+    Sled,
+    /// 内部错误，附带错误信息
+    Internal(String),
+}
+
+/// 匹配到规则时注入的故障
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// 不执行操作，直接返回错误
+    Error(FaultKind),
+    /// 延迟后正常执行。Storage 是同步接口，延迟会阻塞调用线程；
+    /// 在多线程 tokio runtime 中通过 block_in_place 让出 worker，单线程 runtime 会被整体阻塞
+    Latency(Duration),
+    /// 执行后返回错误: 单个写入已生效，set_all 只写入前一半，遍历只返回前一半
+    Partial(FaultKind),
+}
+
+/// 触发方式
+#[derive(Clone, Debug, PartialEq)]
+pub enum Trigger {
+    /// 每次匹配都触发
+    Always,
+    /// 每次匹配按概率触发
+    Probability(f64),
+    /// 第 n 次匹配时触发，n 从 1 开始
+    Calls(Vec<u64>),
+}
+
+/// 故障规则，默认匹配所有操作和 key，每次都触发
+#[derive(Debug)]
+pub struct FaultRule {
+    op: Option<StorageOp>,
+    /// key 匹配模式，`*` 匹配任意字符串
+    pattern: Option<String>,
+    trigger: Trigger,
+    fault: Fault,
+    /// 已匹配的次数
+    calls: AtomicU64,
+}
+
+impl FaultRule {
+    pub fn new(fault: Fault) -> Self {
+        Self {
+            op: None,
+            pattern: None,
+            trigger: Trigger::Always,
+            fault,
+            calls: AtomicU64::new(0),
+        }
+    }
+
+    /// 只对指定操作生效
+    pub fn op(mut self, op: StorageOp) -> Self {
+        self.op = Some(op);
+        self
+    }
+
+    /// 只对匹配的 key 生效，没有 key 的操作(遍历、flush 等)不会匹配
+    pub fn key(mut self, pattern: impl Into<String>) -> Self {
+        self.pattern = Some(pattern.into());
+        self
+    }
+
+    pub fn probability(mut self, p: f64) -> Self {
+        self.trigger = Trigger::Probability(p);
+        self
+    }
+
+    /// 按脚本在第 n 次匹配时触发
+    pub fn calls(mut self, calls: impl Into<Vec<u64>>) -> Self {
+        self.trigger = Trigger::Calls(calls.into());
+        self
+    }
+
+    fn matches(&self, op: StorageOp, key: Option<&str>) -> bool {
+        if matches!(self.op, Some(o) if o != op) {
+            return false;
+        }
+        match (&self.pattern, key) {
+            (None, _) => true,
+            (Some(pattern), Some(key)) => glob_match(pattern.as_bytes(), key.as_bytes()),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// 按规则注入错误、延迟和部分失败的存储包装，用于测试错误处理路径
+///
+/// 概率触发使用固定种子，相同的调用顺序得到相同的结果
+pub struct FaultyStorage<S> {
+    inner: S,
+    rules: RwLock<Vec<FaultRule>>,
+    rng: Mutex<fastrand::Rng>,
+    /// 累计注入的故障数量
+    injected: AtomicU64,
+}
+
+impl<S: Storage> FaultyStorage<S> {
+    pub fn new(inner: S, seed: u64) -> Self {
+        Self {
+            inner,
+            rules: RwLock::new(Vec::new()),
+            rng: Mutex::new(fastrand::Rng::with_seed(seed)),
+            injected: AtomicU64::new(0),
+        }
+    }
+
+    pub fn rule(self, rule: FaultRule) -> Self {
+        self.add_rule(rule);
+        self
+    }
+
+    /// 运行中添加规则
+    pub fn add_rule(&self, rule: FaultRule) {
+        self.rules.write().unwrap().push(rule);
+    }
+
+    /// 清除所有规则
+    pub fn clear(&self) {
+        self.rules.write().unwrap().clear();
+    }
+
+    pub fn injected(&self) -> u64 {
+        self.injected.load(Ordering::Relaxed)
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// 找到第一条匹配并触发的规则，延迟故障在这里直接执行
+    fn check(&self, op: StorageOp, key: Option<&str>) -> Option<Fault> {
+        let rules = self.rules.read().unwrap();
+        for rule in rules.iter().filter(|r| r.matches(op, key)) {
+            let n = rule.calls.fetch_add(1, Ordering::Relaxed) + 1;
+            let fire = match &rule.trigger {
+                Trigger::Always => true,
+                Trigger::Probability(p) => self.rng.lock().unwrap().f64() < *p,
+                Trigger::Calls(calls) => calls.contains(&n),
+            };
+            if !fire {
+                continue;
+            }
+            self.injected.fetch_add(1, Ordering::Relaxed);
+            debug!("Inject {:?} into {:?} {:?}", rule.fault, op, key);
+            if let Fault::Latency(d) = rule.fault {
+                sleep(d);
+                return None;
+            }
+            return Some(rule.fault.clone());
+        }
+        None
+    }
+
+    /// 执行单个操作，Partial 故障在执行后返回错误
+    fn run<T>(
+        &self,
+        op: StorageOp,
+        key: Option<&str>,
+        f: impl FnOnce(&S) -> Result<T, HikvError>,
+    ) -> Result<T, HikvError> {
+        match self.check(op, key) {
+            Some(Fault::Error(kind)) => Err(kind.into()),
+            Some(Fault::Partial(kind)) => {
+                f(&self.inner)?;
+                Err(kind.into())
+            }
+            _ => f(&self.inner),
+        }
+    }
+}
+
+impl From<FaultKind> for HikvError {
+    fn from(kind: FaultKind) -> Self {
+        match kind {
+            FaultKind::Io => io::Error::other("injected I/O error").into(),
+            FaultKind::DiskFull => io::Error::from_raw_os_error(28).into(),
+            #[cfg(feature = "sled")]
+            FaultKind::Sled => sled::Error::Io(io::Error::other("injected sled I/O error")).into(),
+            FaultKind::Internal(msg) => HikvError::Internal(msg),
+        }
+    }
+}
+
+impl<S: Storage> Storage for FaultyStorage<S> {
+    fn set(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, HikvError> {
+        let key = key.into();
+        self.run(StorageOp::Set, Some(&key), |s| s.set(key.clone(), value))
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Value>, HikvError> {
+        self.run(StorageOp::Get, Some(key), |s| s.get(key))
+    }
+
+    fn get_with_meta(&self, key: &str) -> Result<Option<(Value, KeyMeta)>, HikvError> {
+        self.run(StorageOp::Get, Some(key), |s| s.get_with_meta(key))
+    }
+
+    fn set_if_version(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        expected: u64,
+    ) -> Result<Option<Value>, HikvError> {
+        let key = key.into();
+        self.run(StorageOp::Set, Some(&key), |s| {
+            s.set_if_version(key.clone(), value, expected)
+        })
+    }
+
+    fn meta(&self, key: &str) -> Result<Option<KeyMeta>, HikvError> {
+        self.run(StorageOp::Get, Some(key), |s| s.meta(key))
+    }
+
+    fn del(&self, key: &str) -> Result<Option<Value>, HikvError> {
+        self.run(StorageOp::Del, Some(key), |s| s.del(key))
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, HikvError> {
+        self.run(StorageOp::Contains, Some(key), |s| s.contains(key))
+    }
+
+    fn get_iter(&self) -> Result<StorageIter<'_>, HikvError> {
+        match self.check(StorageOp::Iter, None) {
+            Some(Fault::Error(kind)) => Err(kind.into()),
+            Some(Fault::Partial(kind)) => {
+                let mut items: Vec<_> = self.inner.get_iter()?.collect();
+                items.truncate(items.len() / 2);
+                items.push(Err(kind.into()));
+                Ok(Box::new(items.into_iter()))
+            }
+            _ => self.inner.get_iter(),
+        }
+    }
+
+    fn set_all(&self, mut pairs: Vec<Kvpair>) -> Result<(), HikvError> {
+        match self.check(StorageOp::SetAll, None) {
+            Some(Fault::Error(kind)) => Err(kind.into()),
+            Some(Fault::Partial(kind)) => {
+                pairs.truncate(pairs.len() / 2);
+                self.inner.set_all(pairs)?;
+                Err(kind.into())
+            }
+            _ => self.inner.set_all(pairs),
+        }
+    }
+
+    fn flush(&self) -> Result<(), HikvError> {
+        self.run(StorageOp::Flush, None, |s| s.flush())
+    }
+
+    fn compact(&self) -> Result<(), HikvError> {
+        self.run(StorageOp::Compact, None, |s| s.compact())
+    }
+
+    fn stats(&self) -> Result<StorageStats, HikvError> {
+        self.run(StorageOp::Stats, None, |s| s.stats())
+    }
+
+    fn backup(&self, path: &Path, incremental: bool) -> Result<BackupManifest, HikvError> {
+        self.run(StorageOp::Backup, None, |s| s.backup(path, incremental))
+    }
+
+    fn create_snapshot(&self, timeout: Duration) -> Result<u64, HikvError> {
+        self.run(StorageOp::Snapshot, None, |s| s.create_snapshot(timeout))
+    }
+
+    fn get_at(&self, snapshot: u64, key: &str) -> Result<Option<Value>, HikvError> {
+        self.run(StorageOp::Get, Some(key), |s| s.get_at(snapshot, key))
+    }
+
+    fn release_snapshot(&self, snapshot: u64) -> Result<bool, HikvError> {
+        self.run(StorageOp::Snapshot, None, |s| s.release_snapshot(snapshot))
+    }
+
+    fn save(&self) -> Result<usize, HikvError> {
+        self.run(StorageOp::Save, None, |s| s.save())
+    }
+
+    fn bg_save(&self) -> Result<(), HikvError> {
+        self.run(StorageOp::Save, None, |s| s.bg_save())
+    }
+
+    fn evicted(&self) -> u64 {
+        self.inner.evicted()
+    }
+}
+
+/// 简单的通配符匹配，只支持 `*`
+fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some((b'*', rest)) => (0..=s.len()).any(|i| glob_match(rest, &s[i..])),
+        Some((c, rest)) => s.first() == Some(c) && glob_match(rest, &s[1..]),
+    }
+}
+
+/// 阻塞当前线程 d，在多线程 runtime 的 worker 上先把其他任务移走
+fn sleep(d: Duration) {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| thread::sleep(d))
+        }
+        _ => thread::sleep(d),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemTable;
+
+    #[test]
+    fn glob_match_should_work() {
+        assert!(glob_match(b"user:*", b"user:1"));
+        assert!(glob_match(b"*:name", b"user:name"));
+        assert!(glob_match(b"a*c*e", b"abcde"));
+        assert!(!glob_match(b"user:*", b"order:1"));
+        assert!(!glob_match(b"abc", b"abcd"));
+    }
+
+    #[test]
+    fn faults_should_match_op_and_key() {
+        let store = FaultyStorage::new(MemTable::new(), 1).rule(
+            FaultRule::new(Fault::Error(FaultKind::DiskFull))
+                .op(StorageOp::Set)
+                .key("user:*"),
+        );
+        store.set("order:1", "ok").unwrap();
+        let err = store.set("user:1", "tom").unwrap_err();
+        assert!(matches!(err, HikvError::IoError(e) if e.raw_os_error() == Some(28)));
+        assert_eq!(store.get("user:1").unwrap(), None);
+        assert_eq!(store.injected(), 1);
+
+        store.clear();
+        store.set("user:1", "tom").unwrap();
+    }
+
+    #[test]
+    fn partial_faults_should_apply_part_of_the_work() {
+        let store = FaultyStorage::new(MemTable::new(), 1)
+            .rule(FaultRule::new(Fault::Partial(FaultKind::Io)).op(StorageOp::Set))
+            .rule(
+                FaultRule::new(Fault::Partial(FaultKind::Internal("partial".into())))
+                    .op(StorageOp::SetAll),
+            );
+
+        assert!(store.set("hello", "world").is_err());
+        assert_eq!(store.get("hello").unwrap(), Some("world".into()));
+
+        let pairs = (0..4).map(|i| Kvpair::new(format!("k{}", i), i.into()));
+        let err = store.set_all(pairs.collect()).unwrap_err();
+        assert!(matches!(err, HikvError::Internal(_)));
+        assert_eq!(store.get("k1").unwrap(), Some(1.into()));
+        assert_eq!(store.get("k2").unwrap(), None);
+    }
+
+    #[test]
+    fn scripted_and_seeded_faults_should_be_deterministic() {
+        let store = FaultyStorage::new(MemTable::new(), 1).rule(
+            FaultRule::new(Fault::Error(FaultKind::Internal("boom".into())))
+                .op(StorageOp::Get)
+                .calls([2, 3]),
+        );
+        let ret: Vec<bool> = (0..4).map(|_| store.get("k").is_ok()).collect();
+        assert_eq!(ret, [true, false, false, true]);
+
+        let run = |seed| {
+            let store = FaultyStorage::new(MemTable::new(), seed)
+                .rule(FaultRule::new(Fault::Error(FaultKind::Io)).probability(0.5));
+            (0..32).map(|_| store.get("k").is_ok()).collect::<Vec<_>>()
+        };
+        assert_eq!(run(42), run(42));
+        assert!(run(42).contains(&true) && run(42).contains(&false));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn latency_should_not_starve_other_tasks() {
+        let store = FaultyStorage::new(MemTable::new(), 1)
+            .rule(FaultRule::new(Fault::Latency(Duration::from_millis(200))));
+        let (tx, rx) = std::sync::mpsc::channel();
+        tokio::spawn(async move { tx.send(()).unwrap() });
+        // 延迟期间唯一的 worker 被让出，另一个任务仍能执行
+        assert!(store.get("k").is_ok());
+        assert!(rx.try_recv().is_ok());
+    }
+}
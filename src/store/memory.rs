@@ -1,9 +1,124 @@
-use crate::{HikvError, Storage, Value};
-use dashmap::DashMap;
+use crate::{
+    decode_value, read_snapshot, write_snapshot, BackupManifest, CompressOptions, Compression,
+    Compressor, Digest, HikvError, History, KeyMeta, Kvpair, RecordHeader, Storage, StorageIter,
+    StorageKind, StorageStats, Value, VersionClock,
+};
+use bytes::Bytes;
+use dashmap::{mapref::entry::Entry, DashMap};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::Duration,
+};
+use tracing::{error, info};
 
-#[derive(Clone, Debug, Default)]
+/// 每次淘汰时随机采样的 key 数量
+const EVICTION_SAMPLES: usize = 5;
+/// 每个 entry 除 key/value 外的额外开销估算
+const ENTRY_OVERHEAD: usize = 64;
+/// 有内存上限时每个 key 在索引中的额外开销估算，key 本身在索引中只保存一份
+const INDEX_OVERHEAD: usize = 48;
+/// 新 key 的初始 LFU 计数，避免刚写入就被淘汰
+const LFU_INIT: u8 = 5;
+/// LFU 对数计数因子，越大计数增长越慢
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// 保存快照时读视图的超时时间，保存结束后立即释放
+const SAVE_VIEW_TIMEOUT: Duration = Duration::from_secs(24 * 3600);
+
+/// 内存超过上限时的淘汰策略
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    /// 不淘汰，超过上限时拒绝写入
+    #[default]
+    NoEviction,
+    /// 近似 LRU: 在采样的 key 中淘汰最久未访问的
+    AllKeysLru,
+    /// 近似 LFU: 在采样的 key 中淘汰访问频率最低的
+    AllKeysLfu,
+    /// 随机淘汰
+    AllKeysRandom,
+}
+
+#[derive(Debug, Default)]
 pub struct MemTable {
-    innner: DashMap<String, Value>,
+    innner: Arc<DashMap<String, Slot>>,
+    /// 快照文件路径
+    snapshot: Option<PathBuf>,
+    /// 写操作持有读锁，生成快照时持有写锁，保证快照是同一时刻的数据
+    barrier: Arc<RwLock<()>>,
+    /// 是否有后台快照正在进行
+    saving: Arc<AtomicBool>,
+    /// 内存上限，None 表示不限制
+    limit: Option<MemoryLimit>,
+    /// 当前占用内存的估算值
+    used: AtomicUsize,
+    /// 所有 slot 的 size 之和，用于统计 key 和 value 的字节数
+    data: AtomicUsize,
+    /// 累计淘汰的 key 数量
+    evicted: AtomicU64,
+    /// 逻辑时钟，用于记录访问先后
+    clock: AtomicU64,
+    /// 分配写入的版本号
+    versions: VersionClock,
+    /// 读视图及有读视图期间被覆盖的旧值，快照读和保存快照共用，后台保存线程也会持有
+    history: Arc<History<OldValue>>,
+    /// value 压缩，None 表示不压缩
+    compression: Option<Compressor>,
+}
+
+/// 写入前的 value 和元数据，key 不存在时为 None
+type OldValue = Option<(Value, KeyMeta)>;
+
+/// 保存快照期间持有的读视图，drop 时释放
+struct SaveView {
+    id: u64,
+    seq: u64,
+    barrier: Arc<RwLock<()>>,
+    history: Arc<History<OldValue>>,
+}
+
+/// 保存的 value 及其访问信息
+#[derive(Debug)]
+struct Slot {
+    value: Stored,
+    meta: KeyMeta,
+    size: usize,
+    /// 最近一次访问的逻辑时间
+    access: AtomicU64,
+    /// 对数访问计数
+    freq: AtomicU8,
+}
+
+/// slot 中的 value，开启压缩时超过阈值的 value 保存为压缩后的编码
+#[derive(Clone, Debug)]
+enum Stored {
+    Plain(Value),
+    Compressed(Bytes),
+}
+
+#[derive(Debug)]
+struct MemoryLimit {
+    max: usize,
+    policy: EvictionPolicy,
+    /// 所有 key 的索引，用于随机采样。和 map 的 shard 一一对应，在 shard 锁内更新
+    keys: Vec<Mutex<KeyIndex>>,
+    /// 每个索引中的 key 数量，采样时不需要逐个加锁
+    lens: Vec<AtomicUsize>,
+}
+
+/// 支持 O(1) 随机采样的 key 集合
+#[derive(Debug, Default)]
+struct KeyIndex {
+    keys: Vec<Arc<str>>,
+    pos: HashMap<Arc<str>, usize>,
 }
 
 impl MemTable {
@@ -11,29 +126,859 @@ impl MemTable {
         Self::default()
     }
 
+    /// 使用快照文件创建 MemTable，文件存在时先从中恢复数据
+    pub fn with_snapshot(path: impl AsRef<Path>) -> Result<Self, HikvError> {
+        let path = path.as_ref();
+        let table = Self {
+            snapshot: Some(path.to_path_buf()),
+            ..Default::default()
+        };
+
+        if path.exists() {
+            let entries = read_snapshot(path)?;
+            info!("Restored {} keys from snapshot {:?}", entries.len(), path);
+            for (k, v, meta) in entries {
+                table.versions.observe(meta.version);
+                let slot = Slot::new(&k, Stored::Plain(v), meta, 0);
+                table.add_slot(slot.size);
+                table.innner.insert(k, slot);
+            }
+        }
+
+        Ok(table)
+    }
+
+    /// 设置内存上限及淘汰策略，已有数据超过上限时立即淘汰
+    pub fn max_memory(mut self, max: usize, policy: EvictionPolicy) -> Self {
+        let mut keys: Vec<KeyIndex> = self
+            .innner
+            .shards()
+            .iter()
+            .map(|_| KeyIndex::default())
+            .collect();
+        for e in self.innner.iter() {
+            let added = keys[self.innner.determine_map(e.key())].insert(e.key());
+            *self.used.get_mut() += added;
+        }
+        self.limit = Some(MemoryLimit {
+            max,
+            policy,
+            lens: keys
+                .iter()
+                .map(|k| AtomicUsize::new(k.keys.len()))
+                .collect(),
+            keys: keys.into_iter().map(Mutex::new).collect(),
+        });
+        self.evict();
+        self
+    }
+
+    /// 开启 value 压缩，已有数据同时按新配置重新保存
+    pub fn compression(mut self, options: CompressOptions) -> Self {
+        let compressor = Compressor::new(options);
+        for mut e in self.innner.iter_mut() {
+            if let Ok(value) = Stored::new(e.value.get(), Some(&compressor)) {
+                let old = e.size;
+                e.size = e.key().len() + value.len() + ENTRY_OVERHEAD;
+                e.value = value;
+                *self.used.get_mut() = *self.used.get_mut() + e.size - old;
+                *self.data.get_mut() = *self.data.get_mut() + e.size - old;
+            }
+        }
+        self.compression = Some(compressor);
+        self
+    }
+
+    /// 当前占用内存的估算值，有内存上限时包含 key 索引
+    pub fn used_memory(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// 创建保存快照用的读视图，只在登记视图时短暂阻塞写操作
+    fn save_view(&self) -> SaveView {
+        let _guard = self.barrier.write().unwrap();
+        let (id, seq) = self.history.create(SAVE_VIEW_TIMEOUT);
+        SaveView {
+            id,
+            seq,
+            barrier: self.barrier.clone(),
+            history: self.history.clone(),
+        }
+    }
+
+    fn snapshot_path(&self) -> Result<&Path, HikvError> {
+        self.snapshot
+            .as_deref()
+            .ok_or_else(|| HikvError::SnapshotError("snapshot path is not configured".into()))
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// 写入 slot 并更新元数据，expected 为版本前置条件。有内存上限时同时维护 key 索引
+    fn insert(
+        &self,
+        key: String,
+        mut slot: Slot,
+        expected: Option<u64>,
+    ) -> Result<Option<Slot>, HikvError> {
+        if let Some(limit) = &self.limit {
+            // 新 key 同时占用索引
+            let (needed, freed) = match self.innner.get(&key) {
+                Some(old) => (slot.size, old.size),
+                None => (slot.size + KeyIndex::size(&key), 0),
+            };
+            if limit.policy == EvictionPolicy::NoEviction && needed > freed {
+                let used = self.used_memory();
+                if used + needed - freed > limit.max {
+                    return Err(HikvError::OutOfMemory(used, limit.max));
+                }
+            }
+        }
+
+        // 元数据和 key 索引都在 shard 锁内更新，保证和 map 一致
+        let size = slot.size;
+        let old = match self.innner.entry(key) {
+            Entry::Occupied(mut e) => {
+                slot.meta = self
+                    .versions
+                    .update(e.key(), Some(&e.get().meta), expected)?;
+                self.history
+                    .record(e.key(), || Some((e.get().value.get(), e.get().meta)));
+                Some(e.insert(slot))
+            }
+            Entry::Vacant(e) => {
+                slot.meta = self.versions.update(e.key(), None, expected)?;
+                self.history.record(e.key(), || None);
+                if let Some(limit) = &self.limit {
+                    let added = limit.insert(&self.innner, e.key());
+                    self.used.fetch_add(added, Ordering::Relaxed);
+                }
+                e.insert(slot);
+                None
+            }
+        };
+        self.add_slot(size);
+        if let Some(old) = &old {
+            self.sub_slot(old.size);
+        }
+        Ok(old)
+    }
+
+    /// slot 放入 map 后更新内存统计
+    fn add_slot(&self, size: usize) {
+        self.used.fetch_add(size, Ordering::Relaxed);
+        self.data.fetch_add(size, Ordering::Relaxed);
+    }
+
+    /// slot 移出 map 后更新内存统计
+    fn sub_slot(&self, size: usize) {
+        self.used.fetch_sub(size, Ordering::Relaxed);
+        self.data.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    fn put(
+        &self,
+        key: String,
+        value: Value,
+        expected: Option<u64>,
+    ) -> Result<Option<Value>, HikvError> {
+        let value = Stored::new(value, self.compression.as_ref())?;
+        let slot = Slot::new(&key, value, KeyMeta::default(), self.tick());
+        let old_value = {
+            let _guard = self.barrier.read().unwrap();
+            self.insert(key, slot, expected)?.map(|s| s.value.get())
+        };
+        self.evict();
+        Ok(old_value)
+    }
+
+    fn remove(&self, key: &str) -> Option<Slot> {
+        let removed = self.innner.remove_if(key, |k, slot| {
+            if let Some(limit) = &self.limit {
+                let removed = limit.remove(&self.innner, k);
+                self.used.fetch_sub(removed, Ordering::Relaxed);
+            }
+            self.history
+                .record(k, || Some((slot.value.get(), slot.meta)));
+            true
+        });
+        removed.map(|(_, slot)| {
+            self.sub_slot(slot.size);
+            slot
+        })
+    }
+
+    /// 超过内存上限时按策略淘汰，直到回到上限以内
+    fn evict(&self) {
+        let limit = match &self.limit {
+            Some(limit) if limit.policy != EvictionPolicy::NoEviction => limit,
+            _ => return,
+        };
+
+        while self.used_memory() > limit.max {
+            let candidates = limit.sample(EVICTION_SAMPLES);
+            let victim = match limit.policy {
+                EvictionPolicy::AllKeysRandom => candidates.into_iter().next(),
+                EvictionPolicy::AllKeysLru => {
+                    self.pick(candidates, |s| (s.access.load(Ordering::Relaxed), 0))
+                }
+                EvictionPolicy::AllKeysLfu => self.pick(candidates, |s| {
+                    let freq = s.freq.load(Ordering::Relaxed) as u64;
+                    (freq, s.access.load(Ordering::Relaxed))
+                }),
+                EvictionPolicy::NoEviction => unreachable!(),
+            };
+
+            match victim {
+                Some(key) => {
+                    let _guard = self.barrier.read().unwrap();
+                    if self.remove(&key).is_some() {
+                        self.evicted.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// 从候选 key 中选出 rank 最小的
+    fn pick(&self, candidates: Vec<String>, rank: impl Fn(&Slot) -> (u64, u64)) -> Option<String> {
+        candidates
+            .into_iter()
+            .filter_map(|k| {
+                let r = self.innner.get(&k).map(|s| rank(s.value()));
+                r.map(|r| (r, k))
+            })
+            .min_by_key(|(r, _)| *r)
+            .map(|(_, k)| k)
+    }
+
     // fn get_or_create(&self,name:&str)
 }
 
+/// 复制出独立的 MemTable，包含当前的数据和配置，不包含读视图和后台保存的状态
+impl Clone for MemTable {
+    fn clone(&self) -> Self {
+        let table = Self {
+            snapshot: self.snapshot.clone(),
+            compression: self
+                .compression
+                .as_ref()
+                .map(|c| Compressor::new(c.options())),
+            ..Default::default()
+        };
+        for e in self.innner.iter() {
+            let slot = e.value();
+            table.versions.observe(slot.meta.version);
+            table.add_slot(slot.size);
+            table.innner.insert(
+                e.key().clone(),
+                Slot {
+                    value: slot.value.clone(),
+                    meta: slot.meta,
+                    size: slot.size,
+                    access: AtomicU64::new(slot.access.load(Ordering::Relaxed)),
+                    freq: AtomicU8::new(slot.freq.load(Ordering::Relaxed)),
+                },
+            );
+        }
+        match &self.limit {
+            Some(limit) => table.max_memory(limit.max, limit.policy),
+            None => table,
+        }
+    }
+}
+
 impl Storage for MemTable {
     fn set(
         &self,
         key: impl Into<String>,
         value: impl Into<Value>,
     ) -> Result<Option<Value>, HikvError> {
-        let old_value = self.innner.insert(key.into(), value.into());
-        Ok(old_value)
+        self.put(key.into(), value.into(), None)
     }
 
     fn get(&self, key: &str) -> Result<Option<Value>, HikvError> {
-        let value = self.innner.get(key).map(|v| v.value().clone());
+        Ok(self.get_with_meta(key)?.map(|(v, _)| v))
+    }
+
+    fn get_with_meta(&self, key: &str) -> Result<Option<(Value, KeyMeta)>, HikvError> {
+        let value = self.innner.get(key).map(|v| {
+            if self.limit.is_some() {
+                v.touch(self.tick());
+            }
+            (v.value.get(), v.meta)
+        });
         Ok(value)
     }
 
+    fn set_if_version(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        expected: u64,
+    ) -> Result<Option<Value>, HikvError> {
+        self.put(key.into(), value.into(), Some(expected))
+    }
+
     fn del(&self, key: &str) -> Result<Option<Value>, HikvError> {
-        Ok(self.innner.remove(key).map(|(_k, v)| v))
+        let _guard = self.barrier.read().unwrap();
+        Ok(self.remove(key).map(|s| s.value.get()))
+    }
+
+    fn create_snapshot(&self, timeout: Duration) -> Result<u64, HikvError> {
+        let _guard = self.barrier.write().unwrap();
+        Ok(self.history.create(timeout).0)
+    }
+
+    fn get_at(&self, snapshot: u64, key: &str) -> Result<Option<Value>, HikvError> {
+        let seq = self.history.views.get(snapshot)?;
+        // 持有 shard 读锁，保证读到的 history 和当前值一致
+        let current = self.innner.get(key);
+        match self.history.before(key, seq) {
+            Some(old) => Ok(old.map(|(v, _)| v)),
+            None => Ok(current.map(|slot| slot.value.get())),
+        }
+    }
+
+    fn release_snapshot(&self, snapshot: u64) -> Result<bool, HikvError> {
+        let _guard = self.barrier.write().unwrap();
+        Ok(self.history.release(snapshot))
     }
 
     fn contains(&self, key: &str) -> Result<bool, HikvError> {
         Ok(self.innner.contains_key(key))
     }
+
+    fn get_iter(&self) -> Result<StorageIter<'_>, HikvError> {
+        let mut pairs: Vec<Kvpair> = self
+            .innner
+            .iter()
+            .map(|e| Kvpair::new(e.key().as_str(), e.value().value.get()))
+            .collect();
+        pairs.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        Ok(Box::new(pairs.into_iter().map(Ok)))
+    }
+
+    /// 配置了快照文件时保存快照
+    fn flush(&self) -> Result<(), HikvError> {
+        if self.snapshot.is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// 等待进行中的后台保存结束再保存，避免较旧的后台快照覆盖这次的结果
+    fn save(&self) -> Result<usize, HikvError> {
+        let path = self.snapshot_path()?;
+        while self.saving.load(Ordering::Acquire) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let view = self.save_view();
+        let n = write_snapshot(path, view.entries(&self.innner))?;
+        info!("Saved {} keys to snapshot {:?}", n, path);
+        Ok(n)
+    }
+
+    fn bg_save(&self) -> Result<(), HikvError> {
+        let path = self.snapshot_path()?.to_path_buf();
+        if self.saving.swap(true, Ordering::AcqRel) {
+            return Err(HikvError::SnapshotError(
+                "background save already in progress".into(),
+            ));
+        }
+
+        let view = self.save_view();
+        let data = self.innner.clone();
+        let saving = self.saving.clone();
+        thread::spawn(move || {
+            match write_snapshot(&path, view.entries(&data)) {
+                Ok(n) => info!("Background saved {} keys to snapshot {:?}", n, path),
+                Err(e) => error!("Background save to {:?} failed: {}", path, e),
+            }
+            drop(view);
+            saving.store(false, Ordering::Release);
+        });
+
+        Ok(())
+    }
+
+    /// 写入一个快照文件，不支持增量
+    fn backup(&self, path: &Path, incremental: bool) -> Result<BackupManifest, HikvError> {
+        if incremental {
+            return Err(HikvError::Unsupported("IncrementalBackup"));
+        }
+        if path.exists() {
+            return Err(HikvError::BackupError(format!(
+                "backup target {:?} already exists",
+                path
+            )));
+        }
+        let view = self.save_view();
+        let mut digest = Digest::default();
+        let mut failed = None;
+        let entries = view.entries(&self.innner).inspect(|(key, value, _)| {
+            if let Err(e) = digest.update(&Kvpair::new(key.as_str(), value.clone())) {
+                failed.get_or_insert(e);
+            }
+        });
+        write_snapshot(path, entries)?;
+        if let Some(e) = failed {
+            return Err(e);
+        }
+        Ok(BackupManifest::new(StorageKind::Memory, digest, false))
+    }
+
+    /// 用维护的计数器统计，不遍历数据；压缩的 value 按压缩后的大小计算
+    fn stats(&self) -> Result<StorageStats, HikvError> {
+        let keys = self.innner.len();
+        let data = self.data.load(Ordering::Relaxed);
+        let stats = StorageStats {
+            keys: keys as u64,
+            logical_bytes: data.saturating_sub(keys * ENTRY_OVERHEAD) as u64,
+            ..Default::default()
+        };
+        let mut stats = stats
+            .with("memory.used", self.used.load(Ordering::Relaxed) as i64)
+            .with("memory.evicted", self.evicted() as i64);
+        if let Some(compressor) = &self.compression {
+            stats = stats.with_compression(compressor.stats());
+        }
+        Ok(stats)
+    }
+
+    fn evicted(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+}
+
+impl SaveView {
+    /// 按 shard 依次复制读视图中的数据，每次只阻塞一个 shard 的写操作
+    fn entries<'a>(
+        &'a self,
+        data: &'a DashMap<String, Slot>,
+    ) -> impl Iterator<Item = (String, Value, KeyMeta)> + 'a {
+        (0..data.shards().len()).flat_map(move |i| self.shard_entries(data, i))
+    }
+
+    fn shard_entries(
+        &self,
+        data: &DashMap<String, Slot>,
+        i: usize,
+    ) -> Vec<(String, Value, KeyMeta)> {
+        // 持有 shard 读锁期间这个 shard 的 key 不会增删，和 history 一致
+        let shard = data.shards()[i].read();
+        let mut entries = Vec::with_capacity(shard.len());
+        for (key, slot) in shard.iter() {
+            match self.history.before(key, self.seq) {
+                Some(old) => entries.extend(old.map(|(v, meta)| (key.clone(), v, meta))),
+                None => entries.push((key.clone(), slot.get().value.get(), slot.get().meta)),
+            }
+        }
+        // 视图之后被删除的 key
+        for e in self.history.versions.iter() {
+            if data.determine_map(e.key()) != i || shard.contains_key(e.key()) {
+                continue;
+            }
+            if let Some((_, Some((v, meta)))) = e.value().iter().find(|(s, _)| *s > self.seq) {
+                entries.push((e.key().clone(), v.clone(), *meta));
+            }
+        }
+        entries
+    }
+}
+
+impl Drop for SaveView {
+    fn drop(&mut self) {
+        let _guard = self.barrier.write().unwrap();
+        self.history.release(self.id);
+    }
+}
+
+impl Stored {
+    fn new(value: Value, compressor: Option<&Compressor>) -> Result<Self, HikvError> {
+        let compressor = match compressor {
+            Some(compressor) => compressor,
+            None => return Ok(Self::Plain(value)),
+        };
+        let data = compressor.encode_value(&value)?;
+        let compression = match RecordHeader::read(&mut data.as_slice())? {
+            Some(header) => header.compression()?,
+            None => Compression::None,
+        };
+        Ok(match compression {
+            Compression::None => Self::Plain(value),
+            _ => Self::Compressed(data.into()),
+        })
+    }
+
+    fn get(&self) -> Value {
+        match self {
+            Self::Plain(value) => value.clone(),
+            Self::Compressed(data) => {
+                decode_value(data).expect("value compressed by MemTable should be decodable")
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Plain(value) => value.encoded_len(),
+            Self::Compressed(data) => data.len(),
+        }
+    }
+}
+
+impl Slot {
+    fn new(key: &str, value: Stored, meta: KeyMeta, now: u64) -> Self {
+        Self {
+            size: key.len() + value.len() + ENTRY_OVERHEAD,
+            value,
+            meta,
+            access: AtomicU64::new(now),
+            freq: AtomicU8::new(LFU_INIT),
+        }
+    }
+
+    /// 记录一次访问，LFU 计数按对数概率增长
+    fn touch(&self, now: u64) {
+        self.access.store(now, Ordering::Relaxed);
+        let freq = self.freq.load(Ordering::Relaxed);
+        if freq < u8::MAX {
+            let base = freq.saturating_sub(LFU_INIT) as f64;
+            if fastrand::f64() < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+                let _ = self.freq.compare_exchange(
+                    freq,
+                    freq + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                );
+            }
+        }
+    }
+}
+
+impl MemoryLimit {
+    /// 把 key 加入所在 shard 的索引，返回增加的内存占用
+    fn insert(&self, data: &DashMap<String, Slot>, key: &str) -> usize {
+        let i = data.determine_map(key);
+        let mut index = self.keys[i].lock().unwrap();
+        let added = index.insert(key);
+        self.lens[i].store(index.keys.len(), Ordering::Relaxed);
+        added
+    }
+
+    /// 从所在 shard 的索引中删除 key，返回减少的内存占用
+    fn remove(&self, data: &DashMap<String, Slot>, key: &str) -> usize {
+        let i = data.determine_map(key);
+        let mut index = self.keys[i].lock().unwrap();
+        let removed = index.remove(key);
+        self.lens[i].store(index.keys.len(), Ordering::Relaxed);
+        removed
+    }
+
+    /// 有放回地随机采样 n 个 key，每个 key 被选中的概率相同
+    fn sample(&self, n: usize) -> Vec<String> {
+        let lens: Vec<usize> = self
+            .lens
+            .iter()
+            .map(|len| len.load(Ordering::Relaxed))
+            .collect();
+        let total: usize = lens.iter().sum();
+        if total == 0 {
+            return Vec::new();
+        }
+        (0..n)
+            .filter_map(|_| {
+                // 按全局位置找到所在 shard，计数和索引之间的短暂不一致只影响采样分布
+                let mut pos = fastrand::usize(..total);
+                let i = lens.iter().position(|&len| {
+                    let found = pos < len;
+                    if !found {
+                        pos -= len;
+                    }
+                    found
+                })?;
+                self.keys[i].lock().unwrap().get(pos)
+            })
+            .collect()
+    }
+}
+
+impl KeyIndex {
+    /// 返回增加的内存占用，key 已存在时为 0
+    fn insert(&mut self, key: &str) -> usize {
+        if self.pos.contains_key(key) {
+            return 0;
+        }
+        let key: Arc<str> = key.into();
+        self.pos.insert(key.clone(), self.keys.len());
+        self.keys.push(key.clone());
+        Self::size(&key)
+    }
+
+    /// 返回减少的内存占用，key 不存在时为 0
+    fn remove(&mut self, key: &str) -> usize {
+        match self.pos.remove(key) {
+            Some(i) => {
+                self.keys.swap_remove(i);
+                if let Some(moved) = self.keys.get(i) {
+                    self.pos.insert(moved.clone(), i);
+                }
+                Self::size(key)
+            }
+            None => 0,
+        }
+    }
+
+    fn get(&self, pos: usize) -> Option<String> {
+        self.keys.get(pos).map(|k| k.to_string())
+    }
+
+    fn size(key: &str) -> usize {
+        key.len() + INDEX_OVERHEAD
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn should_work_memtable_save_and_restore() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("dump.snap");
+
+        let store = MemTable::with_snapshot(&path).unwrap();
+        store.set("hello", "world").unwrap();
+        store.set("age", Value::from(18)).unwrap();
+        assert_eq!(store.save().unwrap(), 2);
+
+        let meta = store.meta("hello").unwrap();
+        let store = MemTable::with_snapshot(&path).unwrap();
+        assert_eq!(store.get("hello").unwrap(), Some("world".into()));
+        assert_eq!(store.get("age").unwrap(), Some(18.into()));
+        assert_eq!(store.meta("hello").unwrap(), meta);
+    }
+
+    #[test]
+    fn should_work_memtable_bg_save() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("dump.snap");
+
+        let store = MemTable::with_snapshot(&path).unwrap();
+        store.set("hello", "world").unwrap();
+        store.bg_save().unwrap();
+
+        // 后台线程结束后 saving 标志会被清除
+        while store.saving.load(Ordering::Acquire) {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let store = MemTable::with_snapshot(&path).unwrap();
+        assert_eq!(store.get("hello").unwrap(), Some("world".into()));
+    }
+
+    #[test]
+    fn save_during_bg_save_should_not_mix_snapshots() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("dump.snap");
+        let store = MemTable::with_snapshot(&path).unwrap();
+        for i in 0..1000 {
+            store.set(format!("k{}", i), Value::from(i)).unwrap();
+        }
+        store.bg_save().unwrap();
+        store.set("late", "v").unwrap();
+        assert_eq!(store.save().unwrap(), 1001);
+        assert!(!store.saving.load(Ordering::Acquire));
+
+        // 后保存的快照生效，没有残留的临时文件
+        assert_eq!(read_snapshot(&path).unwrap().len(), 1001);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn save_should_see_data_at_view_creation() {
+        let store = MemTable::new();
+        for i in 0..100 {
+            store.set(format!("key{}", i), i).unwrap();
+        }
+        let view = store.save_view();
+
+        // 保存期间的写入不会阻塞，也不会出现在快照中
+        store.set("key1", "changed").unwrap();
+        store.del("key2").unwrap();
+        store.set("new", "key").unwrap();
+        store.del("new").unwrap();
+
+        let mut entries: Vec<_> = view
+            .entries(&store.innner)
+            .map(|(k, v, _)| (k, v))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut expected: Vec<_> = (0..100)
+            .map(|i| (format!("key{}", i), Value::from(i)))
+            .collect();
+        expected.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(entries, expected);
+
+        drop(view);
+        assert!(store.history.versions.is_empty());
+    }
+
+    #[test]
+    fn save_without_snapshot_path_should_fail() {
+        let store = MemTable::new();
+        assert!(store.save().is_err());
+        assert!(store.bg_save().is_err());
+    }
+
+    #[test]
+    fn should_work_memtable_snapshot_reads() {
+        let store = MemTable::new();
+        store.set("hello", "world").unwrap();
+        store.set("lang", "rust").unwrap();
+
+        let id = store.create_snapshot(Duration::from_secs(60)).unwrap();
+        store.set("hello", "there").unwrap();
+        store.set("hello", "again").unwrap();
+        store.del("lang").unwrap();
+        store.set("new", "key").unwrap();
+
+        assert_eq!(store.get_at(id, "hello").unwrap(), Some("world".into()));
+        assert_eq!(store.get_at(id, "lang").unwrap(), Some("rust".into()));
+        assert_eq!(store.get_at(id, "new").unwrap(), None);
+        assert_eq!(store.get("hello").unwrap(), Some("again".into()));
+
+        let id2 = store.create_snapshot(Duration::from_secs(60)).unwrap();
+        store.set("hello", "latest").unwrap();
+        assert_eq!(store.get_at(id2, "hello").unwrap(), Some("again".into()));
+
+        assert!(store.release_snapshot(id).unwrap());
+        assert!(store.get_at(id, "hello").is_err());
+        assert_eq!(store.get_at(id2, "hello").unwrap(), Some("again".into()));
+        assert!(store.release_snapshot(id2).unwrap());
+        assert!(store.history.versions.is_empty());
+    }
+
+    #[test]
+    fn memtable_snapshot_should_expire() {
+        let store = MemTable::new();
+        let id = store.create_snapshot(Duration::from_millis(1)).unwrap();
+        thread::sleep(Duration::from_millis(5));
+        store.set("hello", "world").unwrap();
+        assert!(matches!(
+            store.get_at(id, "hello"),
+            Err(HikvError::SnapshotNotFound(_))
+        ));
+        assert!(store.history.versions.is_empty());
+    }
+
+    #[test]
+    fn should_track_used_memory() {
+        let store = MemTable::new();
+        store.set("hello", "world").unwrap();
+        let used = store.used_memory();
+        assert!(used > 0);
+
+        store.set("hello", "world!").unwrap();
+        assert_eq!(store.used_memory(), used + 1);
+        let stats = store.stats().unwrap();
+        let scanned = StorageStats::scan(store.get_iter().unwrap()).unwrap();
+        assert_eq!(
+            (stats.keys, stats.logical_bytes),
+            (scanned.keys, scanned.logical_bytes)
+        );
+
+        store.del("hello").unwrap();
+        assert_eq!(store.used_memory(), 0);
+    }
+
+    #[test]
+    fn should_evict_lru_keys_when_full() {
+        fastrand::seed(7);
+        let store = MemTable::new().max_memory(4096, EvictionPolicy::AllKeysLru);
+        store.set("hot", "value").unwrap();
+        for i in 0..200 {
+            store.set(format!("key{}", i), "value").unwrap();
+            store.get("hot").unwrap();
+        }
+
+        assert!(store.used_memory() <= 4096);
+        assert!(store.evicted() > 0);
+        assert!(store.contains("hot").unwrap());
+        let indexed: usize = store
+            .limit
+            .as_ref()
+            .unwrap()
+            .keys
+            .iter()
+            .map(|index| index.lock().unwrap().keys.len())
+            .sum();
+        assert_eq!(indexed, store.innner.len());
+    }
+
+    #[test]
+    fn should_compress_large_values_in_memory() {
+        let json = "{\"id\": 1, \"tags\": [\"a\", \"b\"]}".repeat(1000);
+        let plain = MemTable::new();
+        plain.set("doc", json.as_str()).unwrap();
+
+        let store = MemTable::new().compression(CompressOptions::gzip());
+        store.set("doc", json.as_str()).unwrap();
+        store.set("small", "value").unwrap();
+        assert!(store.used_memory() * 10 < plain.used_memory());
+        assert_eq!(store.get("doc").unwrap(), Some(json.as_str().into()));
+        assert_eq!(store.del("doc").unwrap(), Some(json.as_str().into()));
+
+        let stats = store.stats().unwrap();
+        match stats.engine["compression.write_ratio"].value {
+            Some(crate::value::Value::Float(ratio)) => assert!(ratio > 10.0),
+            ref v => panic!("unexpected ratio {:?}", v),
+        }
+
+        // 已有数据在开启压缩时重新保存
+        let plain = plain.compression(CompressOptions::gzip());
+        assert!(plain.used_memory() * 10 < json.len());
+        assert_eq!(plain.get("doc").unwrap(), Some(json.as_str().into()));
+    }
+
+    #[test]
+    fn key_index_should_count_in_used_memory() {
+        let store = MemTable::new();
+        store.set("hello", "world").unwrap();
+        let used = store.used_memory();
+
+        let store = store.max_memory(1024, EvictionPolicy::AllKeysLru);
+        assert_eq!(store.used_memory(), used + KeyIndex::size("hello"));
+        store.del("hello").unwrap();
+        assert_eq!(store.used_memory(), 0);
+    }
+
+    #[test]
+    fn clone_should_copy_data_and_config() {
+        let store = MemTable::new().max_memory(4096, EvictionPolicy::AllKeysLru);
+        store.set("hello", "world").unwrap();
+
+        let copy = store.clone();
+        assert_eq!(copy.used_memory(), store.used_memory());
+        assert_eq!(copy.meta("hello").unwrap(), store.meta("hello").unwrap());
+        copy.set("hello", "there").unwrap();
+        assert_eq!(store.get("hello").unwrap(), Some("world".into()));
+    }
+
+    #[test]
+    fn should_reject_writes_without_eviction() {
+        let store = MemTable::new().max_memory(128, EvictionPolicy::NoEviction);
+        store.set("k1", "v1").unwrap();
+        let err = store.set("k2", Bytes::from(vec![0u8; 128])).unwrap_err();
+        assert!(matches!(err, HikvError::OutOfMemory(_, 128)));
+        assert_eq!(store.evicted(), 0);
+        assert!(store.contains("k1").unwrap());
+    }
 }
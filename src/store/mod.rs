@@ -1,11 +1,210 @@
-use crate::{HikvError, Value};
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+use prost::Message;
+
+use crate::{BackupManifest, HikvError, Kvpair, Value};
+
+mod any;
+mod bitcask;
+mod codec;
+#[cfg(feature = "encryption")]
+mod encrypted;
+mod faulty;
 mod memory;
+mod mvcc;
+#[cfg(feature = "rocksdb")]
 mod rocks_db;
+mod sharded;
+#[cfg(feature = "sled")]
 mod sleddb;
-pub use memory::MemTable;
+mod snapshot;
+mod tiered;
+pub use any::{AnyStorage, StorageKind, StorageSpec};
+pub use bitcask::{Bitcask, BitcaskOptions};
+pub use codec::{
+    decode_entry, decode_value, encode_entry, encode_value, Codec, CompressOptions, Compression,
+    CompressionStats, Compressor, RecordHeader, DEFAULT_COMPRESS_THRESHOLD, FLAG_COMPRESSION,
+    FLAG_META, FORMAT_VERSION,
+};
+#[cfg(feature = "encryption")]
+pub use encrypted::{EncryptedStorage, KeyRing, RotationReport};
+pub use faulty::{Fault, FaultKind, FaultRule, FaultyStorage, StorageOp, Trigger};
+pub use memory::{EvictionPolicy, MemTable};
+pub(crate) use mvcc::{History, ReadViews};
+pub use mvcc::{DEFAULT_SNAPSHOT_TIMEOUT, MAX_SNAPSHOT_TIMEOUT};
+#[cfg(feature = "rocksdb")]
 pub use rocks_db::RocksDb;
+pub use sharded::{
+    rebalance, HashRing, RebalanceReport, ShardManifest, ShardedStorage, SHARD_DATA, SHARD_MANIFEST,
+};
+#[cfg(feature = "sled")]
 pub use sleddb::SledDb;
+pub use snapshot::{read_snapshot, write_snapshot};
+pub use tiered::{CacheStats, TieredStorage, WriteMode};
+
+/// key 的元数据
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyMeta {
+    /// 版本号，每次写入严格递增，删除后重新创建也不会重复，0 表示 key 不存在
+    pub version: u64,
+    /// 创建时间(毫秒时间戳)
+    pub created_at: u64,
+    /// 最后修改时间(毫秒时间戳)
+    pub updated_at: u64,
+}
+
+impl KeyMeta {
+    /// 新建 key 的元数据
+    pub fn new(version: u64) -> Self {
+        let now = now_millis();
+        Self {
+            version,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// 没有保存元数据的旧记录，时间未知
+    pub fn legacy() -> Self {
+        Self {
+            version: 1,
+            ..Default::default()
+        }
+    }
+
+    /// 再次写入后的元数据
+    pub fn next(&self, version: u64) -> Self {
+        Self {
+            version,
+            created_at: self.created_at,
+            updated_at: now_millis(),
+        }
+    }
+}
+
+/// 分配版本号的时钟，同一个存储分配的版本号严格递增
+///
+/// 版本号以微秒时间戳为下限，所以删除后重新创建的 key、重启后的写入都不会拿到用过的版本号，
+/// 除非系统时钟回拨
+#[derive(Debug, Default)]
+pub struct VersionClock(AtomicU64);
+
+impl VersionClock {
+    /// 保证之后分配的版本号大于 version，加载已有数据时调用
+    pub fn observe(&self, version: u64) {
+        self.0.fetch_max(version, Ordering::AcqRel);
+    }
+
+    /// 分配下一个版本号
+    pub fn next(&self) -> u64 {
+        let now = now_micros();
+        let prev = self
+            .0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |v| {
+                Some((v + 1).max(now))
+            })
+            .unwrap();
+        (prev + 1).max(now)
+    }
+
+    /// 由旧的元数据生成本次写入的元数据，同时检查版本前置条件
+    pub fn update(
+        &self,
+        key: &str,
+        old: Option<&KeyMeta>,
+        expected: Option<u64>,
+    ) -> Result<KeyMeta, HikvError> {
+        let actual = old.map_or(0, |m| m.version);
+        if let Some(expected) = expected.filter(|&e| e != actual) {
+            return Err(HikvError::VersionMismatch(
+                key.to_string(),
+                expected,
+                actual,
+            ));
+        }
+        self.observe(actual);
+        let version = self.next();
+        Ok(old.map_or_else(|| KeyMeta::new(version), |m| m.next(version)))
+    }
+}
+
+/// 存储统计信息
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StorageStats {
+    /// key 数量
+    pub keys: u64,
+    /// key 和 value 编码后的总字节数，各引擎按自己的存储格式计算，rocksdb 为估算值
+    pub logical_bytes: u64,
+    /// 占用的磁盘空间，纯内存存储为 0
+    pub disk_bytes: u64,
+    /// 各引擎特有的计数器
+    pub engine: BTreeMap<String, Value>,
+}
+
+impl StorageStats {
+    /// 遍历所有 key-value 统计 key 数量和逻辑大小
+    pub fn scan(iter: StorageIter<'_>) -> Result<Self, HikvError> {
+        let mut stats = Self::default();
+        for pair in iter {
+            let pair = pair?;
+            stats.keys += 1;
+            stats.logical_bytes +=
+                (pair.key.len() + pair.value.map_or(0, |v| v.encoded_len())) as u64;
+        }
+        Ok(stats)
+    }
+
+    /// 记录一个引擎计数器
+    pub fn with(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.engine.insert(name.into(), value.into());
+        self
+    }
+
+    /// 记录自启动以来写入的 value 压缩前后字节数和压缩比，是写入流量指标
+    pub fn with_compression(self, stats: CompressionStats) -> Self {
+        self.with("compression.written_raw_bytes", stats.raw_bytes as i64)
+            .with(
+                "compression.written_stored_bytes",
+                stats.stored_bytes as i64,
+            )
+            .with("compression.write_ratio", stats.ratio())
+    }
+
+    /// 按 keys、logical_bytes、disk_bytes、引擎计数器的顺序展开
+    pub fn to_pairs(&self) -> Vec<Kvpair> {
+        let mut pairs = vec![
+            Kvpair::new("keys", (self.keys as i64).into()),
+            Kvpair::new("logical_bytes", (self.logical_bytes as i64).into()),
+            Kvpair::new("disk_bytes", (self.disk_bytes as i64).into()),
+        ];
+        pairs.extend(
+            self.engine
+                .iter()
+                .map(|(name, value)| Kvpair::new(name, value.clone())),
+        );
+        pairs
+    }
+}
+
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_micros() as u64)
+}
+
+/// 按 key 升序返回 key-value 的迭代器
+pub type StorageIter<'a> = Box<dyn Iterator<Item = Result<Kvpair, HikvError>> + 'a>;
 
 pub trait Storage {
     /// 保存 key-value,返回 old value
@@ -18,11 +217,97 @@ pub trait Storage {
     /// 获取指定 key 对应的 value
     fn get(&self, key: &str) -> Result<Option<Value>, HikvError>;
 
+    /// 获取 value 及其元数据
+    fn get_with_meta(&self, _key: &str) -> Result<Option<(Value, KeyMeta)>, HikvError> {
+        Err(HikvError::Unsupported("Meta"))
+    }
+
+    /// 当前版本等于 expected 时才写入，expected 为 0 表示 key 必须不存在
+    fn set_if_version(
+        &self,
+        _key: impl Into<String>,
+        _value: impl Into<Value>,
+        _expected: u64,
+    ) -> Result<Option<Value>, HikvError> {
+        Err(HikvError::Unsupported("SetIfVersion"))
+    }
+
+    /// 获取 key 的元数据
+    fn meta(&self, key: &str) -> Result<Option<KeyMeta>, HikvError> {
+        Ok(self.get_with_meta(key)?.map(|(_, meta)| meta))
+    }
+
     /// 删除指定 key
     fn del(&self, key: &str) -> Result<Option<Value>, HikvError>;
 
     /// 查看指定 key 是否存在
     fn contains(&self, key: &str) -> Result<bool, HikvError>;
+
+    /// 按 key 升序遍历所有 key-value
+    fn get_iter(&self) -> Result<StorageIter<'_>, HikvError> {
+        Err(HikvError::Unsupported("Iterate"))
+    }
+
+    /// 批量写入，后端支持时整批原子生效
+    fn set_all(&self, pairs: Vec<Kvpair>) -> Result<(), HikvError> {
+        for pair in pairs {
+            self.set(pair.key, pair.value.unwrap_or_default())?;
+        }
+        Ok(())
+    }
+
+    /// 把已写入的数据持久化到磁盘
+    fn flush(&self) -> Result<(), HikvError> {
+        Ok(())
+    }
+
+    /// 整理磁盘数据，回收被覆盖或删除的记录占用的空间，不需要整理的后端什么也不做
+    fn compact(&self) -> Result<(), HikvError> {
+        Ok(())
+    }
+
+    /// 统计信息，默认遍历所有 key-value
+    fn stats(&self) -> Result<StorageStats, HikvError> {
+        StorageStats::scan(self.get_iter()?)
+    }
+
+    /// 把同一时刻的数据副本写入 path，返回备份清单
+    ///
+    /// incremental 为 true 时 path 中可能已有上次备份的数据，不支持增量的后端返回 Unsupported；
+    /// 否则 path 必须不存在，已存在时返回错误，不会删除其中的数据
+    fn backup(&self, _path: &Path, _incremental: bool) -> Result<BackupManifest, HikvError> {
+        Err(HikvError::Unsupported("Backup"))
+    }
+
+    /// 创建一致性读视图，返回快照 id，超过 timeout 未释放时自动失效
+    fn create_snapshot(&self, _timeout: Duration) -> Result<u64, HikvError> {
+        Err(HikvError::Unsupported("Snapshot"))
+    }
+
+    /// 读取快照创建时 key 对应的 value
+    fn get_at(&self, snapshot: u64, _key: &str) -> Result<Option<Value>, HikvError> {
+        Err(HikvError::SnapshotNotFound(snapshot))
+    }
+
+    /// 释放快照，快照不存在或已超时返回 false
+    fn release_snapshot(&self, _snapshot: u64) -> Result<bool, HikvError> {
+        Ok(false)
+    }
+
+    /// 同步保存全部数据到快照文件，返回保存的 key 数量
+    fn save(&self) -> Result<usize, HikvError> {
+        Err(HikvError::Unsupported("Save"))
+    }
+
+    /// 在后台保存快照，立即返回
+    fn bg_save(&self) -> Result<(), HikvError> {
+        Err(HikvError::Unsupported("BgSave"))
+    }
+
+    /// 因内存上限累计淘汰的 key 数量
+    fn evicted(&self) -> u64 {
+        0
+    }
 }
 
 #[cfg(test)]
@@ -37,6 +322,7 @@ mod tests {
         test_basic_interface(store);
     }
 
+    #[cfg(feature = "sled")]
     #[test]
     fn should_work_sleddb_basic() {
         let dir = tempdir().unwrap();
@@ -44,6 +330,32 @@ mod tests {
         test_basic_interface(store);
     }
 
+    #[test]
+    fn should_work_bitcask_basic() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::new(&dir);
+        test_basic_interface(store);
+    }
+
+    crate::storage_conformance!(memtable_conformance, |_| MemTable::new());
+    #[cfg(feature = "sled")]
+    crate::storage_conformance!(sleddb_conformance, SledDb::new);
+    #[cfg(feature = "rocksdb")]
+    crate::storage_conformance!(rocksdb_conformance, RocksDb::new);
+    crate::storage_conformance!(bitcask_conformance, Bitcask::new);
+    #[cfg(feature = "encryption")]
+    crate::storage_conformance!(encrypted_conformance, |dir: &Path| {
+        let keys = dir.join("keys");
+        KeyRing::generate(&keys, 1).unwrap();
+        EncryptedStorage::open(MemTable::new(), keys, true).unwrap()
+    });
+    crate::storage_conformance!(sharded_conformance, |_| {
+        ShardedStorage::new((0..3).map(|_| MemTable::new()).collect())
+    });
+    crate::storage_conformance!(tiered_conformance, |dir| {
+        TieredStorage::new(Bitcask::new(dir), 64 * 1024, WriteMode::WriteThrough)
+    });
+
     fn test_basic_interface(store: impl Storage) {
         let v = store.set("hello", "world");
         assert!(v.unwrap().is_none());
@@ -66,5 +378,81 @@ mod tests {
 
         assert_eq!(None, store.del("hello").unwrap());
         assert_eq!(None, store.del("lang").unwrap());
+
+        test_meta_interface(&store);
+        test_stats_interface(&store);
+
+        store
+            .set_all(vec![
+                Kvpair::new("b", 2.into()),
+                Kvpair::new("a", 1.into()),
+                Kvpair::new("c", 3.into()),
+            ])
+            .unwrap();
+        store.flush().unwrap();
+        let pairs: Vec<Kvpair> = store.get_iter().unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(
+            pairs,
+            vec![
+                Kvpair::new("a", 1.into()),
+                Kvpair::new("b", 2.into()),
+                Kvpair::new("c", 3.into()),
+            ]
+        );
+    }
+
+    fn test_stats_interface(store: &impl Storage) {
+        let before = store.stats().unwrap();
+        store.set("stats", "value").unwrap();
+        store.flush().unwrap();
+        let stats = store.stats().unwrap();
+        assert_eq!(stats.keys, before.keys + 1);
+        assert!(stats.logical_bytes > before.logical_bytes);
+
+        store.del("stats").unwrap();
+        match store.compact() {
+            Ok(()) | Err(HikvError::Unsupported(_)) => {}
+            Err(e) => panic!("unexpected error: {:?}", e),
+        }
+        assert_eq!(store.stats().unwrap().keys, before.keys);
+    }
+
+    fn test_meta_interface(store: &impl Storage) {
+        assert_eq!(store.meta("meta").unwrap(), None);
+        store.set("meta", "v1").unwrap();
+        let (v, m1) = store.get_with_meta("meta").unwrap().unwrap();
+        assert_eq!(v, "v1".into());
+        assert!(m1.version > 0);
+        assert!(m1.created_at > 0 && m1.created_at == m1.updated_at);
+
+        store.set("meta", "v2").unwrap();
+        let m2 = store.meta("meta").unwrap().unwrap();
+        assert!(m2.version > m1.version);
+        assert_eq!(m2.created_at, m1.created_at);
+        assert!(m2.updated_at >= m1.updated_at);
+
+        let err = store.set_if_version("meta", "v3", m1.version).unwrap_err();
+        assert!(
+            matches!(err, HikvError::VersionMismatch(_, e, a) if e == m1.version && a == m2.version)
+        );
+        assert_eq!(store.get("meta").unwrap(), Some("v2".into()));
+        let old = store.set_if_version("meta", "v3", m2.version).unwrap();
+        assert_eq!(old, Some("v2".into()));
+        let m3 = store.meta("meta").unwrap().unwrap();
+        assert!(m3.version > m2.version);
+
+        assert!(store.set_if_version("fresh", "v1", 1).is_err());
+        assert_eq!(store.set_if_version("fresh", "v1", 0).unwrap(), None);
+        assert!(store.set_if_version("fresh", "v1", 0).is_err());
+
+        // 删除后重新写入不会复用旧版本号
+        store.del("meta").unwrap();
+        store.del("fresh").unwrap();
+        assert_eq!(store.meta("meta").unwrap(), None);
+        store.set("meta", "again").unwrap();
+        let m4 = store.meta("meta").unwrap().unwrap();
+        assert!(m4.version > m3.version);
+        assert!(store.set_if_version("meta", "stale", m1.version).is_err());
+        store.del("meta").unwrap();
     }
 }
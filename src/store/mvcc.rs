@@ -0,0 +1,215 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+
+use crate::HikvError;
+
+/// 快照未指定超时时使用的默认值
+pub const DEFAULT_SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(60);
+/// Snapshot 命令允许的最长超时，可以通过配置调整
+pub const MAX_SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(600);
+/// 有读视图期间每写入这么多次清理一次旧值
+const GC_INTERVAL: u64 = 1024;
+
+/// 已创建的一致性读视图，超时后自动失效
+#[derive(Debug)]
+pub(crate) struct ReadViews<V> {
+    next_id: AtomicU64,
+    /// 视图数量(可能包含尚未清理的超时视图)，用于无锁判断是否为空
+    count: AtomicUsize,
+    views: Mutex<HashMap<u64, (V, Instant)>>,
+}
+
+impl<V> Default for ReadViews<V> {
+    fn default() -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            count: AtomicUsize::new(0),
+            views: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<V: Clone> ReadViews<V> {
+    /// 登记一个读视图，返回快照 id
+    pub fn insert(&self, view: V, timeout: Duration) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let deadline = Instant::now() + timeout;
+        let mut views = self.views.lock().unwrap();
+        views.insert(id, (view, deadline));
+        self.count.store(views.len(), Ordering::Release);
+        id
+    }
+
+    pub fn get(&self, id: u64) -> Result<V, HikvError> {
+        let mut views = self.views.lock().unwrap();
+        self.expire(&mut views);
+        views
+            .get(&id)
+            .map(|(view, _)| view.clone())
+            .ok_or(HikvError::SnapshotNotFound(id))
+    }
+
+    pub fn remove(&self, id: u64) -> bool {
+        let mut views = self.views.lock().unwrap();
+        self.expire(&mut views);
+        let removed = views.remove(&id).is_some();
+        self.count.store(views.len(), Ordering::Release);
+        removed
+    }
+
+    /// 所有未超时的读视图
+    pub fn active(&self) -> Vec<V> {
+        let mut views = self.views.lock().unwrap();
+        self.expire(&mut views);
+        views.values().map(|(view, _)| view.clone()).collect()
+    }
+
+    /// 没有读视图时不加锁
+    pub fn is_empty(&self) -> bool {
+        if self.count.load(Ordering::Acquire) == 0 {
+            return true;
+        }
+        let mut views = self.views.lock().unwrap();
+        self.expire(&mut views);
+        views.is_empty()
+    }
+
+    fn expire(&self, views: &mut HashMap<u64, (V, Instant)>) {
+        let now = Instant::now();
+        views.retain(|_, (_, deadline)| *deadline > now);
+        self.count.store(views.len(), Ordering::Release);
+    }
+}
+
+/// 读视图及有读视图期间被覆盖的旧值，读视图用旧值还原创建时的数据
+///
+/// 写入方在修改前调用 record，创建读视图时需要阻塞写入，由调用方的 barrier 保证
+#[derive(Debug)]
+pub(crate) struct History<T> {
+    /// 读视图，记录创建时的写入序号
+    pub views: ReadViews<u64>,
+    /// 有读视图时的写入序号
+    seq: AtomicU64,
+    /// key -> [(写入序号, 写入前的值)]
+    pub versions: DashMap<String, Vec<(u64, T)>>,
+    /// versions 可能非空，没有读视图时据此决定是否清理
+    pending: AtomicBool,
+}
+
+impl<T> Default for History<T> {
+    fn default() -> Self {
+        Self {
+            views: ReadViews::default(),
+            seq: AtomicU64::new(0),
+            versions: DashMap::new(),
+            pending: AtomicBool::new(false),
+        }
+    }
+}
+
+impl<T: Clone> History<T> {
+    /// 创建读视图，返回 (快照 id, 写入序号)，调用方需持有 barrier 写锁
+    pub fn create(&self, timeout: Duration) -> (u64, u64) {
+        self.gc();
+        let seq = self.seq.load(Ordering::Acquire);
+        (self.views.insert(seq, timeout), seq)
+    }
+
+    /// 释放读视图并清理不再需要的旧值，调用方需持有 barrier 写锁
+    pub fn release(&self, id: u64) -> bool {
+        let released = self.views.remove(id);
+        self.gc();
+        released
+    }
+
+    /// 有读视图时记录 key 写入前的值，调用方需持有 barrier 读锁，并保证同一个 key 的写入串行
+    ///
+    /// 读视图都已释放或超时时顺便清理旧值，有读视图时每隔 GC_INTERVAL 次写入清理一次
+    pub fn record(&self, key: &str, old: impl FnOnce() -> T) {
+        if self.views.is_empty() {
+            if self.pending.swap(false, Ordering::AcqRel) {
+                self.versions.clear();
+            }
+            return;
+        }
+        let seq = self.seq.fetch_add(1, Ordering::AcqRel) + 1;
+        if seq.is_multiple_of(GC_INTERVAL) {
+            self.gc();
+        }
+        self.versions
+            .entry(key.to_string())
+            .or_default()
+            .push((seq, old()));
+        self.pending.store(true, Ordering::Release);
+    }
+
+    /// 读视图 seq 之后第一次写入前的值即为视图中的值，之后没有写入时返回 None
+    pub fn before(&self, key: &str, seq: u64) -> Option<T> {
+        let versions = self.versions.get(key)?;
+        versions
+            .iter()
+            .find(|(s, _)| *s > seq)
+            .map(|(_, old)| old.clone())
+    }
+
+    /// 清理所有读视图都不再需要的旧值
+    fn gc(&self) {
+        match self.views.active().into_iter().min() {
+            Some(min) => self.versions.retain(|_, versions| {
+                versions.retain(|(seq, _)| *seq > min);
+                !versions.is_empty()
+            }),
+            None => self.versions.clear(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn read_views_should_expire() {
+        let views = ReadViews::default();
+        let a = views.insert("a", Duration::from_secs(60));
+        let b = views.insert("b", Duration::from_millis(1));
+        assert_ne!(a, b);
+
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(views.get(a).unwrap(), "a");
+        assert!(matches!(views.get(b), Err(HikvError::SnapshotNotFound(_))));
+        assert_eq!(views.active(), vec!["a"]);
+
+        assert!(views.remove(a));
+        assert!(!views.remove(a));
+        assert!(views.is_empty());
+    }
+
+    #[test]
+    fn history_should_gc_after_views_expire() {
+        let history = History::default();
+        history.record("k", || 0);
+        assert!(history.versions.is_empty());
+
+        let (_, seq) = history.create(Duration::from_millis(1));
+        history.record("k", || 1);
+        history.record("k", || 2);
+        assert_eq!(history.before("k", seq), Some(1));
+        assert_eq!(history.before("other", seq), None);
+
+        // 视图超时后的第一次写入清理旧值
+        thread::sleep(Duration::from_millis(5));
+        history.record("k", || 3);
+        assert!(history.versions.is_empty());
+    }
+}
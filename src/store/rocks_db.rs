@@ -1,21 +1,131 @@
-use std::path::Path;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::Path,
+    str,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
 
-use rocksdb::DB;
+use rocksdb::{checkpoint::Checkpoint, IteratorMode, Snapshot, WriteBatch, DB};
 
-use crate::{HikvError, Storage, Value};
+use crate::{
+    decode_entry, BackupManifest, CompressOptions, Compressor, Digest, HikvError, KeyMeta, Kvpair,
+    ReadViews, Storage, StorageIter, StorageKind, StorageStats, Value, VersionClock,
+};
 
-pub struct RocksDb(DB);
+/// 按 key 分段的写锁数量
+const LOCK_STRIPES: usize = 64;
+
+/// Info 中输出的 rocksdb 属性
+const PROPERTIES: &[&str] = &[
+    "rocksdb.estimate-num-keys",
+    "rocksdb.total-sst-files-size",
+    "rocksdb.live-sst-files-size",
+    "rocksdb.cur-size-all-mem-tables",
+    "rocksdb.estimate-pending-compaction-bytes",
+    "rocksdb.num-running-compactions",
+];
+
+pub struct RocksDb {
+    db: Arc<DB>,
+    /// 按 key 分段串行化写操作，保证同一个 key 的读旧版本和写入之间不被打断
+    locks: Vec<Mutex<()>>,
+    /// 分配写入的版本号
+    versions: VersionClock,
+    views: ReadViews<Arc<DbSnapshot>>,
+    /// 超过阈值的 value 压缩后写入，rocksdb 自身的 lz4 只压缩 block
+    compressor: Compressor,
+}
+
+/// rocksdb 快照，持有 db 的引用计数保证快照释放前 db 一直有效
+///
+/// snapshot 字段私有，只通过 get 按 &self 的生命周期使用，伪造的 'static 不会泄漏出去
+struct DbSnapshot {
+    // 字段按声明顺序 drop: snapshot 先释放，之后才会释放 _db 持有的引用计数
+    snapshot: Snapshot<'static>,
+    _db: Arc<DB>,
+}
+
+impl DbSnapshot {
+    fn new(db: &Arc<DB>) -> Self {
+        let snapshot = db.snapshot();
+        // SAFETY: Snapshot<'a> 只借用 &'a DB。DB 分配在 Arc 的堆内存中，Arc 移动时地址不变；
+        // 同一结构体的 _db 持有引用计数，并且按字段声明顺序在 snapshot 之后 drop，
+        // 所以 snapshot 存在期间 DB 不会被释放。snapshot 不会被移出结构体，见 get。
+        let snapshot = unsafe { std::mem::transmute::<Snapshot<'_>, Snapshot<'static>>(snapshot) };
+        Self {
+            snapshot,
+            _db: db.clone(),
+        }
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, HikvError> {
+        Ok(self.snapshot.get(key)?)
+    }
+}
 
 impl RocksDb {
     pub fn new(path: impl AsRef<Path>) -> Self {
-        Self(DB::open_default(path).unwrap())
+        Self::open(path).unwrap()
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, HikvError> {
+        Ok(Self {
+            db: Arc::new(DB::open_default(path)?),
+            locks: (0..LOCK_STRIPES).map(|_| Mutex::new(())).collect(),
+            versions: VersionClock::default(),
+            views: ReadViews::default(),
+            compressor: Compressor::default(),
+        })
+    }
+
+    /// 设置 value 压缩，只影响之后的写入
+    pub fn compression(mut self, options: CompressOptions) -> Self {
+        self.compressor = Compressor::new(options);
+        self
+    }
+
+    fn stripe(&self, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() as usize % self.locks.len()
+    }
+
+    fn lock(&self, key: &str) -> MutexGuard<'_, ()> {
+        self.locks[self.stripe(key)].lock().unwrap()
+    }
+
+    /// 按下标顺序锁住这些 key 所在的分段，避免互相等待
+    fn lock_keys<'a>(&self, keys: impl Iterator<Item = &'a str>) -> Vec<MutexGuard<'_, ()>> {
+        let mut stripes: Vec<_> = keys.map(|key| self.stripe(key)).collect();
+        stripes.sort_unstable();
+        stripes.dedup();
+        stripes
+            .into_iter()
+            .map(|i| self.locks[i].lock().unwrap())
+            .collect()
     }
-}
 
-/// 把 Result<Option<Vec<u8>>, rocksdb::Error> convert 成 Result<Option<Value>, HikvError>
-fn convert(x: Result<Option<Vec<u8>>, rocksdb::Error>) -> Result<Option<Value>, HikvError> {
-    x.map_err(|e| e.into())
-        .map(|v| v.map(|v| Value::try_from(&v[..]).unwrap()))
+    fn get_entry(&self, key: &str) -> Result<Option<(Value, KeyMeta)>, HikvError> {
+        self.db.get(key)?.map(|v| decode_entry(&v)).transpose()
+    }
+
+    fn put(
+        &self,
+        key: String,
+        value: Value,
+        expected: Option<u64>,
+    ) -> Result<Option<Value>, HikvError> {
+        let _guard = self.lock(&key);
+        let old = self.get_entry(&key)?;
+        let meta = self
+            .versions
+            .update(&key, old.as_ref().map(|(_, m)| m), expected)?;
+        self.db
+            .put(key, self.compressor.encode_entry(&value, &meta)?)?;
+        Ok(old.map(|(v, _)| v))
+    }
 }
 
 impl Storage for RocksDb {
@@ -24,33 +134,132 @@ impl Storage for RocksDb {
         key: impl Into<String>,
         value: impl Into<crate::Value>,
     ) -> Result<Option<crate::Value>, crate::HikvError> {
-        let key = key.into();
-        let ret = self.0.get(key.clone());
-        if ret.is_ok() {
-            let data: Vec<u8> = value.into().try_into()?;
-            if let Err(e) = self.0.put(key, data) {
-                return Err(e.into());
-            }
-        }
-        return convert(ret);
+        self.put(key.into(), value.into(), None)
     }
 
     fn get(&self, key: &str) -> Result<Option<crate::Value>, crate::HikvError> {
-        let ret = self.0.get(key.clone());
-        return convert(ret);
+        Ok(self.get_entry(key)?.map(|(v, _)| v))
+    }
+
+    fn get_with_meta(&self, key: &str) -> Result<Option<(Value, KeyMeta)>, HikvError> {
+        self.get_entry(key)
+    }
+
+    fn set_if_version(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        expected: u64,
+    ) -> Result<Option<Value>, HikvError> {
+        self.put(key.into(), value.into(), Some(expected))
     }
 
     fn del(&self, key: &str) -> Result<Option<crate::Value>, crate::HikvError> {
-        let ret = self.0.get(key);
-        if ret.is_ok() {
-            if let Err(e) = self.0.delete(key) {
-                return Err(e.into());
-            }
+        let _guard = self.lock(key);
+        let old = self.get(key)?;
+        if old.is_some() {
+            self.db.delete(key)?;
         }
-        return convert(ret);
+        Ok(old)
     }
 
+    /// key_may_exist 可能误报，命中时再读一次确认
     fn contains(&self, key: &str) -> Result<bool, crate::HikvError> {
-        Ok(self.0.key_may_exist(key))
+        Ok(self.db.key_may_exist(key) && self.db.get(key)?.is_some())
+    }
+
+    fn get_iter(&self) -> Result<StorageIter<'_>, HikvError> {
+        let iter = self.db.iterator(IteratorMode::Start).map(|(k, v)| {
+            let key = str::from_utf8(&k).map_err(|e| HikvError::Internal(e.to_string()))?;
+            Ok(Kvpair::new(key, decode_entry(&v)?.0))
+        });
+        Ok(Box::new(iter))
+    }
+
+    fn set_all(&self, pairs: Vec<Kvpair>) -> Result<(), HikvError> {
+        let _guards = self.lock_keys(pairs.iter().map(|pair| pair.key.as_str()));
+        let mut batch = WriteBatch::default();
+        for pair in pairs {
+            let old = self.get_entry(&pair.key)?;
+            let meta = self
+                .versions
+                .update(&pair.key, old.as_ref().map(|(_, m)| m), None)?;
+            batch.put(
+                &pair.key,
+                self.compressor
+                    .encode_entry(&pair.value.unwrap_or_default(), &meta)?,
+            );
+        }
+        Ok(self.db.write(batch)?)
+    }
+
+    fn create_snapshot(&self, timeout: Duration) -> Result<u64, HikvError> {
+        let snapshot = DbSnapshot::new(&self.db);
+        Ok(self.views.insert(Arc::new(snapshot), timeout))
+    }
+
+    fn get_at(&self, snapshot: u64, key: &str) -> Result<Option<Value>, HikvError> {
+        let view = self.views.get(snapshot)?;
+        let data = view.get(key)?;
+        Ok(data.map(|v| decode_entry(&v)).transpose()?.map(|(v, _)| v))
+    }
+
+    fn release_snapshot(&self, snapshot: u64) -> Result<bool, HikvError> {
+        Ok(self.views.remove(snapshot))
+    }
+
+    fn flush(&self) -> Result<(), HikvError> {
+        Ok(self.db.flush()?)
+    }
+
+    /// 整理全部 key 范围
+    fn compact(&self) -> Result<(), HikvError> {
+        self.db.compact_range::<&[u8], &[u8]>(None, None);
+        Ok(())
+    }
+
+    /// 生成 checkpoint，同一文件系统上 sst 文件以硬链接共享，不支持增量
+    fn backup(&self, path: &Path, incremental: bool) -> Result<BackupManifest, HikvError> {
+        if incremental {
+            return Err(HikvError::Unsupported("IncrementalBackup"));
+        }
+        if path.exists() {
+            return Err(HikvError::BackupError(format!(
+                "backup target {:?} already exists",
+                path
+            )));
+        }
+        // 持有全部写锁，checkpoint 和用于计算摘要的快照是同一时刻的数据
+        let snapshot = {
+            let _guards: Vec<_> = self.locks.iter().map(|l| l.lock().unwrap()).collect();
+            Checkpoint::new(&self.db)?.create_checkpoint(path)?;
+            self.db.snapshot()
+        };
+        let mut digest = Digest::default();
+        for (k, v) in snapshot.iterator(IteratorMode::Start) {
+            let key = str::from_utf8(&k).map_err(|e| HikvError::Internal(e.to_string()))?;
+            digest.update(&Kvpair::new(key, decode_entry(&v)?.0))?;
+        }
+        Ok(BackupManifest::new(StorageKind::Rocks, digest, false))
+    }
+
+    /// key 数量和逻辑大小取 rocksdb 的估算值，不遍历数据
+    fn stats(&self) -> Result<StorageStats, HikvError> {
+        let property = |name| Ok::<_, HikvError>(self.db.property_int_value(name)?.unwrap_or(0));
+        let mut stats = StorageStats {
+            keys: property("rocksdb.estimate-num-keys")?,
+            logical_bytes: property("rocksdb.estimate-live-data-size")?,
+            ..Default::default()
+        };
+        for name in PROPERTIES {
+            if let Some(v) = self.db.property_int_value(name)? {
+                stats = stats.with(*name, v as i64);
+            }
+        }
+        stats.disk_bytes = self
+            .db
+            .property_int_value("rocksdb.total-sst-files-size")?
+            .unwrap_or(0);
+        Ok(stats.with_compression(self.compressor.stats()))
     }
 }
@@ -0,0 +1,634 @@
+use std::{
+    fs,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    Digest, HikvError, KeyMeta, Kvpair, ReadViews, Storage, StorageIter, StorageStats, Value,
+};
+
+/// 每个分片在哈希环上的虚拟节点数
+const VIRTUAL_NODES: usize = 160;
+/// 分片目录中的描述文件
+pub const SHARD_MANIFEST: &str = "SHARD";
+/// 分片目录中交给后端的数据路径
+pub const SHARD_DATA: &str = "data";
+/// 重新分片时每批搬移的 key 数量
+const REBALANCE_BATCH: usize = 1000;
+
+/// 分片目录的描述，防止以错误的顺序或数量打开分片
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardManifest {
+    pub index: usize,
+    pub count: usize,
+    /// 正在重新分片到的数量，重新分片中断时不能打开
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rebalancing: Option<usize>,
+    /// 重新分片前全部数据的摘要，中断后继续时用它校验
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<Digest>,
+}
+
+impl ShardManifest {
+    pub fn load(dir: impl AsRef<Path>) -> Result<Option<Self>, HikvError> {
+        let path = dir.as_ref().join(SHARD_MANIFEST);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path)?;
+        serde_yaml::from_str(&content)
+            .map(Some)
+            .map_err(|e| HikvError::ShardError(e.to_string()))
+    }
+
+    fn store(&self, dir: &Path) -> Result<(), HikvError> {
+        let content =
+            serde_yaml::to_string(self).map_err(|e| HikvError::ShardError(e.to_string()))?;
+        let path = dir.join(SHARD_MANIFEST);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// 一致性哈希环，分片的位置只和分片序号有关，增减分片时只有约 1/N 的 key 需要搬移
+#[derive(Clone, Debug)]
+pub struct HashRing {
+    points: Vec<(u64, usize)>,
+}
+
+impl HashRing {
+    pub fn new(shards: usize) -> Self {
+        let mut points: Vec<_> = (0..shards)
+            .flat_map(|shard| {
+                (0..VIRTUAL_NODES)
+                    .map(move |v| (hash(format!("shard-{}#{}", shard, v).as_bytes()), shard))
+            })
+            .collect();
+        points.sort_unstable();
+        Self { points }
+    }
+
+    /// key 所在的分片: 顺时针方向第一个虚拟节点
+    pub fn shard(&self, key: &str) -> usize {
+        let hash = hash(key.as_bytes());
+        let i = self.points.partition_point(|(point, _)| *point < hash);
+        self.points[i % self.points.len()].1
+    }
+}
+
+/// FNV-1a 加 murmur3 的 fmix64 打散高位，结果决定 key 落在哪个分片，必须跨版本稳定
+fn hash(data: &[u8]) -> u64 {
+    let mut h = data.iter().fold(0xcbf29ce484222325, |h: u64, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    });
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^ (h >> 33)
+}
+
+/// 按一致性哈希把 key 分散到多个存储
+///
+/// 单 key 操作只访问一个分片；get_iter 归并各分片的有序结果，stats 汇总各分片。
+/// set_all 按分片分组写入，只在分片内原子；快照在各分片上依次创建，不是跨分片的同一时刻
+pub struct ShardedStorage<S> {
+    shards: Vec<S>,
+    ring: HashRing,
+    /// 快照 id -> 各分片上的快照 id
+    views: ReadViews<Arc<Vec<u64>>>,
+}
+
+impl<S: Storage> ShardedStorage<S> {
+    /// 使用已打开的分片，顺序决定 key 的分布
+    pub fn new(shards: Vec<S>) -> Self {
+        assert!(
+            !shards.is_empty(),
+            "ShardedStorage needs at least one shard"
+        );
+        Self {
+            ring: HashRing::new(shards.len()),
+            shards,
+            views: ReadViews::default(),
+        }
+    }
+
+    /// 打开每个目录下的分片，数据位于 `<dir>/data`，open 负责打开具体后端
+    ///
+    /// 新目录会写入分片描述；已有描述和目录顺序、数量不一致时报错
+    pub fn open<F>(dirs: &[PathBuf], open: F) -> Result<Self, HikvError>
+    where
+        F: Fn(&Path) -> Result<S, HikvError>,
+    {
+        if dirs.is_empty() {
+            return Err(HikvError::ShardError("no shard directory".into()));
+        }
+        let mut shards = Vec::with_capacity(dirs.len());
+        for (index, dir) in dirs.iter().enumerate() {
+            let expected = ShardManifest {
+                index,
+                count: dirs.len(),
+                rebalancing: None,
+                digest: None,
+            };
+            match ShardManifest::load(dir)? {
+                Some(manifest) if manifest.rebalancing.is_some() => {
+                    return Err(HikvError::ShardError(format!(
+                        "{:?} is being rebalanced, finish it with the rebalance tool first",
+                        dir
+                    )))
+                }
+                Some(manifest) if manifest != expected => {
+                    return Err(HikvError::ShardError(format!(
+                        "{:?} is shard {} of {}, but opened as shard {} of {}",
+                        dir, manifest.index, manifest.count, index, expected.count
+                    )))
+                }
+                Some(_) => {}
+                None => {
+                    fs::create_dir_all(dir)?;
+                    expected.store(dir)?;
+                }
+            }
+            shards.push(open(&dir.join(SHARD_DATA))?);
+        }
+        Ok(Self::new(shards))
+    }
+
+    pub fn shards(&self) -> &[S] {
+        &self.shards
+    }
+
+    /// key 所在的分片
+    pub fn shard(&self, key: &str) -> &S {
+        &self.shards[self.ring.shard(key)]
+    }
+}
+
+impl<S: Storage> Storage for ShardedStorage<S> {
+    fn set(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, HikvError> {
+        let key = key.into();
+        self.shard(&key).set(key.as_str(), value)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Value>, HikvError> {
+        self.shard(key).get(key)
+    }
+
+    fn get_with_meta(&self, key: &str) -> Result<Option<(Value, KeyMeta)>, HikvError> {
+        self.shard(key).get_with_meta(key)
+    }
+
+    fn set_if_version(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        expected: u64,
+    ) -> Result<Option<Value>, HikvError> {
+        let key = key.into();
+        self.shard(&key)
+            .set_if_version(key.as_str(), value, expected)
+    }
+
+    fn meta(&self, key: &str) -> Result<Option<KeyMeta>, HikvError> {
+        self.shard(key).meta(key)
+    }
+
+    fn del(&self, key: &str) -> Result<Option<Value>, HikvError> {
+        self.shard(key).del(key)
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, HikvError> {
+        self.shard(key).contains(key)
+    }
+
+    fn get_iter(&self) -> Result<StorageIter<'_>, HikvError> {
+        let iters = self
+            .shards
+            .iter()
+            .map(|s| s.get_iter().map(Iterator::peekable))
+            .collect::<Result<_, _>>()?;
+        Ok(Box::new(MergeIter { iters }))
+    }
+
+    fn set_all(&self, pairs: Vec<Kvpair>) -> Result<(), HikvError> {
+        let mut groups: Vec<Vec<Kvpair>> = self.shards.iter().map(|_| Vec::new()).collect();
+        for pair in pairs {
+            groups[self.ring.shard(&pair.key)].push(pair);
+        }
+        for (shard, pairs) in self.shards.iter().zip(groups) {
+            if !pairs.is_empty() {
+                shard.set_all(pairs)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), HikvError> {
+        self.shards.iter().try_for_each(|s| s.flush())
+    }
+
+    fn compact(&self) -> Result<(), HikvError> {
+        self.shards.iter().try_for_each(|s| s.compact())
+    }
+
+    /// 汇总各分片的 key 数量和字节数，引擎计数器加上 `shard<N>.` 前缀
+    fn stats(&self) -> Result<StorageStats, HikvError> {
+        let mut stats = StorageStats::default();
+        for (i, shard) in self.shards.iter().enumerate() {
+            let s = shard.stats()?;
+            stats.keys += s.keys;
+            stats.logical_bytes += s.logical_bytes;
+            stats.disk_bytes += s.disk_bytes;
+            for (name, value) in s.engine {
+                stats.engine.insert(format!("shard{}.{}", i, name), value);
+            }
+        }
+        Ok(stats.with("sharded.shards", self.shards.len() as i64))
+    }
+
+    fn create_snapshot(&self, timeout: Duration) -> Result<u64, HikvError> {
+        let mut ids = Vec::with_capacity(self.shards.len());
+        for shard in &self.shards {
+            match shard.create_snapshot(timeout) {
+                Ok(id) => ids.push(id),
+                Err(e) => {
+                    for (shard, id) in self.shards.iter().zip(ids) {
+                        shard.release_snapshot(id)?;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(self.views.insert(Arc::new(ids), timeout))
+    }
+
+    fn get_at(&self, snapshot: u64, key: &str) -> Result<Option<Value>, HikvError> {
+        let ids = self.views.get(snapshot)?;
+        let i = self.ring.shard(key);
+        self.shards[i].get_at(ids[i], key)
+    }
+
+    fn release_snapshot(&self, snapshot: u64) -> Result<bool, HikvError> {
+        let ids = match self.views.get(snapshot) {
+            Ok(ids) => ids,
+            Err(_) => return Ok(false),
+        };
+        self.views.remove(snapshot);
+        for (shard, id) in self.shards.iter().zip(ids.iter()) {
+            shard.release_snapshot(*id)?;
+        }
+        Ok(true)
+    }
+
+    fn save(&self) -> Result<usize, HikvError> {
+        self.shards.iter().map(|s| s.save()).sum()
+    }
+
+    fn bg_save(&self) -> Result<(), HikvError> {
+        self.shards.iter().try_for_each(|s| s.bg_save())
+    }
+
+    fn evicted(&self) -> u64 {
+        self.shards.iter().map(|s| s.evicted()).sum()
+    }
+}
+
+/// 归并多个按 key 升序的迭代器
+struct MergeIter<'a> {
+    iters: Vec<Peekable<StorageIter<'a>>>,
+}
+
+impl Iterator for MergeIter<'_> {
+    type Item = Result<Kvpair, HikvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut min: Option<(usize, String)> = None;
+        for (i, iter) in self.iters.iter_mut().enumerate() {
+            match iter.peek() {
+                Some(Ok(pair)) if min.as_ref().is_none_or(|(_, key)| pair.key < *key) => {
+                    min = Some((i, pair.key.clone()));
+                }
+                Some(Ok(_)) => {}
+                // 错误立即返回
+                Some(Err(_)) => return iter.next(),
+                None => {}
+            }
+        }
+        min.and_then(|(i, _)| self.iters[i].next())
+    }
+}
+
+/// 重新分片结果
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RebalanceReport {
+    /// 搬移到其他分片的 key 数量
+    pub moved: u64,
+    pub digest: Digest,
+}
+
+/// 离线把分片数量调整为 shards，dirs 需要包含调整前后用到的全部分片目录
+///
+/// 只搬移所属分片发生变化的 key，先写入新分片再从旧分片删除；
+/// 开始前把全部数据的摘要记在分片描述中，中断后分片不能直接打开，
+/// 用同样的参数重新运行即可继续，已写入新分片但未删除的 key 会被再次搬移，最后按原摘要校验。
+/// 缩容后多出的目录不再属于分片，可以手动删除；被搬移的 key 元数据重新计算
+pub fn rebalance<S, F>(
+    dirs: &[PathBuf],
+    shards: usize,
+    open: F,
+) -> Result<RebalanceReport, HikvError>
+where
+    S: Storage,
+    F: Fn(&Path) -> Result<S, HikvError>,
+{
+    let first = dirs
+        .first()
+        .ok_or_else(|| HikvError::ShardError("no shard directory".into()))?;
+    let (current, resumed) = match ShardManifest::load(first)? {
+        Some(manifest) => (manifest.count, manifest.digest),
+        None => return Err(HikvError::ShardError(format!("{:?} is not a shard", first))),
+    };
+    let total = current.max(shards);
+    if shards == 0 || dirs.len() < total {
+        return Err(HikvError::ShardError(format!(
+            "rebalance {} shards to {} needs {} directories, got {}",
+            current,
+            shards,
+            total,
+            dirs.len()
+        )));
+    }
+
+    for (index, dir) in dirs[..total].iter().enumerate() {
+        if let Some(manifest) = ShardManifest::load(dir)? {
+            if manifest.index != index || manifest.count != current {
+                return Err(HikvError::ShardError(format!(
+                    "{:?} is shard {} of {}, expect shard {} of {}",
+                    dir, manifest.index, manifest.count, index, current
+                )));
+            }
+            if let Some(target) = manifest.rebalancing.filter(|&n| n != shards) {
+                return Err(HikvError::ShardError(format!(
+                    "{:?} is being rebalanced to {} shards, not {}",
+                    dir, target, shards
+                )));
+            }
+        }
+        fs::create_dir_all(dir)?;
+    }
+
+    let stores = dirs[..total]
+        .iter()
+        .map(|dir| open(&dir.join(SHARD_DATA)))
+        .collect::<Result<Vec<_>, _>>()?;
+    // 中断后继续时部分 key 可能同时在新旧分片中，只能使用第一次运行时的摘要
+    let before = match resumed {
+        Some(digest) => digest,
+        None => digest_all(&stores)?,
+    };
+    // 先在所有目录记下目标数量和摘要，中断后不会被当作正常分片打开
+    for (index, dir) in dirs[..total].iter().enumerate() {
+        ShardManifest {
+            index,
+            count: current,
+            rebalancing: Some(shards),
+            digest: Some(before),
+        }
+        .store(dir)?;
+    }
+    let ring = HashRing::new(shards);
+
+    let mut moved = 0;
+    for (from, store) in stores.iter().enumerate() {
+        // 先找出要搬移的 key，避免遍历时修改分片
+        let mut keys = Vec::new();
+        for pair in store.get_iter()? {
+            let key = pair?.key;
+            let to = ring.shard(&key);
+            if to != from {
+                keys.push((key, to));
+            }
+        }
+        for chunk in keys.chunks(REBALANCE_BATCH) {
+            moved += move_batch(store, &stores, chunk)?;
+        }
+    }
+
+    let after = digest_all(&stores[..shards])?;
+    if after != before {
+        return Err(HikvError::ShardError(format!(
+            "rebalance verify failed: before {:?}, after {:?}",
+            before, after
+        )));
+    }
+    for (index, dir) in dirs[..total].iter().enumerate() {
+        if index < shards {
+            ShardManifest {
+                index,
+                count: shards,
+                rebalancing: None,
+                digest: None,
+            }
+            .store(dir)?;
+        } else {
+            fs::remove_file(dir.join(SHARD_MANIFEST))?;
+        }
+    }
+    info!(
+        "Rebalanced {} shards to {}, moved {} keys",
+        current, shards, moved
+    );
+    Ok(RebalanceReport {
+        moved,
+        digest: after,
+    })
+}
+
+/// 写入目标分片并 flush 后再从源分片删除
+fn move_batch<S: Storage>(
+    from: &S,
+    stores: &[S],
+    keys: &[(String, usize)],
+) -> Result<u64, HikvError> {
+    let mut groups: Vec<Vec<Kvpair>> = stores.iter().map(|_| Vec::new()).collect();
+    for (key, to) in keys {
+        if let Some(value) = from.get(key)? {
+            groups[*to].push(Kvpair::new(key.as_str(), value));
+        }
+    }
+    for (to, pairs) in groups.into_iter().enumerate() {
+        if !pairs.is_empty() {
+            stores[to].set_all(pairs)?;
+            stores[to].flush()?;
+        }
+    }
+    for (key, _) in keys {
+        from.del(key)?;
+    }
+    from.flush()?;
+    Ok(keys.len() as u64)
+}
+
+fn digest_all<S: Storage>(stores: &[S]) -> Result<Digest, HikvError> {
+    let mut digest = Digest::default();
+    for store in stores {
+        for pair in store.get_iter()? {
+            digest.update(&pair?)?;
+        }
+    }
+    Ok(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        Bitcask, BitcaskOptions, Fault, FaultKind, FaultRule, FaultyStorage, MemTable, StorageOp,
+    };
+
+    fn open_bitcask(path: &Path) -> Result<Bitcask, HikvError> {
+        let opts = BitcaskOptions {
+            merge_interval: None,
+            ..Default::default()
+        };
+        Bitcask::open(path, opts)
+    }
+
+    fn dirs(root: &Path, n: usize) -> Vec<PathBuf> {
+        (0..n).map(|i| root.join(format!("shard{}", i))).collect()
+    }
+
+    #[test]
+    fn hash_ring_should_spread_keys_and_move_few_on_resize() {
+        let ring = HashRing::new(4);
+        let mut counts = [0; 4];
+        for i in 0..10000 {
+            counts[ring.shard(&format!("key{}", i))] += 1;
+        }
+        assert!(counts.iter().all(|&n| n > 1500), "{:?}", counts);
+
+        let grown = HashRing::new(5);
+        let moved = (0..10000)
+            .map(|i| format!("key{}", i))
+            .filter(|k| ring.shard(k) != grown.shard(k))
+            .count();
+        assert!(moved < 3000, "moved {}", moved);
+    }
+
+    #[test]
+    fn cross_shard_scan_and_stats_should_merge() {
+        let store = ShardedStorage::new((0..3).map(|_| MemTable::new()).collect());
+        for i in (0..50).rev() {
+            store.set(format!("key{:02}", i), Value::from(i)).unwrap();
+        }
+        assert!(store
+            .shards()
+            .iter()
+            .all(|s| s.get_iter().unwrap().count() > 0));
+
+        let keys: Vec<_> = store.get_iter().unwrap().map(|p| p.unwrap().key).collect();
+        let expected: Vec<_> = (0..50).map(|i| format!("key{:02}", i)).collect();
+        assert_eq!(keys, expected);
+
+        let stats = store.stats().unwrap();
+        assert_eq!(stats.keys, 50);
+        assert!(stats.engine.contains_key("shard2.memory.used"));
+
+        let id = store.create_snapshot(Duration::from_secs(60)).unwrap();
+        store.del("key07").unwrap();
+        assert_eq!(store.get_at(id, "key07").unwrap(), Some(7.into()));
+        assert!(store.release_snapshot(id).unwrap());
+        assert!(!store.release_snapshot(id).unwrap());
+    }
+
+    #[test]
+    fn open_should_check_shard_layout() {
+        let dir = tempdir().unwrap();
+        let shards = dirs(dir.path(), 3);
+        let store = ShardedStorage::open(&shards, open_bitcask).unwrap();
+        store.set("hello", "world").unwrap();
+        drop(store);
+
+        assert!(ShardedStorage::open(&shards[..2], open_bitcask).is_err());
+        let swapped = vec![shards[1].clone(), shards[0].clone(), shards[2].clone()];
+        assert!(ShardedStorage::open(&swapped, open_bitcask).is_err());
+        let store = ShardedStorage::open(&shards, open_bitcask).unwrap();
+        assert_eq!(store.get("hello").unwrap(), Some("world".into()));
+    }
+
+    #[test]
+    fn rebalance_should_grow_and_shrink_shards() {
+        let dir = tempdir().unwrap();
+        let all = dirs(dir.path(), 4);
+        let store = ShardedStorage::open(&all[..2], open_bitcask).unwrap();
+        for i in 0..200 {
+            store.set(format!("key{}", i), Value::from(i)).unwrap();
+        }
+        let digest = Digest::of(&store).unwrap();
+        drop(store);
+
+        let report = rebalance(&all, 4, open_bitcask).unwrap();
+        assert_eq!(report.digest, digest);
+        assert!(report.moved > 0 && report.moved < 200);
+        let store = ShardedStorage::open(&all, open_bitcask).unwrap();
+        assert_eq!(Digest::of(&store).unwrap(), digest);
+        assert_eq!(store.get("key42").unwrap(), Some(42.into()));
+        drop(store);
+
+        rebalance(&all, 1, open_bitcask).unwrap();
+        assert!(ShardedStorage::open(&all[..2], open_bitcask).is_err());
+        let store = ShardedStorage::open(&all[..1], open_bitcask).unwrap();
+        assert_eq!(Digest::of(&store).unwrap(), digest);
+        assert!(ShardManifest::load(&all[3]).unwrap().is_none());
+    }
+
+    #[test]
+    fn rebalance_should_resume_after_crash() {
+        let dir = tempdir().unwrap();
+        let all = dirs(dir.path(), 3);
+        let store = ShardedStorage::open(&all[..2], open_bitcask).unwrap();
+        for i in 0..200 {
+            store.set(format!("key{}", i), Value::from(i)).unwrap();
+        }
+        let digest = Digest::of(&store).unwrap();
+        drop(store);
+
+        // 第一批写入新分片后、从旧分片删除前中断，key 同时存在于新旧分片
+        let crash = |path: &Path| {
+            Ok(FaultyStorage::new(open_bitcask(path)?, 1).rule(
+                FaultRule::new(Fault::Error(FaultKind::Io))
+                    .op(StorageOp::Del)
+                    .calls([1]),
+            ))
+        };
+        assert!(rebalance(&all, 3, crash).is_err());
+        assert!(ShardedStorage::open(&all, open_bitcask).is_err());
+        assert!(rebalance(&all, 2, open_bitcask).is_err());
+
+        let report = rebalance(&all, 3, open_bitcask).unwrap();
+        assert_eq!(report.digest, digest);
+        let store = ShardedStorage::open(&all, open_bitcask).unwrap();
+        assert_eq!(Digest::of(&store).unwrap(), digest);
+        assert_eq!(ShardManifest::load(&all[0]).unwrap().unwrap().digest, None);
+    }
+
+    #[test]
+    fn rebalance_should_reject_empty_dirs() {
+        assert!(rebalance(&[], 2, open_bitcask).is_err());
+    }
+}
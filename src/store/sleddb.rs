@@ -1,14 +1,145 @@
-use sled::Db;
-use std::{convert::TryInto, path::Path, str};
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError},
+    Db, IVec,
+};
+use std::{
+    path::Path,
+    str,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
 
-use crate::Storage;
+use crate::{
+    decode_entry, decode_value, BackupManifest, CompressOptions, Compressor, Digest, HikvError,
+    History, KeyMeta, Kvpair, Storage, StorageIter, StorageKind, StorageStats, Value, VersionClock,
+};
+
+/// 备份期间持有的读视图的超时
+const BACKUP_VIEW_TIMEOUT: Duration = Duration::from_secs(24 * 3600);
 
 #[derive(Debug)]
-pub struct SledDb(Db);
+pub struct SledDb {
+    db: Db,
+    /// 写操作持有读锁，创建和释放快照时持有写锁
+    barrier: RwLock<()>,
+    /// sled 没有时间点快照，有快照时写入前记录旧值
+    history: History<Option<IVec>>,
+    /// 超过阈值的 value 压缩后写入
+    compressor: Compressor,
+    /// 分配写入的版本号
+    versions: VersionClock,
+    /// key 数量，打开时统计一次，之后随写入维护
+    keys: AtomicI64,
+    /// key 和编码后 value 的总字节数
+    bytes: AtomicI64,
+}
 
 impl SledDb {
     pub fn new(path: impl AsRef<Path>) -> Self {
-        Self(sled::open(path).unwrap())
+        Self::open(path).unwrap()
+    }
+
+    /// 打开时遍历一次数据统计 key 数量和字节数
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, HikvError> {
+        let db = sled::open(path)?;
+        let (mut keys, mut bytes) = (0, 0);
+        for item in db.iter() {
+            let (k, v) = item?;
+            keys += 1;
+            bytes += (k.len() + v.len()) as i64;
+        }
+        Ok(Self {
+            db,
+            barrier: RwLock::new(()),
+            history: History::default(),
+            compressor: Compressor::default(),
+            versions: VersionClock::default(),
+            keys: AtomicI64::new(keys),
+            bytes: AtomicI64::new(bytes),
+        })
+    }
+
+    /// 设置 value 压缩，只影响之后的写入
+    pub fn compression(mut self, options: CompressOptions) -> Self {
+        self.compressor = Compressor::new(options);
+        self
+    }
+
+    /// 基于 compare-and-swap 写入，保证版本号和前置条件检查不受并发写入影响
+    fn put(
+        &self,
+        key: String,
+        value: Value,
+        expected: Option<u64>,
+    ) -> Result<Option<Value>, HikvError> {
+        let _guard = self.barrier.read().unwrap();
+        loop {
+            let current = self.db.get(&key)?;
+            let old = current.as_deref().map(decode_entry).transpose()?;
+            let meta = self
+                .versions
+                .update(&key, old.as_ref().map(|(_, m)| m), expected)?;
+            let data = self.compressor.encode_entry(&value, &meta)?;
+            // 先记录再写入，读快照时先读当前值再查 history
+            self.history.record(&key, || current.clone());
+            let len = data.len();
+            let old_len = current.as_ref().map(|v| v.len());
+            if self.db.compare_and_swap(&key, current, Some(data))?.is_ok() {
+                self.account(key.len(), old_len, Some(len));
+                return Ok(old.map(|(v, _)| v));
+            }
+        }
+    }
+
+    /// 写入成功后更新 key 数量和字节数
+    fn account(&self, key_len: usize, old: Option<usize>, new: Option<usize>) {
+        if let Some(len) = new {
+            self.keys.fetch_add(1, Ordering::Relaxed);
+            self.bytes
+                .fetch_add((key_len + len) as i64, Ordering::Relaxed);
+        }
+        if let Some(len) = old {
+            self.keys.fetch_sub(1, Ordering::Relaxed);
+            self.bytes
+                .fetch_sub((key_len + len) as i64, Ordering::Relaxed);
+        }
+    }
+
+    /// 读取快照 seq 时 key 的原始数据，必须先读当前值再查 history
+    fn get_raw_at(&self, seq: u64, key: &str) -> Result<Option<IVec>, HikvError> {
+        let current = self.db.get(key)?;
+        Ok(self.history.before(key, seq).unwrap_or(current))
+    }
+
+    /// 把快照 seq 时的全部数据写入 db: 快照后没有修改过的 key 取当前值，修改过的 key 取 history 中的旧值
+    fn copy_view(&self, seq: u64, db: &Db) -> Result<(), HikvError> {
+        for item in self.db.iter() {
+            let (k, v) = item?;
+            let key = str::from_utf8(&k).map_err(|e| HikvError::Internal(e.to_string()))?;
+            // 读到当前值之后再查 history
+            if let Some(v) = self.history.before(key, seq).unwrap_or(Some(v)) {
+                db.insert(k, v)?;
+            }
+        }
+        // 快照之后被删除、遍历时已经不在的 key，遍历中写入过的 key 不再重复写
+        let deleted: Vec<_> = self
+            .history
+            .versions
+            .iter()
+            .filter_map(|e| {
+                let old = e.value().iter().find(|(s, _)| *s > seq)?.1.clone()?;
+                Some((e.key().clone(), old))
+            })
+            .collect();
+        for (key, old) in deleted {
+            if !db.contains_key(&key)? {
+                db.insert(key, old)?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -23,25 +154,250 @@ impl Storage for SledDb {
         key: impl Into<String>,
         value: impl Into<crate::Value>,
     ) -> Result<Option<crate::Value>, crate::HikvError> {
-        let data: Vec<u8> = value.into().try_into()?;
-        let ret = self
-            .0
-            .insert(key.into(), data)?
-            .map(|v| v.as_ref().try_into());
-        flip(ret)
+        self.put(key.into(), value.into(), None)
     }
 
     fn get(&self, key: &str) -> Result<Option<crate::Value>, crate::HikvError> {
-        let ret = self.0.get(key)?.map(|v| v.as_ref().try_into());
+        let ret = self.db.get(key)?.map(|v| decode_value(&v));
         flip(ret)
     }
 
-    fn del(&self, key: &str) -> Result<Option<crate::Value>, crate::HikvError> {
-        let ret = self.0.remove(key)?.map(|v| v.as_ref().try_into());
+    fn get_with_meta(&self, key: &str) -> Result<Option<(Value, KeyMeta)>, HikvError> {
+        let ret = self.db.get(key)?.map(|v| decode_entry(&v));
         flip(ret)
     }
 
+    fn set_if_version(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        expected: u64,
+    ) -> Result<Option<Value>, HikvError> {
+        self.put(key.into(), value.into(), Some(expected))
+    }
+
+    fn del(&self, key: &str) -> Result<Option<crate::Value>, crate::HikvError> {
+        let _guard = self.barrier.read().unwrap();
+        loop {
+            let current = match self.db.get(key)? {
+                Some(current) => current,
+                None => return Ok(None),
+            };
+            self.history.record(key, || Some(current.clone()));
+            if self
+                .db
+                .compare_and_swap(key, Some(&current), None as Option<IVec>)?
+                .is_ok()
+            {
+                self.account(key.len(), Some(current.len()), None);
+                return decode_value(&current).map(Some);
+            }
+        }
+    }
+
     fn contains(&self, key: &str) -> Result<bool, crate::HikvError> {
-        Ok(self.0.contains_key(key)?)
+        Ok(self.db.contains_key(key)?)
+    }
+
+    fn get_iter(&self) -> Result<StorageIter<'_>, HikvError> {
+        let iter = self.db.iter().map(|item| {
+            let (k, v) = item?;
+            let key = str::from_utf8(&k).map_err(|e| HikvError::Internal(e.to_string()))?;
+            Ok(Kvpair::new(key, decode_value(&v)?))
+        });
+        Ok(Box::new(iter))
+    }
+
+    /// 在事务中整批原子写入，读旧版本和写入之间不受并发写入影响
+    fn set_all(&self, pairs: Vec<Kvpair>) -> Result<(), HikvError> {
+        let _guard = self.barrier.read().unwrap();
+        let ret = self.db.transaction(|tx| {
+            let mut changes = Vec::with_capacity(pairs.len());
+            for pair in &pairs {
+                let current = tx.get(&pair.key)?;
+                let encoded = current
+                    .as_deref()
+                    .map(decode_entry)
+                    .transpose()
+                    .and_then(|old| {
+                        let meta =
+                            self.versions
+                                .update(&pair.key, old.as_ref().map(|(_, m)| m), None)?;
+                        let value = pair.value.clone().unwrap_or_default();
+                        self.compressor.encode_entry(&value, &meta)
+                    });
+                let data = encoded.map_err(ConflictableTransactionError::Abort)?;
+                self.history.record(&pair.key, || current.clone());
+                changes.push((pair.key.len(), current.map(|v| v.len()), data.len()));
+                tx.insert(pair.key.as_bytes(), data)?;
+            }
+            Ok(changes)
+        });
+        let changes = ret.map_err(|e| match e {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => e.into(),
+        })?;
+        for (key_len, old, new) in changes {
+            self.account(key_len, old, Some(new));
+        }
+        Ok(())
+    }
+
+    /// 只在登记快照时短暂阻塞写入，之后的写入记录旧值
+    fn create_snapshot(&self, timeout: Duration) -> Result<u64, HikvError> {
+        let _guard = self.barrier.write().unwrap();
+        Ok(self.history.create(timeout).0)
+    }
+
+    fn get_at(&self, snapshot: u64, key: &str) -> Result<Option<Value>, HikvError> {
+        let seq = self.history.views.get(snapshot)?;
+        let ret = self.get_raw_at(seq, key)?.map(|v| decode_value(&v));
+        flip(ret)
+    }
+
+    fn release_snapshot(&self, snapshot: u64) -> Result<bool, HikvError> {
+        let _guard = self.barrier.write().unwrap();
+        Ok(self.history.release(snapshot))
+    }
+
+    fn flush(&self) -> Result<(), HikvError> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// 把同一时刻的全部数据写入新的 sled 数据目录，复制期间不阻塞写入，不支持增量
+    fn backup(&self, path: &Path, incremental: bool) -> Result<BackupManifest, HikvError> {
+        if incremental {
+            return Err(HikvError::Unsupported("IncrementalBackup"));
+        }
+        if path.exists() {
+            return Err(HikvError::BackupError(format!(
+                "backup target {:?} already exists",
+                path
+            )));
+        }
+        let db = sled::open(path)?;
+        let (id, seq) = {
+            let _guard = self.barrier.write().unwrap();
+            self.history.create(BACKUP_VIEW_TIMEOUT)
+        };
+        let ret = self.copy_view(seq, &db);
+        {
+            let _guard = self.barrier.write().unwrap();
+            self.history.release(id);
+        }
+        ret?;
+
+        let mut digest = Digest::default();
+        for item in db.iter() {
+            let (k, v) = item?;
+            let key = str::from_utf8(&k).map_err(|e| HikvError::Internal(e.to_string()))?;
+            digest.update(&Kvpair::new(key, decode_value(&v)?))?;
+        }
+        db.flush()?;
+        Ok(BackupManifest::new(StorageKind::Sled, digest, false))
+    }
+
+    /// sled 在后台自动整理，没有手动整理的接口
+    fn compact(&self) -> Result<(), HikvError> {
+        Err(HikvError::Unsupported("Compact"))
+    }
+
+    /// 用维护的计数器统计，不遍历数据；value 按编码后(含元数据)的大小计算
+    fn stats(&self) -> Result<StorageStats, HikvError> {
+        let mut stats = StorageStats {
+            keys: self.keys.load(Ordering::Relaxed).max(0) as u64,
+            logical_bytes: self.bytes.load(Ordering::Relaxed).max(0) as u64,
+            ..Default::default()
+        };
+        stats.disk_bytes = self.db.size_on_disk()?;
+        Ok(stats
+            .with("sled.trees", self.db.tree_names().len() as i64)
+            .with_compression(self.compressor.stats()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn should_work_sleddb_snapshot_reads() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(&dir);
+        store.set("hello", "world").unwrap();
+        store.set("lang", "rust").unwrap();
+
+        let id = store.create_snapshot(Duration::from_secs(60)).unwrap();
+        store.set("hello", "there").unwrap();
+        store.del("lang").unwrap();
+        store.set("new", "key").unwrap();
+
+        assert_eq!(store.get_at(id, "hello").unwrap(), Some("world".into()));
+        assert_eq!(store.get_at(id, "lang").unwrap(), Some("rust".into()));
+        assert_eq!(store.get_at(id, "new").unwrap(), None);
+        assert_eq!(store.get("hello").unwrap(), Some("there".into()));
+
+        assert!(store.release_snapshot(id).unwrap());
+        assert!(store.get_at(id, "hello").is_err());
+        assert!(store.history.versions.is_empty());
+    }
+
+    #[test]
+    fn sleddb_snapshot_should_expire() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(&dir);
+        let id = store.create_snapshot(Duration::from_millis(1)).unwrap();
+        store.set("hello", "world").unwrap();
+        thread::sleep(Duration::from_millis(5));
+        store.set("hello", "again").unwrap();
+        assert!(store.get_at(id, "hello").is_err());
+        assert!(store.history.versions.is_empty());
+    }
+
+    #[test]
+    fn stats_counters_should_match_data() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(&dir);
+        store.set("hello", "world").unwrap();
+        store.set("hello", "there").unwrap();
+        store
+            .set_all(vec![
+                Kvpair::new("a", 1.into()),
+                Kvpair::new("hello", "again".into()),
+            ])
+            .unwrap();
+        store.set("gone", "soon").unwrap();
+        store.del("gone").unwrap();
+        let stats = store.stats().unwrap();
+        assert_eq!(stats.keys, 2);
+
+        // 和打开时遍历统计的结果一致
+        let bytes: usize = store
+            .db
+            .iter()
+            .map(|item| item.map(|(k, v)| k.len() + v.len()).unwrap())
+            .sum();
+        assert_eq!(stats.logical_bytes, bytes as u64);
+    }
+
+    #[test]
+    fn backup_should_copy_all_data() {
+        let dir = tempdir().unwrap();
+        let store = SledDb::new(dir.path().join("data"));
+        store.set("hello", "world").unwrap();
+        store.set("lang", "rust").unwrap();
+
+        let path = dir.path().join("backup");
+        let manifest = store.backup(&path, false).unwrap();
+        assert_eq!(manifest.digest.count, 2);
+        assert!(store.history.views.is_empty());
+
+        let restored = SledDb::new(&path);
+        assert_eq!(restored.get("lang").unwrap(), Some("rust".into()));
     }
 }
@@ -0,0 +1,234 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use bytes::{Buf, BufMut};
+use crc32fast::Hasher;
+use prost::Message;
+
+use crate::{decode_entry, encode_entry, HikvError, KeyMeta, Value};
+
+/// 快照文件魔数
+const MAGIC: &[u8; 8] = b"HIKVSNAP";
+/// 快照格式版本，版本 2 的 value 带元数据
+const VERSION: u8 = 2;
+/// value 为 protobuf 编码、没有元数据的旧版本
+const VERSION_V1: u8 = 1;
+/// 文件头长度: magic + version + entry count
+const HEADER_LEN: usize = MAGIC.len() + 1 + 8;
+/// 校验和长度(crc32)
+const CHECKSUM_LEN: usize = 4;
+
+/// 把 entries 写入快照文件，返回写入的 entry 数量
+///
+/// 文件格式: magic | version | count | (key_len | key | value_len | value)* | crc32,
+/// value 为带元数据的存储格式。先写同目录下的唯一临时文件再 rename，
+/// 保证不会留下写了一半的快照，并发保存也不会写到同一个临时文件。
+/// entries 逐条写入，count 在写完后回填，不需要事先知道数量
+pub fn write_snapshot<I>(path: impl AsRef<Path>, entries: I) -> Result<usize, HikvError>
+where
+    I: IntoIterator<Item = (String, Value, KeyMeta)>,
+{
+    let path = path.as_ref();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let tmp = tempfile::NamedTempFile::new_in(dir)?;
+
+    let mut writer = BufWriter::new(tmp.as_file());
+    writer.write_all(&[0; HEADER_LEN])?;
+    let mut writer = ChecksumWriter::new(writer);
+    let mut count = 0;
+    let mut buf = Vec::new();
+    for (key, value, meta) in entries {
+        let data = encode_entry(&value, &meta)?;
+        buf.clear();
+        buf.put_u32(key.len() as _);
+        buf.put_slice(key.as_bytes());
+        buf.put_u32(data.len() as _);
+        buf.put_slice(&data);
+        writer.write_all(&buf)?;
+        count += 1;
+    }
+
+    // 校验和覆盖文件头，由文件头和 entries 两部分合并得到
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.put_slice(MAGIC);
+    header.put_u8(VERSION);
+    header.put_u64(count as u64);
+    let (mut inner, body) = writer.finish();
+    let mut hasher = Hasher::new();
+    hasher.update(&header);
+    hasher.combine(&body);
+    inner.write_all(&hasher.finalize().to_be_bytes())?;
+
+    let mut file = inner.into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header)?;
+    file.sync_all()?;
+    tmp.persist(path).map_err(|e| e.error)?;
+
+    Ok(count)
+}
+
+/// 读取并校验快照文件，兼容没有元数据的旧版本快照
+pub fn read_snapshot(path: impl AsRef<Path>) -> Result<Vec<(String, Value, KeyMeta)>, HikvError> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    if data.len() < HEADER_LEN + CHECKSUM_LEN || &data[..MAGIC.len()] != MAGIC {
+        return Err(HikvError::SnapshotError("invalid snapshot header".into()));
+    }
+
+    let (body, mut checksum) = data.split_at(data.len() - CHECKSUM_LEN);
+    if crc32fast::hash(body) != checksum.get_u32() {
+        return Err(HikvError::SnapshotError("checksum mismatch".into()));
+    }
+
+    let mut body = &body[MAGIC.len()..];
+    let version = body.get_u8();
+    if version != VERSION && version != VERSION_V1 {
+        return Err(HikvError::SnapshotError(format!(
+            "unsupported snapshot version {}",
+            version
+        )));
+    }
+
+    let count = body.get_u64() as usize;
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        let key = read_chunk(&mut body)?;
+        let key = String::from_utf8(key.to_vec())
+            .map_err(|_| HikvError::SnapshotError("key is not valid utf8".into()))?;
+        let data = read_chunk(&mut body)?;
+        let (value, meta) = match version {
+            VERSION_V1 => (Value::decode(data)?, KeyMeta::legacy()),
+            _ => decode_entry(data)?,
+        };
+        entries.push((key, value, meta));
+    }
+
+    if body.has_remaining() {
+        return Err(HikvError::SnapshotError(
+            "trailing bytes after entries".into(),
+        ));
+    }
+
+    Ok(entries)
+}
+
+/// 读取一段 len + data
+fn read_chunk<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8], HikvError> {
+    if buf.remaining() < 4 {
+        return Err(HikvError::SnapshotError(
+            "unexpected end of snapshot".into(),
+        ));
+    }
+    let len = buf.get_u32() as usize;
+    if buf.remaining() < len {
+        return Err(HikvError::SnapshotError(
+            "unexpected end of snapshot".into(),
+        ));
+    }
+    let (chunk, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(chunk)
+}
+
+/// 写入时同时计算 crc32
+struct ChecksumWriter<W> {
+    inner: W,
+    hasher: Hasher,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Hasher::new(),
+        }
+    }
+
+    fn finish(self) -> (W, Hasher) {
+        (self.inner, self.hasher)
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn should_work_snapshot_roundtrip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("dump.snap");
+        let entries: Vec<(String, Value, KeyMeta)> = vec![
+            ("hello".into(), "world".into(), KeyMeta::new(1)),
+            ("age".into(), 18.into(), KeyMeta::new(1).next(2)),
+            ("bin".into(), b"data".into(), KeyMeta::legacy()),
+        ];
+
+        let n = write_snapshot(&path, entries.clone()).unwrap();
+        assert_eq!(n, 3);
+
+        let loaded = read_snapshot(&path).unwrap();
+        assert_eq!(loaded, entries);
+    }
+
+    #[test]
+    fn should_reject_corrupted_snapshot() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("dump.snap");
+        let entries = vec![("hello".to_string(), Value::from("world"), KeyMeta::new(1))];
+        write_snapshot(&path, entries.into_iter()).unwrap();
+
+        let mut data = fs::read(&path).unwrap();
+        let i = data.len() - CHECKSUM_LEN - 1;
+        data[i] ^= 0xff;
+        fs::write(&path, data).unwrap();
+
+        let err = read_snapshot(&path).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"));
+    }
+
+    #[test]
+    fn should_read_v1_snapshot() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("dump.snap");
+        let value = Value::from("world");
+
+        let mut body = Vec::new();
+        body.put_slice(MAGIC);
+        body.put_u8(VERSION_V1);
+        body.put_u64(1);
+        body.put_u32(5);
+        body.put_slice(b"hello");
+        body.put_u32(value.encoded_len() as _);
+        value.encode(&mut body).unwrap();
+        let checksum = crc32fast::hash(&body);
+        body.put_u32(checksum);
+        fs::write(&path, body).unwrap();
+
+        let loaded = read_snapshot(&path).unwrap();
+        assert_eq!(loaded, vec![("hello".into(), value, KeyMeta::legacy())]);
+    }
+}
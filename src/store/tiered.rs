@@ -0,0 +1,440 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, Weak,
+    },
+    thread,
+    time::Duration,
+};
+
+use tracing::{error, info};
+
+use crate::{
+    BackupManifest, EvictionPolicy, HikvError, KeyMeta, MemTable, Storage, StorageIter,
+    StorageStats, Value,
+};
+
+/// 串行化同一个 key 的缓存和磁盘操作的锁数量
+const LOCK_STRIPES: usize = 64;
+
+/// 写入模式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteMode {
+    /// 同步写入磁盘，写入和读过的数据都放入缓存
+    WriteThrough,
+    /// 先写缓存，按间隔批量刷到磁盘；写回前对同一个 key 的多次修改合并为一次，只产生一个新版本
+    ///
+    /// 进程崩溃时尚未写回的修改会丢失。正常关闭前调用 [`Storage::flush`] 或
+    /// [`TieredStorage::flush_dirty`]，drop 时也会尽量写回
+    WriteBack { interval: Duration },
+}
+
+/// 缓存命中统计
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// 等待写回磁盘的 key 数量
+    pub dirty: usize,
+}
+
+/// 有内存上限的 MemTable 缓存 + 任意磁盘存储
+pub struct TieredStorage<S: Storage> {
+    inner: Arc<TieredInner<S>>,
+}
+
+struct TieredInner<S: Storage> {
+    cache: MemTable,
+    disk: S,
+    mode: WriteMode,
+    /// write-back 模式下尚未写回磁盘的修改，None 表示删除
+    dirty: Mutex<HashMap<String, Option<Value>>>,
+    locks: Vec<Mutex<()>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<S> TieredStorage<S>
+where
+    S: Storage + Send + Sync + 'static,
+{
+    /// 创建分层存储，cache_size 为缓存的内存上限(字节)
+    pub fn new(disk: S, cache_size: usize, mode: WriteMode) -> Self {
+        let inner = Arc::new(TieredInner {
+            cache: MemTable::new().max_memory(cache_size, EvictionPolicy::AllKeysLru),
+            disk,
+            mode,
+            dirty: Mutex::new(HashMap::new()),
+            locks: (0..LOCK_STRIPES).map(|_| Mutex::new(())).collect(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        });
+
+        if let WriteMode::WriteBack { interval } = mode {
+            spawn_flusher(Arc::downgrade(&inner), interval);
+        }
+
+        Self { inner }
+    }
+
+    /// 把 write-back 缓存中的修改写回磁盘，返回写回的 key 数量，关闭服务前需要调用
+    pub fn flush_dirty(&self) -> Result<usize, HikvError> {
+        self.inner.flush()
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.inner.hits.load(Ordering::Relaxed),
+            misses: self.inner.misses.load(Ordering::Relaxed),
+            dirty: self.inner.dirty.lock().unwrap().len(),
+        }
+    }
+}
+
+impl<S: Storage> TieredInner<S> {
+    fn lock(&self, key: &str) -> MutexGuard<'_, ()> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let i = hasher.finish() as usize % self.locks.len();
+        self.locks[i].lock().unwrap()
+    }
+
+    /// 尚未写回的修改: Some(None) 表示已删除
+    fn pending(&self, key: &str) -> Option<Option<Value>> {
+        self.dirty.lock().unwrap().get(key).cloned()
+    }
+
+    /// 读取当前值，调用方需持有 key 锁
+    fn load(&self, key: &str) -> Result<Option<Value>, HikvError> {
+        if let Some(v) = self.pending(key) {
+            return Ok(v);
+        }
+        if let Some(v) = self.cache.get(key)? {
+            return Ok(Some(v));
+        }
+        self.disk.get(key)
+    }
+
+    /// 把一个 key 尚未写回的修改写到磁盘，调用方需持有 key 锁
+    fn flush_key(&self, key: &str) -> Result<(), HikvError> {
+        let value = match self.pending(key) {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+        match &value {
+            Some(v) => self.disk.set(key, v.clone())?,
+            None => self.disk.del(key)?,
+        };
+        let mut dirty = self.dirty.lock().unwrap();
+        if dirty.get(key) == Some(&value) {
+            dirty.remove(key);
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<usize, HikvError> {
+        let keys: Vec<String> = self.dirty.lock().unwrap().keys().cloned().collect();
+        for key in &keys {
+            let _guard = self.lock(key);
+            self.flush_key(key)?;
+        }
+        Ok(keys.len())
+    }
+}
+
+/// 关闭时把剩余的修改写回磁盘
+impl<S: Storage> Drop for TieredInner<S> {
+    fn drop(&mut self) {
+        match self.flush() {
+            Ok(0) => {}
+            Ok(n) => info!("Flushed {} dirty keys to disk on shutdown", n),
+            Err(e) => error!("Failed to flush dirty keys on shutdown: {}", e),
+        }
+    }
+}
+
+impl<S: Storage> Storage for TieredStorage<S> {
+    fn set(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, HikvError> {
+        let (key, value) = (key.into(), value.into());
+        let inner = &self.inner;
+        let _guard = inner.lock(&key);
+
+        match inner.mode {
+            WriteMode::WriteThrough => {
+                let old = inner.disk.set(key.as_str(), value.clone())?;
+                inner.cache.set(key, value)?;
+                Ok(old)
+            }
+            WriteMode::WriteBack { .. } => {
+                let old = inner.load(&key)?;
+                inner
+                    .dirty
+                    .lock()
+                    .unwrap()
+                    .insert(key.clone(), Some(value.clone()));
+                inner.cache.set(key, value)?;
+                Ok(old)
+            }
+        }
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Value>, HikvError> {
+        let inner = &self.inner;
+        if let Some(v) = inner.cache.get(key)? {
+            inner.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(Some(v));
+        }
+
+        let _guard = inner.lock(key);
+        if let Some(v) = inner.pending(key) {
+            // 被缓存淘汰但还未写回的修改
+            inner.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(v);
+        }
+        inner.misses.fetch_add(1, Ordering::Relaxed);
+        let value = inner.disk.get(key)?;
+        if let Some(v) = &value {
+            inner.cache.set(key, v.clone())?;
+        }
+        Ok(value)
+    }
+
+    /// 元数据以磁盘存储为准，先写回该 key 的修改
+    fn get_with_meta(&self, key: &str) -> Result<Option<(Value, KeyMeta)>, HikvError> {
+        let inner = &self.inner;
+        let _guard = inner.lock(key);
+        inner.flush_key(key)?;
+        inner.disk.get_with_meta(key)
+    }
+
+    fn set_if_version(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        expected: u64,
+    ) -> Result<Option<Value>, HikvError> {
+        let (key, value) = (key.into(), value.into());
+        let inner = &self.inner;
+        let _guard = inner.lock(&key);
+        inner.flush_key(&key)?;
+        let old = inner
+            .disk
+            .set_if_version(key.as_str(), value.clone(), expected)?;
+        inner.cache.set(key, value)?;
+        Ok(old)
+    }
+
+    fn del(&self, key: &str) -> Result<Option<Value>, HikvError> {
+        let inner = &self.inner;
+        let _guard = inner.lock(key);
+
+        let old = match inner.mode {
+            WriteMode::WriteThrough => inner.disk.del(key)?,
+            WriteMode::WriteBack { .. } => {
+                let old = inner.load(key)?;
+                inner.dirty.lock().unwrap().insert(key.into(), None);
+                old
+            }
+        };
+        inner.cache.del(key)?;
+        Ok(old)
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, HikvError> {
+        let inner = &self.inner;
+        if inner.cache.contains(key)? {
+            return Ok(true);
+        }
+        match inner.pending(key) {
+            Some(v) => Ok(v.is_some()),
+            None => inner.disk.contains(key),
+        }
+    }
+
+    /// 先写回所有修改，再遍历磁盘存储
+    fn get_iter(&self) -> Result<StorageIter<'_>, HikvError> {
+        self.inner.flush()?;
+        self.inner.disk.get_iter()
+    }
+
+    fn flush(&self) -> Result<(), HikvError> {
+        self.inner.flush()?;
+        self.inner.disk.flush()
+    }
+
+    fn compact(&self) -> Result<(), HikvError> {
+        self.inner.flush()?;
+        self.inner.disk.compact()
+    }
+
+    /// 磁盘存储的统计信息加上缓存命中计数
+    fn stats(&self) -> Result<StorageStats, HikvError> {
+        self.inner.flush()?;
+        let inner = &self.inner;
+        Ok(inner
+            .disk
+            .stats()?
+            .with("cache.hits", inner.hits.load(Ordering::Relaxed) as i64)
+            .with("cache.misses", inner.misses.load(Ordering::Relaxed) as i64))
+    }
+
+    /// 先写回所有修改，备份由磁盘存储完成
+    fn backup(&self, path: &Path, incremental: bool) -> Result<BackupManifest, HikvError> {
+        self.inner.flush()?;
+        self.inner.disk.backup(path, incremental)
+    }
+
+    /// 先写回所有修改，快照由磁盘存储提供
+    fn create_snapshot(&self, timeout: Duration) -> Result<u64, HikvError> {
+        self.inner.flush()?;
+        self.inner.disk.create_snapshot(timeout)
+    }
+
+    fn get_at(&self, snapshot: u64, key: &str) -> Result<Option<Value>, HikvError> {
+        self.inner.disk.get_at(snapshot, key)
+    }
+
+    fn release_snapshot(&self, snapshot: u64) -> Result<bool, HikvError> {
+        self.inner.disk.release_snapshot(snapshot)
+    }
+
+    fn evicted(&self) -> u64 {
+        self.inner.cache.evicted()
+    }
+}
+
+/// 后台定时写回，存储被 drop 后自动退出
+fn spawn_flusher<S>(inner: Weak<TieredInner<S>>, interval: Duration)
+where
+    S: Storage + Send + Sync + 'static,
+{
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => break,
+        };
+        match inner.flush() {
+            Ok(0) => {}
+            Ok(n) => info!("Flushed {} dirty keys to disk", n),
+            Err(e) => error!("Failed to flush dirty keys: {}", e),
+        }
+    });
+}
+
+#[cfg(all(test, feature = "sled"))]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::SledDb;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn should_work_write_through() {
+        let dir = tempdir().unwrap();
+        let store = TieredStorage::new(SledDb::new(&dir), 1024, WriteMode::WriteThrough);
+
+        store.set("hello", "world").unwrap();
+        assert_eq!(store.inner.disk.get("hello").unwrap(), Some("world".into()));
+
+        assert_eq!(store.get("hello").unwrap(), Some("world".into()));
+        assert_eq!(store.get("lang").unwrap(), None);
+        let stats = store.cache_stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+
+        assert_eq!(store.del("hello").unwrap(), Some("world".into()));
+        assert_eq!(store.get("hello").unwrap(), None);
+        assert_eq!(store.inner.disk.get("hello").unwrap(), None);
+    }
+
+    #[test]
+    fn should_work_write_back() {
+        let dir = tempdir().unwrap();
+        let store = TieredStorage::new(
+            SledDb::new(&dir),
+            1024,
+            WriteMode::WriteBack { interval: HOUR },
+        );
+
+        store.set("hello", "world").unwrap();
+        store.set("lang", "rust").unwrap();
+        assert_eq!(store.inner.disk.get("hello").unwrap(), None);
+        assert_eq!(store.get("hello").unwrap(), Some("world".into()));
+
+        store.del("lang").unwrap();
+        assert!(!store.contains("lang").unwrap());
+        assert_eq!(store.cache_stats().dirty, 2);
+
+        assert_eq!(store.flush_dirty().unwrap(), 2);
+        assert_eq!(store.cache_stats().dirty, 0);
+        assert_eq!(store.inner.disk.get("hello").unwrap(), Some("world".into()));
+        assert_eq!(store.inner.disk.get("lang").unwrap(), None);
+    }
+
+    #[test]
+    fn write_back_should_keep_evicted_dirty_keys() {
+        let dir = tempdir().unwrap();
+        let store = TieredStorage::new(
+            SledDb::new(&dir),
+            256,
+            WriteMode::WriteBack { interval: HOUR },
+        );
+
+        for i in 0..20i64 {
+            store.set(format!("k{}", i), Value::from(i)).unwrap();
+        }
+        assert!(store.evicted() > 0);
+        for i in 0..20i64 {
+            assert_eq!(store.get(&format!("k{}", i)).unwrap(), Some(i.into()));
+        }
+    }
+
+    #[test]
+    fn write_back_should_flush_on_drop() {
+        let dir = tempdir().unwrap();
+        let store = TieredStorage::new(
+            SledDb::new(dir.path()),
+            1024,
+            WriteMode::WriteBack { interval: HOUR },
+        );
+        store.set("hello", "world").unwrap();
+        drop(store);
+
+        let disk = SledDb::new(dir.path());
+        assert_eq!(disk.get("hello").unwrap(), Some("world".into()));
+    }
+
+    #[test]
+    fn write_back_meta_should_follow_disk() {
+        let dir = tempdir().unwrap();
+        let store = TieredStorage::new(
+            SledDb::new(&dir),
+            1024,
+            WriteMode::WriteBack { interval: HOUR },
+        );
+
+        store.set("hello", "world").unwrap();
+        store.set("hello", "world!").unwrap();
+        // 读 meta 前先写回，版本号以磁盘为准
+        let meta = store.meta("hello").unwrap().unwrap();
+        assert_eq!(store.cache_stats().dirty, 0);
+        assert_eq!(store.inner.disk.meta("hello").unwrap(), Some(meta));
+
+        assert!(store
+            .set_if_version("hello", "v2", meta.version + 1)
+            .is_err());
+        store.set_if_version("hello", "v2", meta.version).unwrap();
+        assert_eq!(store.get("hello").unwrap(), Some("v2".into()));
+        let disk = store.inner.disk.meta("hello").unwrap().unwrap();
+        assert!(disk.version > meta.version);
+    }
+}
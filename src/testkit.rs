@@ -0,0 +1,221 @@
+//! Storage 一致性测试套件，自定义后端可以用 [`storage_conformance!`](crate::storage_conformance) 一次运行全部用例
+//!
+//! ```ignore
+//! hikv::storage_conformance!(my_store, |dir| MyStore::open(dir).unwrap());
+//! ```
+
+use std::{collections::HashSet, thread};
+
+use bytes::Bytes;
+
+use crate::{HikvError, Kvpair, Storage, Value};
+
+pub use tempfile::{tempdir, TempDir};
+
+/// 并发测试的线程数
+const THREADS: usize = 8;
+/// 每个线程写入的 key 数量
+const KEYS_PER_THREAD: usize = 100;
+
+/// 为 Storage 实现生成全部一致性测试
+///
+/// 第二个参数是工厂闭包，接收一个每个用例独立的临时目录，返回待测存储
+#[macro_export]
+macro_rules! storage_conformance {
+    ($name:ident, $factory:expr) => {
+        #[cfg(test)]
+        mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            fn run<F>(f: F)
+            where
+                F: FnOnce(&std::path::Path),
+            {
+                let dir = $crate::testkit::tempdir().unwrap();
+                f(dir.path());
+            }
+
+            $crate::storage_conformance!(@cases $factory;
+                point_ops,
+                overwrite,
+                concurrent_writers,
+                large_and_empty_values,
+                unicode_keys,
+                value_variants,
+                ordered_iteration,
+                error_behaviour,
+            );
+        }
+    };
+    (@cases $factory:expr; $($case:ident),* $(,)?) => {
+        $(
+            #[test]
+            fn $case() {
+                run(|dir| $crate::testkit::$case(&($factory)(dir)));
+            }
+        )*
+    };
+}
+
+/// set/get/del/contains 的基本语义
+pub fn point_ops(store: &impl Storage) {
+    assert_eq!(store.get("point").unwrap(), None);
+    assert!(!store.contains("point").unwrap());
+    assert_eq!(store.del("point").unwrap(), None);
+
+    assert_eq!(store.set("point", "v1").unwrap(), None);
+    assert_eq!(store.get("point").unwrap(), Some("v1".into()));
+    assert!(store.contains("point").unwrap());
+
+    assert_eq!(store.del("point").unwrap(), Some("v1".into()));
+    assert_eq!(store.get("point").unwrap(), None);
+    assert!(!store.contains("point").unwrap());
+    assert_eq!(store.del("point").unwrap(), None);
+}
+
+/// 覆盖写返回旧值，版本号递增，创建时间不变
+pub fn overwrite(store: &impl Storage) {
+    assert_eq!(store.set("over", "v1").unwrap(), None);
+    let m1 = store.meta("over").unwrap().unwrap();
+    assert!(m1.version > 0);
+
+    assert_eq!(store.set("over", "v2").unwrap(), Some("v1".into()));
+    assert_eq!(
+        store.set("over", Value::from(3)).unwrap(),
+        Some("v2".into())
+    );
+    assert_eq!(store.get("over").unwrap(), Some(3.into()));
+    let m3 = store.meta("over").unwrap().unwrap();
+    assert!(m3.version > m1.version);
+    assert_eq!(m3.created_at, m1.created_at);
+    assert!(m3.updated_at >= m1.updated_at);
+
+    store
+        .set_all(vec![
+            Kvpair::new("over", "v4".into()),
+            Kvpair::new("other", true.into()),
+        ])
+        .unwrap();
+    assert_eq!(store.get("over").unwrap(), Some("v4".into()));
+    assert_eq!(store.get("other").unwrap(), Some(true.into()));
+    assert!(store.meta("over").unwrap().unwrap().version > m3.version);
+}
+
+/// 多线程同时写不同的 key，并用 set_if_version 对同一个 key 做 compare-and-swap 计数
+pub fn concurrent_writers(store: &(impl Storage + Sync)) {
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            s.spawn(move || {
+                for i in 0..KEYS_PER_THREAD {
+                    store
+                        .set(format!("t{}:{}", t, i), Value::from(i as i64))
+                        .unwrap();
+                    loop {
+                        let version = store.meta("counter").unwrap().map_or(0, |m| m.version);
+                        let n = store
+                            .get("counter")
+                            .unwrap()
+                            .map_or(0, |v| i64::try_from(v).unwrap());
+                        match store.set_if_version("counter", n + 1, version) {
+                            Ok(_) => break,
+                            Err(HikvError::VersionMismatch(..)) => continue,
+                            Err(e) => panic!("unexpected error: {:?}", e),
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let total = (THREADS * KEYS_PER_THREAD) as i64;
+    assert_eq!(store.get("counter").unwrap(), Some(total.into()));
+    for t in 0..THREADS {
+        for i in 0..KEYS_PER_THREAD {
+            let key = format!("t{}:{}", t, i);
+            assert_eq!(store.get(&key).unwrap(), Some((i as i64).into()));
+        }
+    }
+}
+
+/// 1MB 的 value 以及空字符串、空二进制
+pub fn large_and_empty_values(store: &impl Storage) {
+    let large: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let large = Value::from(Bytes::from(large));
+    store.set("large", large.clone()).unwrap();
+    assert_eq!(store.get("large").unwrap(), Some(large));
+
+    store.set("empty_str", "").unwrap();
+    assert_eq!(store.get("empty_str").unwrap(), Some("".into()));
+    let empty = Value::from(Bytes::new());
+    store.set("empty_bin", empty.clone()).unwrap();
+    assert_eq!(store.get("empty_bin").unwrap(), Some(empty));
+
+    store.set("", "empty key").unwrap();
+    assert_eq!(store.get("").unwrap(), Some("empty key".into()));
+}
+
+/// 非 ASCII 的 key 可以读写和遍历
+pub fn unicode_keys(store: &impl Storage) {
+    let keys = ["你好", "ключ", "🔑", "café", "a\u{0}b"];
+    for (i, key) in keys.iter().enumerate() {
+        store.set(*key, i as i64).unwrap();
+    }
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(store.get(key).unwrap(), Some((i as i64).into()));
+    }
+    let found: HashSet<String> = store.get_iter().unwrap().map(|r| r.unwrap().key).collect();
+    assert!(keys.iter().all(|k| found.contains(*k)));
+}
+
+/// 所有 Value 类型原样读回
+pub fn value_variants(store: &impl Storage) {
+    let values = [
+        Value::default(),
+        Value::from("string"),
+        Value::from(Bytes::from_static(b"\x00\xffbinary")),
+        Value::from(i64::MIN),
+        Value::from(i64::MAX),
+        Value::from(0),
+        Value::from(-1.5),
+        Value::from(f64::MAX),
+        Value::from(true),
+        Value::from(false),
+    ];
+    for (i, value) in values.iter().enumerate() {
+        store.set(format!("variant{}", i), value.clone()).unwrap();
+    }
+    for (i, value) in values.iter().enumerate() {
+        let key = format!("variant{}", i);
+        assert_eq!(store.get(&key).unwrap().as_ref(), Some(value), "{}", key);
+    }
+}
+
+/// get_iter 按 key 升序返回全部数据
+pub fn ordered_iteration(store: &impl Storage) {
+    for key in ["c", "a", "b", "aa"] {
+        store.set(key, key).unwrap();
+    }
+    store.del("b").unwrap();
+    store.flush().unwrap();
+
+    let keys: Vec<String> = store.get_iter().unwrap().map(|r| r.unwrap().key).collect();
+    assert_eq!(keys, ["a", "aa", "c"]);
+}
+
+/// 前置条件不满足时返回错误且不修改数据
+pub fn error_behaviour(store: &impl Storage) {
+    store.set("guarded", "v1").unwrap();
+    let version = store.meta("guarded").unwrap().unwrap().version;
+    let err = store.set_if_version("guarded", "v2", 7).unwrap_err();
+    assert!(matches!(err, HikvError::VersionMismatch(_, 7, v) if v == version));
+    assert_eq!(store.get("guarded").unwrap(), Some("v1".into()));
+
+    assert!(store.set_if_version("guarded", "v2", 0).is_err());
+    assert!(store.set_if_version("missing", "v1", 1).is_err());
+    assert_eq!(store.get("missing").unwrap(), None);
+
+    let err = store.get_at(u64::MAX, "guarded").unwrap_err();
+    assert!(matches!(err, HikvError::SnapshotNotFound(_)));
+    assert!(!store.release_snapshot(u64::MAX).unwrap());
+}